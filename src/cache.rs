@@ -0,0 +1,207 @@
+use crate::output;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Name of the cache manifest kept in each output directory.
+pub const MANIFEST_NAME: &str = ".pdf-converter-cache.json";
+
+/// Version of the manifest layout. Manifests with another version are ignored.
+const MANIFEST_VERSION: u32 = 1;
+
+/// Everything besides the input that affects the contents of an output file.
+///
+/// Settings missing from an older manifest read as empty, so its outputs count as stale
+/// rather than making the whole manifest unreadable.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheSettings {
+    /// Version of the converter, since rendering changes between releases.
+    pub converter: String,
+    pub format: String,
+    pub sizing: String,
+    pub background: String,
+    pub color: String,
+    pub dither: String,
+    pub region: String,
+    pub rotations: String,
+    pub tiles: String,
+    pub nup: String,
+    pub render_annotations: bool,
+}
+
+/// How an output file was produced.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputRecord {
+    /// The input path as given when the file was written. With the hash, it tells which
+    /// input owns the file.
+    pub input: String,
+    /// SHA-256 of the input PDF, in lowercase hex.
+    pub input_sha256: String,
+    pub settings: CacheSettings,
+    /// 0-based indices of the source pages stored in the file.
+    pub pages: Vec<usize>,
+    /// Size of the file when it was written, to notice files changed by other tools.
+    pub size: u64,
+}
+
+/// The cache manifest of one output directory, keyed by output file name.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    version: u32,
+    outputs: BTreeMap<String, OutputRecord>,
+}
+
+impl Manifest {
+    /// Read the manifest in `dir`. A missing, unreadable or outdated manifest is treated as
+    /// empty, which simply renders everything again.
+    pub fn load(dir: &Path) -> Self {
+        fs::read(dir.join(MANIFEST_NAME))
+            .ok()
+            .and_then(|data| serde_json::from_slice::<Manifest>(&data).ok())
+            .filter(|manifest| manifest.version == MANIFEST_VERSION)
+            .unwrap_or_default()
+    }
+
+    /// Write the manifest to `dir`, dropping records of files that no longer exist.
+    pub fn save(&mut self, dir: &Path) -> Result<(), String> {
+        self.version = MANIFEST_VERSION;
+        self.outputs.retain(|name, _| dir.join(name).is_file());
+        let data = serde_json::to_vec_pretty(self)
+            .map_err(|e| format!("Failed to serialize cache manifest: {e}"))?;
+        output::write_atomic(&dir.join(MANIFEST_NAME), &data, true)
+            .map_err(|e| format!("Failed to write cache manifest: {e}"))
+    }
+
+    /// Whether the file `name` was written by an earlier run from the input of `expected`:
+    /// one given as the same path, or with the same contents. A file written from another
+    /// input is not ours to replace.
+    pub fn is_owned_by(&self, name: &str, expected: &OutputRecord) -> bool {
+        self.outputs.get(name).is_some_and(|record| {
+            record.input == expected.input || record.input_sha256 == expected.input_sha256
+        })
+    }
+
+    /// Whether the file `name` in `dir` still holds exactly what `expected` would produce.
+    pub fn is_up_to_date(&self, dir: &Path, name: &str, expected: &OutputRecord) -> bool {
+        let Some(record) = self.outputs.get(name) else {
+            return false;
+        };
+        record.input_sha256 == expected.input_sha256
+            && record.settings == expected.settings
+            && record.pages == expected.pages
+            && fs::metadata(dir.join(name)).is_ok_and(|meta| meta.len() == record.size)
+    }
+
+    /// Remember how the file `name` was produced.
+    pub fn record(&mut self, name: String, record: OutputRecord) {
+        self.outputs.insert(name, record);
+    }
+}
+
+/// SHA-256 of `data` in lowercase hex.
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Display form of an input path for [`OutputRecord::input`]. In-memory input is `-`.
+pub fn input_label(input: &Path) -> String {
+    if input.as_os_str().is_empty() {
+        "-".to_string()
+    } else {
+        input.display().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_dir;
+
+    fn record(pages: Vec<usize>, size: u64) -> OutputRecord {
+        OutputRecord {
+            input: "doc.pdf".to_string(),
+            input_sha256: sha256_hex(b"%PDF"),
+            settings: CacheSettings {
+                format: "Png".to_string(),
+                ..CacheSettings::default()
+            },
+            pages,
+            size,
+        }
+    }
+
+    #[test]
+    fn hashes_inputs() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(input_label(Path::new("")), "-");
+        assert_eq!(input_label(Path::new("a/doc.pdf")), "a/doc.pdf");
+    }
+
+    #[test]
+    fn outputs_are_up_to_date_until_anything_changes() {
+        let dir = test_dir("cache");
+        fs::write(dir.join("doc-1.png"), b"1234").unwrap();
+        let mut manifest = Manifest::default();
+        manifest.record("doc-1.png".to_string(), record(vec![0], 4));
+        assert!(manifest.is_up_to_date(&dir, "doc-1.png", &record(vec![0], 4)));
+        assert!(!manifest.is_up_to_date(&dir, "doc-1.png", &record(vec![1], 4)));
+        assert!(!manifest.is_up_to_date(&dir, "doc-2.png", &record(vec![1], 4)));
+
+        let mut changed = record(vec![0], 4);
+        changed.settings.sizing = "Dpi(300.0)".to_string();
+        assert!(!manifest.is_up_to_date(&dir, "doc-1.png", &changed));
+        // Edited by another tool.
+        fs::write(dir.join("doc-1.png"), b"12345").unwrap();
+        assert!(!manifest.is_up_to_date(&dir, "doc-1.png", &record(vec![0], 4)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn saves_records_of_existing_files_only() {
+        let dir = test_dir("manifest");
+        fs::write(dir.join("doc-1.png"), b"1234").unwrap();
+        let mut manifest = Manifest::default();
+        manifest.record("doc-1.png".to_string(), record(vec![0], 4));
+        manifest.record("doc-2.png".to_string(), record(vec![1], 4));
+        manifest.save(&dir).unwrap();
+
+        let loaded = Manifest::load(&dir);
+        assert!(loaded.is_owned_by("doc-1.png", &record(vec![0], 4)));
+        assert!(!loaded.is_owned_by("doc-2.png", &record(vec![1], 4)));
+
+        fs::write(
+            dir.join(MANIFEST_NAME),
+            b"{\"version\": 0, \"outputs\": {}}",
+        )
+        .unwrap();
+        assert!(!Manifest::load(&dir).is_owned_by("doc-1.png", &record(vec![0], 4)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn files_are_owned_by_the_input_that_wrote_them() {
+        let mut manifest = Manifest::default();
+        manifest.record("doc-1.png".to_string(), record(vec![0], 4));
+
+        let mut edited = record(vec![0], 4);
+        edited.input_sha256 = sha256_hex(b"%PDF-1.7");
+        assert!(manifest.is_owned_by("doc-1.png", &edited));
+        let mut moved = record(vec![0], 4);
+        moved.input = "a/doc.pdf".to_string();
+        assert!(manifest.is_owned_by("doc-1.png", &moved));
+        let other = OutputRecord {
+            input: "b/doc.pdf".to_string(),
+            ..edited
+        };
+        assert!(!manifest.is_owned_by("doc-1.png", &other));
+    }
+}
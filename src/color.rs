@@ -0,0 +1,372 @@
+use crate::render::Background;
+use clap::ValueEnum;
+use hayro::vello_cpu::Pixmap;
+
+/// Colour model of raster outputs.
+///
+/// The grey and monochrome modes composite transparent areas onto white, since they have
+/// no alpha channel.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[value(rename_all = "lower")]
+pub enum ColorMode {
+    /// Full colour, with transparency where the format supports it.
+    #[default]
+    Rgb,
+    /// 8-bit greyscale.
+    Gray,
+    /// 16-bit greyscale (PNG and TIFF; other formats use 8 bits).
+    Gray16,
+    /// 1-bit black and white, reduced with the chosen [`Dither`] method.
+    Mono,
+}
+
+/// How greyscale is reduced to black and white in [`ColorMode::Mono`].
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[value(rename_all = "kebab-case")]
+pub enum Dither {
+    /// Plain threshold at 50% grey. Sharpest for text, and the usual choice for OCR.
+    #[default]
+    Threshold,
+    /// Floyd–Steinberg error diffusion. Best for photos and shading.
+    FloydSteinberg,
+    /// Ordered dithering with an 8×8 Bayer matrix. Gives a regular pattern that suits
+    /// e-ink displays and compresses well.
+    Ordered,
+}
+
+/// 8×8 Bayer matrix used by [`Dither::Ordered`].
+const BAYER_8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Lightness of a premultiplied RGBA pixel composited onto white, from 0.0 to 255.0.
+fn luma(px: &[u8]) -> f32 {
+    let paper = 255.0 - px[3] as f32;
+    0.299 * (px[0] as f32 + paper) + 0.587 * (px[1] as f32 + paper) + 0.114 * (px[2] as f32 + paper)
+}
+
+/// Greyscale values of a pixmap, one byte per pixel.
+pub fn gray8(pixmap: &Pixmap) -> Vec<u8> {
+    pixmap
+        .data_as_u8_slice()
+        .chunks_exact(4)
+        .map(|px| luma(px).round().min(255.0) as u8)
+        .collect()
+}
+
+/// Greyscale values of a pixmap, 16 bits per pixel.
+pub fn gray16(pixmap: &Pixmap) -> Vec<u16> {
+    pixmap
+        .data_as_u8_slice()
+        .chunks_exact(4)
+        .map(|px| (luma(px) * 257.0).round().min(65535.0) as u16)
+        .collect()
+}
+
+/// Lightness of every pixel of a pixmap, from 0.0 to 255.0, row by row.
+pub fn levels(pixmap: &Pixmap) -> Vec<f32> {
+    pixmap
+        .data_as_u8_slice()
+        .chunks_exact(4)
+        .map(luma)
+        .collect()
+}
+
+/// Black and white pixels of a pixmap, `true` meaning black, row by row.
+pub fn mono(pixmap: &Pixmap, dither: Dither) -> Vec<bool> {
+    BandDither::new(dither, pixmap.width() as usize).next_band(levels(pixmap))
+}
+
+/// Reduces an image to black and white a band of rows at a time.
+///
+/// The position in the image and the error Floyd–Steinberg diffusion pushes into the next
+/// row are carried from one band to the next, so the result is the same as dithering the
+/// whole image at once.
+pub struct BandDither {
+    dither: Dither,
+    width: usize,
+    /// Index of the first row of the next band.
+    row: usize,
+    /// Error diffused into the first row of the next band.
+    carry: Vec<f32>,
+}
+
+impl BandDither {
+    pub fn new(dither: Dither, width: usize) -> Self {
+        Self {
+            dither,
+            width: width.max(1),
+            row: 0,
+            carry: vec![0.0; width.max(1)],
+        }
+    }
+
+    /// Black and white pixels of the next band, given the lightness of its pixels row by
+    /// row.
+    pub fn next_band(&mut self, mut levels: Vec<f32>) -> Vec<bool> {
+        let width = self.width;
+        let first_row = self.row;
+        self.row += levels.len() / width;
+
+        match self.dither {
+            Dither::Threshold => levels.iter().map(|&l| l < 128.0).collect(),
+            Dither::Ordered => levels
+                .iter()
+                .enumerate()
+                .map(|(i, &l)| {
+                    let (x, y) = (i % width, first_row + i / width);
+                    let threshold = (BAYER_8[y % 8][x % 8] as f32 + 0.5) * (255.0 / 64.0);
+                    l < threshold
+                })
+                .collect(),
+            Dither::FloydSteinberg => {
+                let mut carry = vec![0.0; width];
+                std::mem::swap(&mut carry, &mut self.carry);
+                for (level, error) in levels.iter_mut().zip(carry) {
+                    *level += error;
+                }
+
+                let len = levels.len();
+                let mut out = Vec::with_capacity(len);
+                for i in 0..len {
+                    let x = i % width;
+                    let old = levels[i];
+                    let black = old < 128.0;
+                    let error = old - if black { 0.0 } else { 255.0 };
+                    out.push(black);
+
+                    let mut spread = |j: usize, weight: f32| {
+                        if let Some(level) = levels.get_mut(j) {
+                            *level += error * weight;
+                        } else if let Some(level) = self.carry.get_mut(j - len) {
+                            *level += error * weight;
+                        }
+                    };
+                    if x + 1 < width {
+                        spread(i + 1, 7.0 / 16.0);
+                        spread(i + width + 1, 1.0 / 16.0);
+                    }
+                    if x > 0 {
+                        spread(i + width - 1, 3.0 / 16.0);
+                    }
+                    spread(i + width, 5.0 / 16.0);
+                }
+                out
+            }
+        }
+    }
+}
+
+/// Pack black and white pixels into rows of bits, padded to whole bytes. A set bit is black
+/// when `black_is_one`, white otherwise.
+pub fn pack_bits(pixels: &[bool], width: usize, black_is_one: bool) -> Vec<u8> {
+    let row_bytes = width.div_ceil(8);
+    let mut out = Vec::with_capacity(row_bytes * pixels.len() / width.max(1));
+    for row in pixels.chunks_exact(width.max(1)) {
+        let start = out.len();
+        out.resize(start + row_bytes, 0);
+        for (x, &black) in row.iter().enumerate() {
+            if black == black_is_one {
+                out[start + x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+    out
+}
+
+/// Colour names accepted by [`parse_color`]: the basic CSS colours plus `orange`.
+const NAMED_COLORS: [(&str, [u8; 3]); 19] = [
+    ("white", [255, 255, 255]),
+    ("black", [0, 0, 0]),
+    ("silver", [192, 192, 192]),
+    ("gray", [128, 128, 128]),
+    ("grey", [128, 128, 128]),
+    ("red", [255, 0, 0]),
+    ("maroon", [128, 0, 0]),
+    ("orange", [255, 165, 0]),
+    ("yellow", [255, 255, 0]),
+    ("olive", [128, 128, 0]),
+    ("lime", [0, 255, 0]),
+    ("green", [0, 128, 0]),
+    ("aqua", [0, 255, 255]),
+    ("cyan", [0, 255, 255]),
+    ("teal", [0, 128, 128]),
+    ("blue", [0, 0, 255]),
+    ("navy", [0, 0, 128]),
+    ("fuchsia", [255, 0, 255]),
+    ("purple", [128, 0, 128]),
+];
+
+/// Parse a colour given as `#rrggbb` or `#rgb` (the `#` is optional) or by name, such as
+/// `white`, `black` or `navy`.
+pub fn parse_color(s: &str) -> Result<[u8; 3], String> {
+    let s = s.trim();
+    if let Some((_, rgb)) = NAMED_COLORS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(s))
+    {
+        return Ok(*rgb);
+    }
+
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let invalid =
+        || format!("invalid colour '{s}': expected #rrggbb, #rgb or a colour name such as white");
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).map_err(|_| invalid());
+    match hex.len() {
+        6 => {
+            let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+            Ok([byte(0)?, byte(2)?, byte(4)?])
+        }
+        3 => Ok([digit(0)? * 17, digit(1)? * 17, digit(2)? * 17]),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse a page background: `transparent` or a colour accepted by [`parse_color`].
+pub fn parse_background(s: &str) -> Result<Background, String> {
+    if s.trim().eq_ignore_ascii_case("transparent") {
+        Ok(Background::Transparent)
+    } else {
+        parse_color(s).map(Background::Color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::RasterEncoding;
+    use crate::testing::pixmap;
+    use png::{BitDepth, ColorType};
+    use std::io::Cursor;
+
+    /// A pixmap of opaque grey `levels`, `width` per row.
+    fn gray_pixmap(width: u16, levels: &[u8]) -> Pixmap {
+        let pixels: Vec<[u8; 4]> = levels.iter().map(|&l| [l, l, l, 255]).collect();
+        pixmap(width, (levels.len() / width as usize) as u16, &pixels)
+    }
+
+    /// The colour type, bit depth and image data of a PNG file.
+    fn decode_png(data: Vec<u8>) -> (ColorType, BitDepth, Vec<u8>) {
+        let mut reader = png::Decoder::new(Cursor::new(data)).read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+        let frame = reader.next_frame(&mut buf).unwrap();
+        buf.truncate(frame.buffer_size());
+        (frame.color_type, frame.bit_depth, buf)
+    }
+
+    #[test]
+    fn composites_grey_levels_onto_white() {
+        // Opaque grey, black, fully transparent, and half-transparent black.
+        let pixmap = pixmap(
+            4,
+            1,
+            &[[100, 100, 100, 255], [0, 0, 0, 255], [0; 4], [0, 0, 0, 128]],
+        );
+        assert_eq!(gray8(&pixmap), [100, 0, 255, 127]);
+        assert_eq!(gray16(&pixmap), [25700, 0, 65535, 32639]);
+    }
+
+    #[test]
+    fn thresholds_at_half_grey() {
+        let pixels = mono(&gray_pixmap(4, &[0, 127, 128, 255]), Dither::Threshold);
+        assert_eq!(pixels, [true, true, false, false]);
+    }
+
+    #[test]
+    fn orders_half_grey_into_a_bayer_pattern() {
+        let pixels = mono(&gray_pixmap(8, &[128; 64]), Dither::Ordered);
+        assert_eq!(
+            pixels[..8],
+            [false, true, false, true, false, true, false, true]
+        );
+        assert_eq!(pixels.iter().filter(|&&black| black).count(), 32);
+    }
+
+    #[test]
+    fn diffuses_errors_floyd_steinberg() {
+        // 100 is black and pushes +100 on; 143.75 is white and pushes -111.25 on.
+        let pixels = mono(&gray_pixmap(4, &[100; 4]), Dither::FloydSteinberg);
+        assert_eq!(pixels, [true, false, true, true]);
+
+        // Dithering in bands of rows gives the same pixels as the whole image at once.
+        let levels: Vec<u8> = (0..48).map(|i| (i * 37 % 256) as u8).collect();
+        let whole = mono(&gray_pixmap(6, &levels), Dither::FloydSteinberg);
+        let mut bands = BandDither::new(Dither::FloydSteinberg, 6);
+        let banded: Vec<bool> = levels
+            .chunks(12)
+            .flat_map(|band| bands.next_band(band.iter().map(|&l| l as f32).collect()))
+            .collect();
+        assert_eq!(banded, whole);
+    }
+
+    #[test]
+    fn packs_rows_into_whole_bytes() {
+        // Padding bits are always clear.
+        let mut pixels = vec![false; 20];
+        for i in [0, 7, 8, 9, 10] {
+            pixels[i] = true;
+        }
+        assert_eq!(
+            pack_bits(&pixels, 10, true),
+            [0b1000_0001, 0b1100_0000, 0b1000_0000, 0]
+        );
+        assert_eq!(
+            pack_bits(&pixels, 10, false),
+            [0b0111_1110, 0, 0b0111_1111, 0b1100_0000]
+        );
+    }
+
+    #[test]
+    fn writes_grey_and_mono_pngs_at_their_bit_depth() {
+        let encode = |color| {
+            let pixmap = gray_pixmap(10, &[[0; 5], [255; 5]].concat());
+            decode_png(
+                RasterEncoding::Png
+                    .encode(pixmap, color, Dither::Threshold)
+                    .unwrap(),
+            )
+        };
+        assert_eq!(
+            encode(ColorMode::Mono),
+            (
+                ColorType::Grayscale,
+                BitDepth::One,
+                vec![0b0000_0111, 0b1100_0000]
+            )
+        );
+        let (color_type, depth, data) = encode(ColorMode::Gray);
+        assert_eq!((color_type, depth), (ColorType::Grayscale, BitDepth::Eight));
+        assert_eq!(data, [[0; 5], [255; 5]].concat());
+        let (color_type, depth, data) = encode(ColorMode::Gray16);
+        assert_eq!(
+            (color_type, depth),
+            (ColorType::Grayscale, BitDepth::Sixteen)
+        );
+        assert_eq!(data[..2], [0, 0]);
+        assert_eq!(data[18..], [255, 255]);
+        // Opaque pages leave out the alpha channel.
+        let (color_type, depth, _) = encode(ColorMode::Rgb);
+        assert_eq!((color_type, depth), (ColorType::Rgb, BitDepth::Eight));
+    }
+
+    #[test]
+    fn parses_colours_and_backgrounds() {
+        assert_eq!(parse_color("#ff8000"), Ok([255, 128, 0]));
+        assert_eq!(parse_color(" f80 "), Ok([255, 136, 0]));
+        assert_eq!(parse_color("Navy"), Ok([0, 0, 128]));
+        assert!(parse_color("#ff80").is_err());
+        assert!(parse_color("dusk").is_err());
+        assert_eq!(parse_background("transparent"), Ok(Background::Transparent));
+        assert_eq!(parse_background("white"), Ok(Background::WHITE));
+    }
+}
@@ -0,0 +1,1835 @@
+use crate::cache::{self, CacheSettings, Manifest, OutputRecord};
+use crate::color::{ColorMode, Dither};
+use crate::crop::{CropRect, PageBox, PageRotation, PageView, Region};
+use crate::error::{Error, ErrorKind};
+use crate::images::{self, EmbeddedImage};
+use crate::labels;
+use crate::montage::{self, SheetLayout};
+use crate::naming::{NameFields, NameTemplate};
+use crate::nup::Nup;
+use crate::output::{self, AtomicFile, ExistingFiles};
+use crate::password;
+use crate::pyramid::{Pyramid, PyramidLayout};
+use crate::raster::RasterEncoding;
+use crate::render::{Background, rasterize, rasterize_tile};
+use crate::sizing::Sizing;
+use crate::svg;
+use crate::text;
+use crate::tiff_writer::{MultiPageTiff, TiffCompression};
+use crate::tiles::{
+    self, DEFAULT_TILE_SIZE, MAX_PIXMAP_SIZE, PAGE_BYTES_PER_PIXEL, TileGrid, fill_rect,
+    paste_pixmap,
+};
+use crate::utils;
+use file_format::FileFormat;
+use hayro::hayro_syntax::{DecryptionError, LoadPdfError, Pdf};
+use hayro::vello_cpu::Pixmap;
+use hayro_interpret::InterpreterSettings;
+use hayro_svg::{SvgRenderSettings, convert};
+use rayon::prelude::*;
+use hayro_interpret::hayro_syntax::page::Page;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Cursor, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The output format of a conversion, with its format-specific settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    /// Lossy JPEG. Transparent areas are flattened onto `background`.
+    Jpeg { quality: u8, background: [u8; 3] },
+    /// WebP, either lossy with the given `quality` or lossless.
+    Webp { quality: u8, lossless: bool },
+    /// One multi-page TIFF file per document. `bilevel` stores every page as 1-bit black
+    /// and white, like [`ColorMode::Mono`].
+    Tiff {
+        compression: TiffCompression,
+        bilevel: bool,
+    },
+    Svg,
+    /// Deep Zoom image pyramid of every page: a `.dzi` descriptor and PNG tiles of
+    /// `tile_size` pixels, plus `overlap` pixels shared with each neighbour, in
+    /// `<name>_files/<level>/<col>_<row>.png`.
+    Dzi { tile_size: u32, overlap: u32 },
+    /// Map tile pyramid of every page: PNG tiles of `tile_size` pixels in
+    /// `<name>/<z>/<x>/<y>.png`, and a JSON descriptor with the image size and zoom levels.
+    /// Tiles at the right and bottom edges are filled up with the background.
+    Xyz { tile_size: u32 },
+    /// Contact sheets: PNG thumbnails of the pages in rows of up to `columns`, with `spacing`
+    /// pixels between them and, with `captions`, the page number under each. Sheets are
+    /// limited to `max_sheet` pixels; the pages go onto as many sheets as needed, and the
+    /// thumbnails are scaled down when not even one row fits.
+    Montage {
+        columns: u32,
+        spacing: u32,
+        captions: bool,
+        max_sheet: Option<(u32, u32)>,
+    },
+    /// The text of every page: plain text with a line per baseline, or with `json` every run
+    /// of text with its font, size and bounding box in points on the page.
+    Text { json: bool },
+    /// The raster images embedded in every page, each in a file of its own named after the
+    /// page and its object number. JPEG and JPEG 2000 images are copied unchanged, others
+    /// are decoded to PNG.
+    Images,
+}
+
+impl OutputFormat {
+    /// Human-readable name used in log messages.
+    pub fn label(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "PNG",
+            OutputFormat::Jpeg { .. } => "JPEG",
+            OutputFormat::Webp { .. } => "WebP",
+            OutputFormat::Tiff { .. } => "TIFF",
+            OutputFormat::Svg => "SVG",
+            OutputFormat::Dzi { .. } => "DZI",
+            OutputFormat::Xyz { .. } => "XYZ",
+            OutputFormat::Montage { .. } => "montage",
+            OutputFormat::Text { .. } => "text",
+            OutputFormat::Images => "image",
+        }
+    }
+
+    /// File extension used for outputs in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg { .. } => "jpg",
+            OutputFormat::Webp { .. } => "webp",
+            OutputFormat::Tiff { .. } => "tif",
+            OutputFormat::Svg => "svg",
+            OutputFormat::Dzi { .. } => "dzi",
+            OutputFormat::Xyz { .. } => "json",
+            OutputFormat::Montage { .. } => "png",
+            OutputFormat::Text { json: false } => "txt",
+            OutputFormat::Text { json: true } => "json",
+            // Only the base of image names, which take the extension of each image.
+            OutputFormat::Images => "png",
+        }
+    }
+
+    /// Whether the pages of a document go into shared output files rather than one each: a
+    /// single TIFF file, or the sheets of a montage.
+    pub fn is_container(&self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Tiff { .. } | OutputFormat::Montage { .. }
+        )
+    }
+
+    fn raster_encoding(&self) -> Option<RasterEncoding> {
+        match *self {
+            OutputFormat::Png => Some(RasterEncoding::Png),
+            OutputFormat::Jpeg {
+                quality,
+                background,
+            } => Some(RasterEncoding::Jpeg {
+                quality,
+                background,
+            }),
+            OutputFormat::Webp { quality, lossless } => {
+                Some(RasterEncoding::Webp { quality, lossless })
+            }
+            OutputFormat::Tiff { .. }
+            | OutputFormat::Svg
+            | OutputFormat::Dzi { .. }
+            | OutputFormat::Xyz { .. }
+            | OutputFormat::Montage { .. }
+            | OutputFormat::Text { .. }
+            | OutputFormat::Images => None,
+        }
+    }
+
+    /// The tile pyramid of a page of `width` x `height` pixels, for pyramid formats.
+    fn pyramid(&self, width: u32, height: u32) -> Option<Pyramid> {
+        let (layout, tile_size, overlap) = match *self {
+            OutputFormat::Dzi { tile_size, overlap } => {
+                (PyramidLayout::DeepZoom, tile_size, overlap)
+            }
+            OutputFormat::Xyz { tile_size } => (PyramidLayout::Xyz, tile_size, 0),
+            _ => return None,
+        };
+        Some(Pyramid::new(layout, width, height, tile_size, overlap))
+    }
+}
+
+/// Metadata about one source page of a rendered output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageInfo {
+    /// 0-based index of the page in the document.
+    pub index: usize,
+    /// Width of the page in the output, in pixels (SVG: user units). Tiles give their own
+    /// size.
+    pub width: u32,
+    /// Height of the page in the output, in pixels (SVG: user units). Tiles give their own
+    /// size.
+    pub height: u32,
+    /// Scale factor the page was rendered at.
+    pub scale: f32,
+}
+
+/// One output file of a conversion, held in memory.
+#[derive(Clone, Debug)]
+pub struct RenderedOutput {
+    /// File name the output is written to, relative to the output directory.
+    pub file_name: String,
+    /// The pages contained in this output, in order. Per-page formats have exactly one,
+    /// unless pages are put side by side with [`Converter::nup`].
+    pub pages: Vec<PageInfo>,
+    /// The encoded file contents.
+    pub bytes: Vec<u8>,
+}
+
+/// The files written by [`Converter::convert`].
+#[derive(Clone, Debug, Default)]
+pub struct Conversion {
+    /// Paths of the written files, in output order.
+    pub files: Vec<PathBuf>,
+    /// Number of pages rendered.
+    pub pages: usize,
+    /// Number of files left alone because they already existed ([`ExistingFiles::Skip`]).
+    pub skipped: usize,
+    /// Number of files left alone because the cache manifest shows they are up to date
+    /// ([`Converter::incremental`]).
+    pub up_to_date: usize,
+}
+
+/// Converts PDF documents into images, SVG or text.
+///
+/// Configure a converter once with the builder methods, then call [`Converter::convert`]
+/// to write files or [`Converter::render`] to get the outputs in memory. Pages are rendered
+/// in parallel on the current rayon thread pool.
+///
+/// ```no_run
+/// use pdf_converter::{Converter, OutputFormat, Sizing};
+///
+/// let converter = Converter::new(OutputFormat::Png)
+///     .sizing(Sizing::Dpi(150.0))
+///     .pages(["1-3"]);
+/// for output in converter.render("report.pdf".as_ref())? {
+///     println!("{}: {} bytes", output.file_name, output.bytes.len());
+/// }
+/// # Ok::<(), pdf_converter::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct Converter {
+    format: OutputFormat,
+    sizing: Sizing,
+    pages: Vec<String>,
+    ordered: bool,
+    prefix: Option<String>,
+    name_template: Option<NameTemplate>,
+    existing_files: ExistingFiles,
+    incremental: bool,
+    background: Option<Background>,
+    color: ColorMode,
+    dither: Dither,
+    region: Region,
+    rotations: Vec<PageRotation>,
+    tiles: bool,
+    tile_size: Option<u32>,
+    max_memory: Option<u64>,
+    nup: Option<Nup>,
+    password: Option<String>,
+    interpreter_settings: InterpreterSettings,
+}
+
+/// A loaded document with its resolved page selection.
+struct Document {
+    pdf: Pdf,
+    selection: Vec<usize>,
+    /// The area rendered of every selected page, by page index, with the extra rotation of
+    /// the page. Worked out once, as trimming renders the page.
+    views: Vec<Option<PageView>>,
+    /// Output file names: one per selected page, a single one for TIFF, one per sheet of a
+    /// montage, or one per group of pages put side by side.
+    names: Vec<String>,
+    /// Where the pages go on the sheets of a montage.
+    montage: Option<SheetLayout>,
+    /// How pages are put side by side, for formats that support it.
+    nup: Option<Nup>,
+    /// The pages of each output put together by `nup`, as positions in `selection` in
+    /// reading order. `None` marks a blank page.
+    spreads: Vec<Vec<Option<usize>>>,
+}
+
+impl Document {
+    /// The area rendered of the selected page `idx`.
+    fn view(&self, idx: usize) -> PageView {
+        self.views[idx].expect("selected pages have a view")
+    }
+}
+
+/// A page of an output that puts several pages side by side.
+struct SpreadPage {
+    idx: usize,
+    view: PageView,
+    scale: f32,
+    /// Size of the page in the output, in pixels (SVG: user units).
+    size: (u32, u32),
+    /// Top-left corner of the page in the output.
+    position: (u32, u32),
+}
+
+impl Converter {
+    /// Create a converter for `format` that renders every page at scale 1.
+    pub fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            sizing: Sizing::Scale(1.0),
+            pages: Vec::new(),
+            ordered: false,
+            prefix: None,
+            name_template: None,
+            existing_files: ExistingFiles::default(),
+            incremental: false,
+            background: None,
+            color: ColorMode::default(),
+            dither: Dither::default(),
+            region: Region::default(),
+            rotations: Vec::new(),
+            tiles: false,
+            tile_size: None,
+            max_memory: None,
+            nup: None,
+            password: None,
+            interpreter_settings: InterpreterSettings::default(),
+        }
+    }
+
+    /// The configured output format.
+    pub fn output_format(&self) -> OutputFormat {
+        self.format
+    }
+
+    /// Set how the output size of each page is determined.
+    pub fn sizing(mut self, sizing: Sizing) -> Self {
+        self.sizing = sizing;
+        self
+    }
+
+    /// Render every page with a fixed scale factor.
+    pub fn scale(self, scale: f32) -> Self {
+        self.sizing(Sizing::Scale(scale))
+    }
+
+    /// Select pages with the `--page` syntax, one item per element (for example `"1-3"`,
+    /// `"odd"` or `"!2"`). An empty selection converts every page.
+    pub fn pages<I, S>(mut self, items: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.pages = items.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Keep the requested page order and repeated pages instead of document order.
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Prefix for output file names. Without one, it is inferred from the input name.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Name outputs with a template instead of the prefix and page number.
+    pub fn name_template(mut self, template: NameTemplate) -> Self {
+        self.name_template = Some(template);
+        self
+    }
+
+    /// What [`Converter::convert`] does when an output file already exists. Defaults to
+    /// failing before anything is written.
+    pub fn existing_files(mut self, policy: ExistingFiles) -> Self {
+        self.existing_files = policy;
+        self
+    }
+
+    /// What pages are rendered onto. Defaults to white for raster formats and to no
+    /// background for SVG, where a colour inserts a rectangle behind the page.
+    ///
+    /// JPEG has no transparency, so a transparent background is flattened onto the JPEG
+    /// background colour. Bilevel TIFF pages treat transparent areas as white.
+    pub fn background(mut self, background: Background) -> Self {
+        self.background = Some(background);
+        self
+    }
+
+    /// Colour model of raster outputs. Defaults to RGB; SVG ignores it.
+    pub fn color(mut self, color: ColorMode) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// How [`ColorMode::Mono`] and bilevel TIFF pages are reduced to black and white.
+    /// Defaults to a plain threshold.
+    pub fn dither(mut self, dither: Dither) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// Render the given page box instead of the crop box.
+    ///
+    /// Raster outputs are always clipped to the crop box by the renderer, so the parts of a
+    /// larger box outside it show the background. SVG outputs are not clipped.
+    pub fn page_box(mut self, page_box: PageBox) -> Self {
+        self.region.page_box = page_box;
+        self
+    }
+
+    /// Only render the rectangle `crop` of the page box.
+    pub fn crop(mut self, crop: CropRect) -> Self {
+        self.region.crop = Some(crop);
+        self
+    }
+
+    /// Trim every page to the bounding box of its visible content, keeping `margin` points
+    /// around it. Content is whatever differs from the background, or anything painted when
+    /// it is transparent; blank pages are kept whole.
+    pub fn autotrim(mut self, margin: f32) -> Self {
+        self.region.autotrim = Some(margin.max(0.0));
+        self
+    }
+
+    /// Turn pages clockwise, on top of the rotation they define themselves. Rules added later
+    /// win for the pages they share with earlier ones.
+    pub fn rotate(mut self, rotation: PageRotation) -> Self {
+        self.rotations.push(rotation);
+        self
+    }
+
+    /// Ignore the `/Rotate` entry of pages, so they are only turned by
+    /// [`Converter::rotate`].
+    pub fn ignore_page_rotation(mut self, ignore: bool) -> Self {
+        self.region.ignore_rotate = ignore;
+        self
+    }
+
+    /// Write every page of PNG, JPEG and WebP outputs as a grid of separate tiles instead of
+    /// one image. Tiles are named after the page with the row and column appended, counted
+    /// from 0: `page-1_0_2.png` is the third tile of the first row. Other formats ignore
+    /// it.
+    pub fn tiles(mut self, tiles: bool) -> Self {
+        self.tiles = tiles;
+        self
+    }
+
+    /// Edge of square tiles in pixels. Defaults to 1024 for [`Converter::tiles`]; pages
+    /// stitched together under [`Converter::max_memory`] default to bands across the page.
+    pub fn tile_size(mut self, size: u32) -> Self {
+        self.tile_size = Some(size.max(1));
+        self
+    }
+
+    /// Limit the memory used for the pixels of pages being rendered to about `bytes`, shared
+    /// between the worker threads.
+    ///
+    /// PNG pages too large for the limit are rendered in tiles and stitched together while
+    /// they are encoded, which gives practically the same image. Other formats fail with
+    /// [`ErrorKind::Memory`] for such pages unless they are written as separate
+    /// [`Converter::tiles`]. PNG pages wider or taller than 65535 pixels, the largest a
+    /// single pixmap can be, are always stitched together. Pages are trimmed for
+    /// [`Converter::autotrim`] at a resolution that fits the limit as well.
+    pub fn max_memory(mut self, bytes: u64) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    /// Put consecutive selected pages side by side into each output instead of writing one
+    /// output per page, or order them for a booklet. Every page keeps its own size and scale,
+    /// in a grid of cells as large as the largest page of the output.
+    ///
+    /// Applies to PNG, JPEG, WebP and SVG outputs; other formats ignore it. SVG outputs embed
+    /// the drawing of each page as a group, so they stay vector graphics.
+    pub fn nup(mut self, nup: Nup) -> Self {
+        self.nup = Some(nup);
+        self
+    }
+
+    /// Keep a cache manifest in the output directory and skip outputs that are up to date.
+    ///
+    /// The manifest records the SHA-256 of each input, the settings that affect rendering
+    /// and the files produced. An output is up to date when the input and settings are
+    /// unchanged and the file still has its recorded size. Files the manifest records as
+    /// written from the same input (the same path, or the same contents) are replaced when
+    /// they are stale, whatever [`Converter::existing_files`] says; other existing files,
+    /// including those written from another input, still follow that policy.
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Password used to open encrypted documents.
+    ///
+    /// Either the user or the owner password works. Documents that are not encrypted, or
+    /// whose user password is empty, ignore it.
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Settings passed to the PDF interpreter.
+    pub fn interpreter_settings(mut self, settings: InterpreterSettings) -> Self {
+        self.interpreter_settings = settings;
+        self
+    }
+
+    /// Render the PDF at `input` and return its outputs in memory.
+    pub fn render(&self, input: &Path) -> Result<Vec<RenderedOutput>, Error> {
+        let doc = self.open(read_input(input)?, input)?;
+        self.render_document(&doc)
+    }
+
+    /// Render a PDF held in memory. Output names use the configured prefix, or `rendered`.
+    pub fn render_bytes(&self, data: Vec<u8>) -> Result<Vec<RenderedOutput>, Error> {
+        let doc = self.open(data, Path::new(""))?;
+        self.render_document(&doc)
+    }
+
+    /// Render the PDF at `input` into exactly one output file, as when writing to a stream.
+    /// Fails before rendering anything when the selection gives no file or several: with
+    /// [`ErrorKind::PageValidation`] when several pages are selected, and with
+    /// [`ErrorKind::Input`] when the one page gives several files or none, or the montage
+    /// several sheets.
+    pub fn render_single(&self, input: &Path) -> Result<RenderedOutput, Error> {
+        let doc = self.open(read_input(input)?, input)?;
+        self.render_single_document(&doc)
+    }
+
+    /// Render a PDF held in memory into exactly one output file, like
+    /// [`Converter::render_single`].
+    pub fn render_single_bytes(&self, data: Vec<u8>) -> Result<RenderedOutput, Error> {
+        let doc = self.open(data, Path::new(""))?;
+        self.render_single_document(&doc)
+    }
+
+    /// Convert the PDF at `input`, writing the outputs into the existing directory `output`.
+    pub fn convert(&self, input: &Path, output: &Path) -> Result<Conversion, Error> {
+        let doc = self.open(read_input(input)?, input)?;
+        self.write_document(&doc, input, output)
+    }
+
+    /// Convert a PDF held in memory, writing the outputs into the existing directory
+    /// `output`. Output names use the configured prefix, or `rendered`.
+    pub fn convert_bytes(&self, data: Vec<u8>, output: &Path) -> Result<Conversion, Error> {
+        let doc = self.open(data, Path::new(""))?;
+        self.write_document(&doc, Path::new(""), output)
+    }
+
+    fn write_document(
+        &self,
+        doc: &Document,
+        input: &Path,
+        output: &Path,
+    ) -> Result<Conversion, Error> {
+        let cache = self.incremental.then(|| {
+            let template = OutputRecord {
+                input: cache::input_label(input),
+                input_sha256: cache::sha256_hex(doc.pdf.data().as_ref()),
+                settings: self.cache_settings(),
+                pages: Vec::new(),
+                size: 0,
+            };
+            (Mutex::new(Manifest::load(output)), template)
+        });
+        // How each output would be recorded in the manifest, without its size.
+        let expected = |seq: usize| {
+            cache.as_ref().map(|(_, template)| OutputRecord {
+                pages: self.output_pages(doc, seq),
+                ..template.clone()
+            })
+        };
+        let record = |seq: usize, name: &str, size: usize| {
+            if let (Some((manifest, _)), Some(record)) = (&cache, expected(seq)) {
+                let record = OutputRecord {
+                    size: size as u64,
+                    ..record
+                };
+                let mut manifest = manifest.lock().unwrap_or_else(|e| e.into_inner());
+                manifest.record(name.to_string(), record);
+            }
+        };
+
+        // Outputs still to be written, by position in `doc.names`. Existing files are checked
+        // before anything is rendered, so a refused run leaves the directory untouched. Files
+        // of the outputs that may be replaced: all of them unless existing files are refused,
+        // when only those the manifest records as written by an earlier run may be.
+        let mut pending = Vec::with_capacity(doc.names.len());
+        let mut replace = vec![self.existing_files != ExistingFiles::Fail; doc.names.len()];
+        let mut pending_files = Vec::new();
+        let (mut skipped, mut up_to_date) = (0, 0);
+        for (seq, replace) in replace.iter_mut().enumerate() {
+            let files = self.output_files(doc, seq)?;
+            let write = 'decide: {
+                if let (Some((manifest, _)), Some(record)) = (&cache, expected(seq)) {
+                    let manifest = manifest.lock().unwrap_or_else(|e| e.into_inner());
+                    if files
+                        .iter()
+                        .all(|name| manifest.is_up_to_date(output, name, &record))
+                    {
+                        up_to_date += files.len();
+                        break 'decide false;
+                    }
+                    // Files another input wrote follow the existing-file policy below.
+                    let owned = |name: &String| manifest.is_owned_by(name, &record);
+                    let foreign = |name: &String| !owned(name) && output.join(name).exists();
+                    if files.iter().any(owned) && !files.iter().any(foreign) {
+                        *replace = true;
+                        break 'decide true;
+                    }
+                }
+                let Some(path) = files
+                    .iter()
+                    .map(|name| output.join(name))
+                    .find(|p| p.exists())
+                else {
+                    break 'decide true;
+                };
+                match self.existing_files {
+                    ExistingFiles::Overwrite => true,
+                    // A page with some of its tiles missing is rendered again as a whole.
+                    ExistingFiles::Skip => {
+                        let missing = files.iter().any(|name| !output.join(name).exists());
+                        if !missing {
+                            skipped += files.len();
+                        }
+                        missing
+                    }
+                    ExistingFiles::Fail => {
+                        return Err(Error::new(
+                            ErrorKind::OutputExists,
+                            format!("Output file {} already exists", path.display()),
+                        ));
+                    }
+                }
+            };
+            if write {
+                pending.push(seq);
+                pending_files.extend(files);
+            }
+        }
+        remove_stale_temp_files(output, &pending_files);
+
+        let result = self.write_pending(doc, output, &pending, &replace, record);
+        if let Some((manifest, _)) = cache {
+            // Save even when a page failed, so the pages that were written are not rendered
+            // again on the next run.
+            let saved = manifest
+                .into_inner()
+                .unwrap_or_else(|e| e.into_inner())
+                .save(output)
+                .map_err(|msg| Error::new(ErrorKind::FileSystem, msg));
+            if result.is_ok() {
+                saved?;
+            }
+        }
+        let (files, pages) = result?;
+        Ok(Conversion {
+            skipped,
+            files,
+            pages,
+            up_to_date,
+        })
+    }
+
+    /// Write the outputs at positions `pending`, replacing existing files where `replace` is
+    /// set for the position, and calling `record` with the position, name and size of each
+    /// written file. Returns the written paths and the number of pages rendered.
+    fn write_pending<R>(
+        &self,
+        doc: &Document,
+        output: &Path,
+        pending: &[usize],
+        replace: &[bool],
+        record: R,
+    ) -> Result<(Vec<PathBuf>, usize), Error>
+    where
+        R: Fn(usize, &str, usize) + Sync,
+    {
+        if pending.is_empty() {
+            return Ok((Vec::new(), 0));
+        }
+
+        if let OutputFormat::Tiff {
+            compression,
+            bilevel,
+        } = self.format
+        {
+            let out_path = output.join(&doc.names[0]);
+            let mut file = AtomicFile::create(&out_path, replace[0]).map_err(|e| {
+                Error::new(ErrorKind::FileSystem, format!("Failed to create TIFF: {e}"))
+            })?;
+            let pages = self.write_tiff(doc, &mut file, compression, bilevel)?;
+            file.commit()
+                .map_err(|e| write_error("TIFF", &out_path, e))?;
+            let size = fs::metadata(&out_path).map_or(0, |meta| meta.len() as usize);
+            record(0, &doc.names[0], size);
+            return Ok((vec![out_path], pages.len()));
+        }
+
+        let label = self.format.label();
+        if let Some(layout) = &doc.montage {
+            let mut files = Vec::with_capacity(pending.len());
+            let mut pages = 0;
+            for &sheet in pending {
+                let rendered = self.render_sheet(doc, layout, sheet)?;
+                let out_path = output.join(&rendered.file_name);
+                output::write_atomic(&out_path, &rendered.bytes, replace[sheet])
+                    .map_err(|e| write_error(label, &out_path, e))?;
+                record(sheet, &rendered.file_name, rendered.bytes.len());
+                files.push(out_path);
+                pages += rendered.pages.len();
+            }
+            return Ok((files, pages));
+        }
+
+        let files = self.for_each_output(doc, pending, |seq, rendered| {
+            let out_path = output.join(&rendered.file_name);
+            // Tile pyramids keep their tiles in subdirectories.
+            if let Some(dir) = out_path.parent() {
+                fs::create_dir_all(dir).map_err(|e| {
+                    Error::new(
+                        ErrorKind::FileSystem,
+                        format!("Failed to create output directory: {e}"),
+                    )
+                })?;
+            }
+            output::write_atomic(&out_path, &rendered.bytes, replace[seq])
+                .map_err(|e| write_error(label, &out_path, e))?;
+            record(seq, &rendered.file_name, rendered.bytes.len());
+            Ok(out_path)
+        })?;
+        let pages = pending
+            .iter()
+            .map(|&seq| self.output_pages(doc, seq).len())
+            .sum();
+        Ok((files.into_iter().flatten().collect(), pages))
+    }
+
+    /// The settings recorded in the cache manifest: everything besides the input and the
+    /// output name that changes the contents of an output file.
+    fn cache_settings(&self) -> CacheSettings {
+        CacheSettings {
+            converter: env!("CARGO_PKG_VERSION").to_string(),
+            format: format!("{:?}", self.format),
+            sizing: format!("{:?}", self.sizing),
+            background: format!("{:?}", self.background),
+            color: format!("{:?}", self.color),
+            dither: format!("{:?}", self.dither),
+            region: format!("{:?}", self.region),
+            rotations: format!("{:?}", self.rotations),
+            tiles: format!("{:?}", self.tiles.then(|| self.tile_grid_size())),
+            nup: format!("{:?}", self.nup_layout()),
+            render_annotations: self.interpreter_settings.render_annotations,
+        }
+    }
+
+    /// How pages are put side by side, if the output format supports it.
+    fn nup_layout(&self) -> Option<Nup> {
+        self.nup
+            .filter(|_| self.format.raster_encoding().is_some() || self.format == OutputFormat::Svg)
+    }
+
+    /// Edge of the tiles written by [`Converter::tiles`].
+    fn tile_grid_size(&self) -> u32 {
+        self.tile_size.unwrap_or(DEFAULT_TILE_SIZE)
+    }
+
+    /// The share of [`Converter::max_memory`] each worker thread may use.
+    fn worker_budget(&self) -> Option<u64> {
+        self.max_memory
+            .map(|bytes| bytes / rayon::current_num_threads() as u64)
+    }
+
+    /// Whether a raster page of `width` x `height` pixels can be rendered in one piece.
+    fn fits_in_memory(&self, width: u32, height: u32) -> bool {
+        width <= MAX_PIXMAP_SIZE
+            && height <= MAX_PIXMAP_SIZE
+            && self
+                .worker_budget()
+                .is_none_or(|budget| width as u64 * height as u64 * PAGE_BYTES_PER_PIXEL <= budget)
+    }
+
+    /// The error for an output, named by `what`, that is too large to render in one piece.
+    fn too_large(&self, what: &str, width: u32, height: u32) -> Error {
+        let limit = if width > MAX_PIXMAP_SIZE || height > MAX_PIXMAP_SIZE {
+            format!("more than the {MAX_PIXMAP_SIZE} pixels a side an image can have")
+        } else {
+            "too large for the memory limit".to_string()
+        };
+        Error::new(
+            ErrorKind::Memory,
+            format!(
+                "{what} is {width}x{height} pixels, {limit}. Only PNG output can be stitched \
+                 together from tiles; {}",
+                match self.format.raster_encoding() {
+                    Some(_) => format!(
+                        "write {} as separate tiles or reduce the size",
+                        self.format.label()
+                    ),
+                    None => "reduce the size".to_string(),
+                }
+            ),
+        )
+    }
+
+    /// The background raster pages are rendered onto.
+    fn raster_background(&self) -> Background {
+        self.background.unwrap_or(Background::WHITE)
+    }
+
+    /// Parse `data` as a PDF and resolve the page selection against it.
+    fn open(&self, data: Vec<u8>, input: &Path) -> Result<Document, Error> {
+        let pdf = load_pdf(data, self.password.as_deref())?;
+
+        let selection = utils::resolve_page_spec(&self.pages, pdf.pages().len(), self.ordered)
+            .map_err(|msg| Error::new(ErrorKind::PageValidation, msg))?;
+
+        let rotations = self.page_rotations(pdf.pages().len())?;
+        let views = self.page_views(&pdf, &selection, &rotations)?;
+        let montage = self.sheet_layout(&selection, &views)?;
+        let nup = self.nup_layout();
+        if let Some(nup) = nup.filter(|nup| nup.booklet && nup.pages_per_output() != 2) {
+            return Err(Error::new(
+                ErrorKind::PageValidation,
+                format!(
+                    "A booklet needs two pages per output, but the layout {}x{} holds {}",
+                    nup.columns,
+                    nup.rows,
+                    nup.pages_per_output()
+                ),
+            ));
+        }
+        let spreads = nup.map_or_else(Vec::new, |nup| nup.groups(selection.len()));
+        let mut doc = Document {
+            pdf,
+            selection,
+            views,
+            names: Vec::new(),
+            montage,
+            nup,
+            spreads,
+        };
+        doc.names = self.output_names(&doc, input)?;
+        Ok(doc)
+    }
+
+    /// Where the selected pages go on the sheets of a montage, for the montage format.
+    fn sheet_layout(
+        &self,
+        selection: &[usize],
+        views: &[Option<PageView>],
+    ) -> Result<Option<SheetLayout>, Error> {
+        let OutputFormat::Montage {
+            columns,
+            spacing,
+            captions,
+            max_sheet,
+        } = self.format
+        else {
+            return Ok(None);
+        };
+        let sizes: Vec<_> = selection
+            .iter()
+            .map(|&idx| self.output_size(&views[idx].expect("selected pages have a view")))
+            .collect();
+        Ok(Some(SheetLayout::new(
+            &sizes, columns, spacing, captions, max_sheet,
+        )))
+    }
+
+    /// The extra rotation of each of the `total` pages of a document, from the rotation rules.
+    fn page_rotations(&self, total: usize) -> Result<Vec<u16>, Error> {
+        let mut rotations = vec![0; total];
+        for rule in &self.rotations {
+            if rule.angle % 90 != 0 || rule.angle >= 360 {
+                return Err(Error::new(
+                    ErrorKind::PageValidation,
+                    format!("Invalid rotation {}: expected 90, 180 or 270", rule.angle),
+                ));
+            }
+            let pages = if rule.pages.is_empty() {
+                (0..total).collect()
+            } else {
+                utils::resolve_page_spec(&rule.pages, total, false)
+                    .map_err(|msg| Error::new(ErrorKind::PageValidation, msg))?
+            };
+            for idx in pages {
+                rotations[idx] = rule.angle;
+            }
+        }
+        Ok(rotations)
+    }
+
+    /// File names of the outputs of a document, in output order. Container formats get one
+    /// name per sheet, numbered when there is more than one.
+    ///
+    /// Fails when the name template would give two outputs the same name.
+    fn output_names(&self, doc: &Document, input: &Path) -> Result<Vec<String>, Error> {
+        let (pdf, selection) = (&doc.pdf, &doc.selection);
+        let sheets = doc.montage.as_ref().map_or(1, SheetLayout::sheets);
+        let ext = self.format.extension();
+        let Some(template) = &self.name_template else {
+            let prefix = utils::resolve_prefix(self.prefix.as_deref(), input);
+            if self.format.is_container() {
+                let name = utils::container_file_name(&prefix, ext);
+                return Ok(montage::sheet_names(&name, sheets));
+            }
+            if let Some(nup) = &doc.nup {
+                return Ok((0..doc.spreads.len())
+                    .map(|seq| {
+                        let pages = self.output_pages(doc, seq);
+                        nup.output_name(&prefix, seq, doc.spreads.len(), &pages, self.ordered, ext)
+                    })
+                    .collect());
+            }
+            return Ok(selection
+                .iter()
+                .enumerate()
+                .map(|(seq, &idx)| {
+                    utils::output_file_name(&prefix, seq, selection.len(), idx, self.ordered, ext)
+                })
+                .collect());
+        };
+
+        let stem = input
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "rendered".to_string());
+        let pages = pdf.pages();
+        let mut fields = NameFields {
+            stem: &stem,
+            page: 0,
+            seq: 0,
+            total: pages.len(),
+            label: "",
+            width: 0,
+            height: 0,
+            format: ext,
+        };
+
+        if self.format.is_container() {
+            if let Some(field) = template.per_page_fields().first() {
+                return Err(Error::new(
+                    ErrorKind::NameTemplate,
+                    format!(
+                        "The name template '{template}' uses {{{field}}}, but {} output has no \
+                         file per page",
+                        self.format.label()
+                    ),
+                ));
+            }
+            return Ok(montage::sheet_names(
+                &template.file_name(&fields, ext),
+                sheets,
+            ));
+        }
+
+        // Outputs of pages put side by side are named after their first page.
+        let labels = labels::page_labels(pdf);
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        let count = doc.nup.map_or(selection.len(), |_| doc.spreads.len());
+        let mut names = Vec::with_capacity(count);
+        let mut first_pages = Vec::with_capacity(count);
+        for seq in 0..count {
+            let (idx, (width, height)) = match &doc.nup {
+                Some(nup) => {
+                    let (size, spread) = self.spread(doc, nup, seq)?;
+                    (spread.first().map(|page| page.idx), size)
+                }
+                None => {
+                    let idx = doc.selection[seq];
+                    (Some(idx), self.output_size(&doc.view(idx)))
+                }
+            };
+            fields.page = idx.map_or(0, |idx| idx + 1);
+            fields.seq = seq + 1;
+            fields.label = idx.map_or("", |idx| &labels[idx]);
+            fields.width = width;
+            fields.height = height;
+            let name = template.file_name(&fields, ext);
+            if let Some(&other) = seen.get(&name) {
+                let page = |idx: Option<usize>| idx.map_or(0, |idx| idx + 1);
+                return Err(Error::new(
+                    ErrorKind::NameTemplate,
+                    format!(
+                        "The name template '{template}' gives outputs {} (page {}) and {} \
+                         (page {}) the same file name '{name}'",
+                        other + 1,
+                        page(first_pages[other]),
+                        seq + 1,
+                        page(idx)
+                    ),
+                ));
+            }
+            seen.insert(name.clone(), seq);
+            names.push(name);
+            first_pages.push(idx);
+        }
+        Ok(names)
+    }
+
+    /// Document pages held by the output at position `seq`, in order.
+    fn output_pages(&self, doc: &Document, seq: usize) -> Vec<usize> {
+        match &doc.montage {
+            Some(layout) => layout.pages_on(seq).map(|i| doc.selection[i]).collect(),
+            None if self.format.is_container() => doc.selection.clone(),
+            None if doc.nup.is_some() => doc.spreads[seq]
+                .iter()
+                .flatten()
+                .map(|&i| doc.selection[i])
+                .collect(),
+            None => vec![doc.selection[seq]],
+        }
+    }
+
+    /// File names the output at position `seq` is written to: its name, the names of its
+    /// tiles, every file of its tile pyramid, or the images of its page.
+    fn output_files(&self, doc: &Document, seq: usize) -> Result<Vec<String>, Error> {
+        let name = &doc.names[seq];
+        if self.format == OutputFormat::Images {
+            let images = self.page_images(doc, seq, false)?;
+            return Ok(images.into_iter().map(|image| image.file_name).collect());
+        }
+        let tiled = self.tiles && self.format.raster_encoding().is_some();
+        if !tiled && self.format.pyramid(1, 1).is_none() {
+            return Ok(vec![name.clone()]);
+        }
+        let (width, height) = match &doc.nup {
+            Some(nup) => self.spread(doc, nup, seq)?.0,
+            None => self.output_size(&doc.view(doc.selection[seq])),
+        };
+        if let Some(pyramid) = self.format.pyramid(width, height) {
+            return Ok(pyramid.file_names(name));
+        }
+        let size = self.tile_grid_size();
+        let grid = TileGrid::new(width, height, size, size);
+        Ok(grid
+            .tiles()
+            .map(|(row, col)| tiles::tile_name(name, row, col))
+            .collect())
+    }
+
+    /// The area rendered of each selected page, by page index, with the extra `rotations`.
+    /// Pages are trimmed in parallel, each within the memory of a worker.
+    fn page_views(
+        &self,
+        pdf: &Pdf,
+        selection: &[usize],
+        rotations: &[u16],
+    ) -> Result<Vec<Option<PageView>>, Error> {
+        let pages = pdf.pages();
+        let mut views = vec![None; pages.len()];
+        let mut unique = selection.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
+        let found = unique
+            .par_iter()
+            .map(|&idx| {
+                self.region
+                    .page_view(
+                        &pages[idx],
+                        rotations[idx],
+                        self.raster_background(),
+                        &self.sizing,
+                        self.worker_budget(),
+                        &self.interpreter_settings,
+                    )
+                    .map_err(|msg| Error::new(ErrorKind::Crop, msg))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        for (idx, view) in unique.into_iter().zip(found) {
+            views[idx] = Some(view);
+        }
+        Ok(views)
+    }
+
+    /// Size of the output for `view`: pixels for raster formats, user units for SVG.
+    fn output_size(&self, view: &PageView) -> (u32, u32) {
+        let (width, height) = (view.width, view.height);
+        if self.format == OutputFormat::Svg {
+            let scale = self.sizing.scale_for(width, height);
+            ((width * scale).round() as u32, (height * scale).round() as u32)
+        } else {
+            self.sizing.pixel_size_for(width, height)
+        }
+    }
+
+    fn render_document(&self, doc: &Document) -> Result<Vec<RenderedOutput>, Error> {
+        if let OutputFormat::Tiff {
+            compression,
+            bilevel,
+        } = self.format
+        {
+            let mut cursor = Cursor::new(Vec::new());
+            let pages = self.write_tiff(doc, &mut cursor, compression, bilevel)?;
+            return Ok(vec![RenderedOutput {
+                file_name: doc.names[0].clone(),
+                pages,
+                bytes: cursor.into_inner(),
+            }]);
+        }
+        if let Some(layout) = &doc.montage {
+            return (0..layout.sheets())
+                .map(|sheet| self.render_sheet(doc, layout, sheet))
+                .collect();
+        }
+        let all: Vec<usize> = (0..doc.names.len()).collect();
+        let outputs = self.for_each_output(doc, &all, |_, rendered| Ok(rendered))?;
+        Ok(outputs.into_iter().flatten().collect())
+    }
+
+    fn render_single_document(&self, doc: &Document) -> Result<RenderedOutput, Error> {
+        let mut files = 0;
+        for seq in 0..doc.names.len() {
+            files += self.output_files(doc, seq)?.len();
+        }
+        let single = |message: String| {
+            Error::new(
+                ErrorKind::Input,
+                format!("A single output file is needed, but {message}"),
+            )
+        };
+        if files != 1 {
+            return Err(match doc.names.len() {
+                n if doc.montage.is_some() && n > 1 => {
+                    single(format!("the montage has {n} sheets"))
+                }
+                1 if files > 1 => single(format!("the page gives {files} files")),
+                1 => single("the page has no images".to_string()),
+                n => Error::new(
+                    ErrorKind::PageValidation,
+                    format!(
+                        "A single output file needs a single page to be selected, but {n} were"
+                    ),
+                ),
+            });
+        }
+        let mut outputs = self.render_document(doc)?;
+        match outputs.len() {
+            1 => Ok(outputs.remove(0)),
+            n => Err(single(format!("rendering gave {n}"))),
+        }
+    }
+
+    /// Render the selected pages at the output positions `outputs` in parallel and pass
+    /// each of their files to `sink` together with the position. Returns the results of
+    /// `sink` grouped by page.
+    ///
+    /// Every result is collected before errors are propagated, so the reported error is
+    /// deterministic: the first failing page in output order.
+    fn for_each_output<T, F>(
+        &self,
+        doc: &Document,
+        outputs: &[usize],
+        sink: F,
+    ) -> Result<Vec<Vec<T>>, Error>
+    where
+        T: Send,
+        F: Fn(usize, RenderedOutput) -> Result<T, Error> + Sync,
+    {
+        let results: Vec<Result<Vec<T>, Error>> = outputs
+            .par_iter()
+            .map(|&seq| {
+                let mut results = Vec::new();
+                self.render_page(doc, seq, &mut |rendered| {
+                    results.push(sink(seq, rendered)?);
+                    Ok(())
+                })?;
+                Ok(results)
+            })
+            .collect();
+        results.into_iter().collect()
+    }
+
+    /// Render the output at position `seq` and pass it, or each of its tiles, to `emit` as
+    /// soon as it is encoded.
+    fn render_page(
+        &self,
+        doc: &Document,
+        seq: usize,
+        emit: &mut dyn FnMut(RenderedOutput) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        if let Some(nup) = &doc.nup {
+            return self.render_spread(doc, nup, seq, emit);
+        }
+        let idx = doc.selection[seq];
+        let page = &doc.pdf.pages()[idx];
+        let file_name = doc.names[seq].clone();
+        if self.format == OutputFormat::Images {
+            for image in self.page_images(doc, seq, true)? {
+                emit(RenderedOutput {
+                    file_name: image.file_name,
+                    pages: vec![PageInfo {
+                        index: idx,
+                        width: image.width,
+                        height: image.height,
+                        scale: 1.0,
+                    }],
+                    bytes: image.bytes,
+                })?;
+            }
+            return Ok(());
+        }
+        let view = doc.view(idx);
+
+        let (width, height) = self.output_size(&view);
+        if let Some(pyramid) = self.format.pyramid(width, height) {
+            return self.render_pyramid(page, idx, &view, &pyramid, &file_name, emit);
+        }
+
+        let scale = self.sizing.scale_for(view.width, view.height);
+        let info = PageInfo {
+            index: idx,
+            width,
+            height,
+            scale,
+        };
+        if let OutputFormat::Text { json } = self.format {
+            let runs = text::extract_text(page, &view, &self.interpreter_settings);
+            let text = if json {
+                text::page_json(idx, &view, &runs)
+            } else {
+                text::plain_text(&runs)
+            };
+            return emit(RenderedOutput {
+                file_name,
+                pages: vec![info],
+                bytes: text.into_bytes(),
+            });
+        }
+        if let Some(encoding) = self.format.raster_encoding() {
+            let background = self.raster_background();
+            let settings = &self.interpreter_settings;
+            let render = |tile| rasterize_tile(page, &view, scale, tile, background, settings);
+            let what = format!("Page {}", idx + 1);
+            return self.render_raster(
+                encoding,
+                file_name,
+                (width, height),
+                vec![info],
+                &what,
+                &render,
+                emit,
+            );
+        }
+
+        let svg = self.svg_background(self.page_svg(page, &view, scale)?)?;
+        emit(RenderedOutput {
+            file_name,
+            pages: vec![info],
+            bytes: svg.into_bytes(),
+        })
+    }
+
+    /// Encode a raster output of `width` x `height` pixels holding `pages`, whose pixel
+    /// rectangles `render` renders, and pass it or each of its tiles to `emit`. `what` names
+    /// the output in errors.
+    #[allow(clippy::too_many_arguments)]
+    fn render_raster(
+        &self,
+        encoding: RasterEncoding,
+        file_name: String,
+        (width, height): (u32, u32),
+        pages: Vec<PageInfo>,
+        what: &str,
+        render: &dyn Fn((u32, u32, u16, u16)) -> Pixmap,
+        emit: &mut dyn FnMut(RenderedOutput) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        if self.tiles {
+            let size = self.tile_grid_size();
+            let grid = TileGrid::new(width, height, size, size);
+            for (row, col) in grid.tiles() {
+                let tile = grid.tile(row, col);
+                let bytes = encoding
+                    .encode(render(tile), self.color, self.dither)
+                    .map_err(|msg| Error::new(ErrorKind::Encode, msg))?;
+                let pages = pages
+                    .iter()
+                    .map(|info| PageInfo {
+                        width: tile.2 as u32,
+                        height: tile.3 as u32,
+                        ..info.clone()
+                    })
+                    .collect();
+                emit(RenderedOutput {
+                    file_name: tiles::tile_name(&file_name, row, col),
+                    pages,
+                    bytes,
+                })?;
+            }
+            return Ok(());
+        }
+
+        let bytes = if self.fits_in_memory(width, height) {
+            let pixmap = render((0, 0, width as u16, height as u16));
+            encoding.encode(pixmap, self.color, self.dither)
+        } else if matches!(encoding, RasterEncoding::Png) {
+            let grid = tiles::band_grid(width, height, self.tile_size, self.worker_budget());
+            let alpha = self.raster_background() == Background::Transparent;
+            tiles::stitch_png(&grid, self.color, self.dither, alpha, render)
+        } else {
+            return Err(self.too_large(what, width, height));
+        }
+        .map_err(|msg| Error::new(ErrorKind::Encode, msg))?;
+        emit(RenderedOutput {
+            file_name,
+            pages,
+            bytes,
+        })
+    }
+
+    /// The SVG of the area `view` of `page`, scaled by `scale`, without a background.
+    fn page_svg(&self, page: &Page, view: &PageView, scale: f32) -> Result<String, Error> {
+        let svg = convert(
+            page,
+            &hayro_svg::RenderCache::new(),
+            &self.interpreter_settings,
+            &SvgRenderSettings::default(),
+        );
+        let svg = if *view == PageView::full(page) {
+            svg
+        } else {
+            svg::transform_svg(
+                &svg,
+                view.relative_to_full(page).as_coeffs(),
+                view.width,
+                view.height,
+            )
+            .map_err(|msg| Error::new(ErrorKind::Encode, msg))?
+        };
+        if (scale - 1.0).abs() > f32::EPSILON {
+            svg::scale_svg(&svg, scale).map_err(|msg| Error::new(ErrorKind::Encode, msg))
+        } else {
+            Ok(svg)
+        }
+    }
+
+    /// The images embedded in the page at position `seq`, named after its output. Without
+    /// `decode` only their names and sizes are worked out.
+    fn page_images(
+        &self,
+        doc: &Document,
+        seq: usize,
+        decode: bool,
+    ) -> Result<Vec<EmbeddedImage>, Error> {
+        let idx = doc.selection[seq];
+        let page = &doc.pdf.pages()[idx];
+        images::page_images(page, &doc.names[seq], &self.interpreter_settings, decode)
+            .map_err(|msg| Error::new(ErrorKind::Encode, format!("{msg} on page {}", idx + 1)))
+    }
+
+    /// Insert the background colour, if any, behind the drawing of an SVG output.
+    fn svg_background(&self, svg: String) -> Result<String, Error> {
+        match self.background {
+            Some(Background::Color(rgb)) => {
+                svg::add_background(&svg, rgb).map_err(|msg| Error::new(ErrorKind::Encode, msg))
+            }
+            Some(Background::Transparent) | None => Ok(svg),
+        }
+    }
+
+    /// Render the output at position `seq` of pages put side by side and pass it, or each
+    /// of its tiles, to `emit`.
+    ///
+    /// Raster outputs render every page straight into the pixels it covers, so they can be
+    /// rendered in tiles like single pages. SVG outputs embed the SVG of each page.
+    fn render_spread(
+        &self,
+        doc: &Document,
+        nup: &Nup,
+        seq: usize,
+        emit: &mut dyn FnMut(RenderedOutput) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let (size, pages) = self.spread(doc, nup, seq)?;
+        let pdf_pages = doc.pdf.pages();
+        let file_name = doc.names[seq].clone();
+        let infos = pages
+            .iter()
+            .map(|page| PageInfo {
+                index: page.idx,
+                width: page.size.0,
+                height: page.size.1,
+                scale: page.scale,
+            })
+            .collect();
+
+        if let Some(encoding) = self.format.raster_encoding() {
+            let background = self.raster_background();
+            let render = |(x, y, width, height): (u32, u32, u16, u16)| {
+                let mut pixmap = Pixmap::new(width, height);
+                if let Background::Color([r, g, b]) = background {
+                    fill_rect(
+                        &mut pixmap,
+                        (0, 0, width as i64, height as i64),
+                        [r, g, b, 255],
+                    );
+                }
+                for page in &pages {
+                    let (px, py) = page.position;
+                    let (left, top) = (x.max(px), y.max(py));
+                    let right = (x + width as u32).min(px + page.size.0);
+                    let bottom = (y + height as u32).min(py + page.size.1);
+                    if left >= right || top >= bottom {
+                        continue;
+                    }
+                    let tile = (
+                        left - px,
+                        top - py,
+                        (right - left) as u16,
+                        (bottom - top) as u16,
+                    );
+                    let part = rasterize_tile(
+                        &pdf_pages[page.idx],
+                        &page.view,
+                        page.scale,
+                        tile,
+                        background,
+                        &self.interpreter_settings,
+                    );
+                    paste_pixmap(&mut pixmap, &part, left - x, top - y);
+                }
+                pixmap
+            };
+            let what = format!("Output {file_name}");
+            return self.render_raster(encoding, file_name, size, infos, &what, &render, emit);
+        }
+
+        let parts = pages
+            .iter()
+            .map(|page| {
+                let svg = self.page_svg(&pdf_pages[page.idx], &page.view, page.scale)?;
+                Ok((svg, page.position.0 as f32, page.position.1 as f32))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let svg = svg::impose(&parts, size.0 as f32, size.1 as f32)
+            .map_err(|msg| Error::new(ErrorKind::Encode, msg))?;
+        emit(RenderedOutput {
+            file_name,
+            pages: infos,
+            bytes: self.svg_background(svg)?.into_bytes(),
+        })
+    }
+
+    /// Size of the output at position `seq` of pages put side by side, and its pages in
+    /// reading order with their place in it.
+    fn spread(
+        &self,
+        doc: &Document,
+        nup: &Nup,
+        seq: usize,
+    ) -> Result<((u32, u32), Vec<SpreadPage>), Error> {
+        let mut pages = Vec::new();
+        let mut sizes = Vec::new();
+        for (slot, pos) in doc.spreads[seq].iter().enumerate() {
+            let Some(pos) = *pos else {
+                continue;
+            };
+            let idx = doc.selection[pos];
+            let view = doc.view(idx);
+            let size = self.output_size(&view);
+            sizes.push((slot, size));
+            pages.push(SpreadPage {
+                idx,
+                view,
+                scale: self.sizing.scale_for(view.width, view.height),
+                size,
+                position: (0, 0),
+            });
+        }
+        let (size, positions) = nup.place(&sizes);
+        for (page, position) in pages.iter_mut().zip(positions) {
+            page.position = position;
+        }
+        Ok((size, pages))
+    }
+
+    /// Render the tile pyramid of document page `idx` and pass its tiles, then its
+    /// descriptor `name`, to `emit`.
+    ///
+    /// Every level is rendered from the page itself rather than scaled down from the level
+    /// above, a band across the level at a time, from which its row of tiles is cut.
+    fn render_pyramid(
+        &self,
+        page: &Page,
+        idx: usize,
+        view: &PageView,
+        pyramid: &Pyramid,
+        name: &str,
+        emit: &mut dyn FnMut(RenderedOutput) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let scale = self.sizing.scale_for(view.width, view.height);
+        let background = self.raster_background();
+        let settings = &self.interpreter_settings;
+
+        for level in pyramid.levels() {
+            let level_scale = scale * pyramid.level_scale(level);
+            let render = |tile| rasterize_tile(page, view, level_scale, tile, background, settings);
+            let (level_width, _) = pyramid.level_size(level);
+            let (cols, rows) = pyramid.grid(level);
+            for row in 0..rows {
+                let (_, y, _, band_height) = pyramid.tile(level, 0, row);
+                let band = self
+                    .fits_in_memory(level_width, band_height as u32)
+                    .then(|| render((0, y, level_width as u16, band_height)));
+                for col in 0..cols {
+                    let tile = pyramid.tile(level, col, row);
+                    let mut pixmap = match &band {
+                        Some(band) => tiles::crop_pixmap(band, (tile.0, 0, tile.2, tile.3)),
+                        None => render(tile),
+                    };
+                    if let Some(size) = pyramid.padded_tile_size()
+                        && (tile.2, tile.3) != (size, size)
+                    {
+                        let mut padded = Pixmap::new(size, size);
+                        if let Background::Color([r, g, b]) = background {
+                            let size = size as i64;
+                            fill_rect(&mut padded, (0, 0, size, size), [r, g, b, 255]);
+                        }
+                        paste_pixmap(&mut padded, &pixmap, 0, 0);
+                        pixmap = padded;
+                    }
+                    let (width, height) = (pixmap.width() as u32, pixmap.height() as u32);
+                    let bytes = RasterEncoding::Png
+                        .encode(pixmap, self.color, self.dither)
+                        .map_err(|msg| Error::new(ErrorKind::Encode, msg))?;
+                    emit(RenderedOutput {
+                        file_name: pyramid.tile_name(name, level, col, row),
+                        pages: vec![PageInfo {
+                            index: idx,
+                            width,
+                            height,
+                            scale: level_scale,
+                        }],
+                        bytes,
+                    })?;
+                }
+            }
+        }
+
+        emit(RenderedOutput {
+            file_name: name.to_string(),
+            pages: vec![PageInfo {
+                index: idx,
+                width: pyramid.width,
+                height: pyramid.height,
+                scale,
+            }],
+            bytes: pyramid.descriptor().into_bytes(),
+        })
+    }
+
+    /// Render the thumbnails of the pages on `sheet` of a montage in parallel and put them
+    /// together into the sheet.
+    fn render_sheet(
+        &self,
+        doc: &Document,
+        layout: &SheetLayout,
+        sheet: usize,
+    ) -> Result<RenderedOutput, Error> {
+        let (width, height) = layout.sheet_size(sheet);
+        if !self.fits_in_memory(width, height) {
+            return Err(Error::new(
+                ErrorKind::Memory,
+                format!(
+                    "Sheet {} is {width}x{height} pixels, too large for the memory limit. Use a \
+                     smaller sheet size or smaller thumbnails",
+                    sheet + 1
+                ),
+            ));
+        }
+        let pdf_pages = doc.pdf.pages();
+        let background = self.raster_background();
+        let thumbnails = layout
+            .pages_on(sheet)
+            .into_par_iter()
+            .map(|seq| {
+                let idx = doc.selection[seq];
+                let page = &pdf_pages[idx];
+                let view = doc.view(idx);
+                let scale = self.sizing.scale_for(view.width, view.height) * layout.shrink;
+                let (width, height) = layout.thumbnail_size(seq);
+                let tile = (0, 0, width as u16, height as u16);
+                let pixmap = rasterize_tile(
+                    page,
+                    &view,
+                    scale,
+                    tile,
+                    background,
+                    &self.interpreter_settings,
+                );
+                let info = PageInfo {
+                    index: idx,
+                    width,
+                    height,
+                    scale,
+                };
+                Ok(((pixmap, idx + 1), info))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let (thumbnails, pages): (Vec<_>, Vec<_>) = thumbnails.into_iter().unzip();
+
+        let pixmap = layout.compose(sheet, &thumbnails, background);
+        let bytes = RasterEncoding::Png
+            .encode(pixmap, self.color, self.dither)
+            .map_err(|msg| Error::new(ErrorKind::Encode, msg))?;
+        Ok(RenderedOutput {
+            file_name: doc.names[sheet].clone(),
+            pages,
+            bytes,
+        })
+    }
+
+    /// Render every selected page into a multi-page TIFF written to `writer`.
+    fn write_tiff<W: Write + Seek>(
+        &self,
+        doc: &Document,
+        writer: W,
+        compression: TiffCompression,
+        bilevel: bool,
+    ) -> Result<Vec<PageInfo>, Error> {
+        let color = if bilevel { ColorMode::Mono } else { self.color };
+        let alpha = self.raster_background() == Background::Transparent;
+        let mut tiff = MultiPageTiff::new(
+            writer,
+            compression,
+            color,
+            self.dither,
+            alpha,
+            doc.selection.len(),
+        )
+        .map_err(|msg| Error::new(ErrorKind::Encode, msg))?;
+
+        // Render one batch of pages per worker in parallel, then append them in order so only
+        // a bounded number of pixmaps is held in memory.
+        let pdf_pages = doc.pdf.pages();
+        let mut pages = Vec::with_capacity(doc.selection.len());
+        for chunk in doc.selection.chunks(rayon::current_num_threads()) {
+            let rendered: Vec<_> = chunk
+                .par_iter()
+                .map(|&idx| {
+                    let page = &pdf_pages[idx];
+                    let view = doc.view(idx);
+                    let (width, height) = self.output_size(&view);
+                    if !self.fits_in_memory(width, height) {
+                        return Err(self.too_large(&format!("Page {}", idx + 1), width, height));
+                    }
+                    let (pixmap, scale) = rasterize(
+                        page,
+                        &view,
+                        &self.sizing,
+                        self.raster_background(),
+                        &self.interpreter_settings,
+                    );
+                    Ok((idx, pixmap, scale))
+                })
+                .collect::<Result<_, Error>>()?;
+            for (idx, pixmap, scale) in rendered {
+                tiff.add_page(&pixmap, 72.0 * scale)
+                    .map_err(|msg| Error::new(ErrorKind::Encode, msg))?;
+                pages.push(PageInfo {
+                    index: idx,
+                    width: pixmap.width() as u32,
+                    height: pixmap.height() as u32,
+                    scale,
+                });
+            }
+        }
+
+        Ok(pages)
+    }
+}
+
+/// Parse `data` as a PDF, decrypting it with `password` when it is encrypted.
+///
+/// The password may be the user or the owner password. A document whose user password is
+/// empty, which only restricts what may be done with it, opens whatever password is given.
+pub(crate) fn load_pdf(data: Vec<u8>, password: Option<&str>) -> Result<Pdf, Error> {
+    // Detect file format and ensure it's a PDF
+    let fmt = FileFormat::from_bytes(&data);
+    if fmt != FileFormat::PortableDocumentFormat {
+        return Err(Error::new(ErrorKind::FileType, "Input file is not a PDF"));
+    }
+
+    let data = Arc::new(data);
+    let open = |password: &str| Pdf::new_with_password(data.clone(), password);
+    let mut result = open(password.unwrap_or_default());
+    if let Some(password) = password.filter(|p| !p.is_empty()) {
+        let rejected = |result: &Result<Pdf, LoadPdfError>| {
+            matches!(
+                result,
+                Err(LoadPdfError::Decryption(DecryptionError::PasswordProtected))
+            )
+        };
+        if rejected(&result) {
+            result = open("");
+        }
+        if rejected(&result)
+            && let Some(user) = password::user_password_from_owner(&data, password)
+        {
+            // hayro takes passwords as text, so one that is not UTF-8 cannot be given to it.
+            let Ok(user) = String::from_utf8(user) else {
+                return Err(Error::new(
+                    ErrorKind::Encrypted,
+                    "The owner password is valid, but the user password it unlocks is not \
+                     UTF-8 text and cannot be used to open the document",
+                ));
+            };
+            result = open(&user);
+        }
+    }
+
+    result.map_err(|e| match e {
+        LoadPdfError::Decryption(DecryptionError::PasswordProtected) => {
+            let message = if password.is_some() {
+                "The document is encrypted and the given password is not valid"
+            } else {
+                "The document is encrypted and no password was given"
+            };
+            Error::new(ErrorKind::Encrypted, message)
+        }
+        LoadPdfError::Decryption(DecryptionError::UnsupportedAlgorithm) => Error::new(
+            ErrorKind::Encrypted,
+            "The document is encrypted with an unsupported encryption scheme",
+        ),
+        LoadPdfError::Decryption(_) => Error::new(
+            ErrorKind::Pdf,
+            "Failed to read PDF: the encryption dictionary is invalid",
+        ),
+        e => Error::new(ErrorKind::Pdf, format!("Failed to read PDF: {e:?}")),
+    })
+}
+
+/// The error for an output of the format `label` that could not be written to `path`.
+fn write_error(label: &str, path: &Path, e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::AlreadyExists {
+        // Another process created the file after the check before rendering.
+        return Error::new(
+            ErrorKind::OutputExists,
+            format!("Output file {} already exists", path.display()),
+        );
+    }
+    Error::new(
+        ErrorKind::FileSystem,
+        format!("Failed to write {label}: {e}"),
+    )
+}
+
+/// Remove the temporary files interrupted runs left behind for the output files `names`,
+/// given relative to `output`.
+fn remove_stale_temp_files(output: &Path, names: &[String]) {
+    let mut dirs: HashMap<PathBuf, HashSet<&str>> = HashMap::new();
+    for name in names {
+        let path = Path::new(name);
+        if let (Some(dir), Some(file_name)) = (path.parent(), path.file_name()) {
+            let file_names = dirs.entry(output.join(dir)).or_default();
+            file_names.insert(file_name.to_str().unwrap_or_default());
+        }
+    }
+    for (dir, file_names) in &dirs {
+        output::remove_stale_temp_files(dir, file_names);
+    }
+}
+
+pub(crate) fn read_input(input: &Path) -> Result<Vec<u8>, Error> {
+    fs::read(input).map_err(|e| {
+        Error::new(ErrorKind::FileSystem, format!("Failed to read input file: {e}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{blank_pages_pdf, content_page_pdf, test_dir};
+
+    #[test]
+    fn trims_large_pages_within_memory_limit() {
+        // 125000 pixels wide at 300 DPI, untrimmed.
+        let pdf = content_page_pdf(30000, 200, "0 0 0 rg 20000 50 1000 100 re f");
+        let outputs = Converter::new(OutputFormat::Png)
+            .sizing(Sizing::Dpi(300.0))
+            .autotrim(0.0)
+            .max_memory(64 << 20)
+            .render_bytes(pdf)
+            .unwrap();
+        let page = &outputs[0].pages[0];
+        assert!((4166..4600).contains(&page.width), "{}", page.width);
+        assert!((416..700).contains(&page.height), "{}", page.height);
+    }
+
+    #[test]
+    fn pads_map_tiles_but_not_deep_zoom_tiles() {
+        let pdf = content_page_pdf(300, 200, "0 0 1 rg 0 0 300 200 re f");
+        let tile_sizes = |format| {
+            let outputs = Converter::new(format).render_bytes(pdf.clone()).unwrap();
+            let tiles = &outputs[..outputs.len() - 1];
+            tiles
+                .iter()
+                .map(|tile| (tile.pages[0].width, tile.pages[0].height))
+                .collect::<Vec<_>>()
+        };
+        let xyz = tile_sizes(OutputFormat::Xyz { tile_size: 256 });
+        assert_eq!(xyz, vec![(256, 256); 3]);
+        let dzi = tile_sizes(OutputFormat::Dzi {
+            tile_size: 256,
+            overlap: 0,
+        });
+        assert_eq!(dzi.last(), Some(&(44, 200)));
+    }
+
+    #[test]
+    fn incremental_runs_keep_files_of_other_inputs() {
+        let dir = test_dir("incremental-inputs");
+        let (a, b) = (dir.join("a.pdf"), dir.join("b.pdf"));
+        fs::write(&a, content_page_pdf(100, 100, "0 0 1 rg 0 0 50 50 re f")).unwrap();
+        fs::write(&b, content_page_pdf(100, 100, "1 0 0 rg 0 0 50 50 re f")).unwrap();
+        let output = dir.join("out");
+        fs::create_dir(&output).unwrap();
+        let converter = Converter::new(OutputFormat::Png)
+            .prefix("doc")
+            .incremental(true);
+
+        assert_eq!(converter.convert(&a, &output).unwrap().files.len(), 1);
+        let written = fs::read(output.join("doc-1.png")).unwrap();
+        let err = converter.convert(&b, &output).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OutputExists);
+        assert_eq!(fs::read(output.join("doc-1.png")).unwrap(), written);
+
+        // An edited input still replaces its own files.
+        fs::write(&a, content_page_pdf(100, 100, "0 1 0 rg 0 0 50 50 re f")).unwrap();
+        assert_eq!(converter.convert(&a, &output).unwrap().files.len(), 1);
+        assert_ne!(fs::read(output.join("doc-1.png")).unwrap(), written);
+
+        let overwrite = converter.existing_files(ExistingFiles::Overwrite);
+        assert_eq!(overwrite.convert(&b, &output).unwrap().files.len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn single_outputs_are_counted_before_rendering() {
+        let pdf = blank_pages_pdf(&[(200, 300), (300, 200)], &[], "");
+        let converter = Converter::new(OutputFormat::Png);
+        let err = converter.render_single_bytes(pdf.clone()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PageValidation);
+
+        let output = converter
+            .pages(["2"])
+            .render_single_bytes(pdf.clone())
+            .unwrap();
+        assert_eq!(output.file_name, "rendered-2.png");
+        assert_eq!((output.pages[0].width, output.pages[0].height), (300, 200));
+
+        let tiles = Converter::new(OutputFormat::Xyz { tile_size: 256 }).pages(["1"]);
+        assert_eq!(
+            tiles.render_single_bytes(pdf).unwrap_err().kind(),
+            ErrorKind::Input
+        );
+    }
+}
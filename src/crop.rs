@@ -0,0 +1,391 @@
+use crate::render::{Background, rasterize};
+use crate::sizing::Sizing;
+use crate::tiles::PAGE_BYTES_PER_PIXEL;
+use clap::ValueEnum;
+use hayro::kurbo::{Affine, Rect};
+use hayro::vello_cpu::Pixmap;
+use hayro_interpret::InterpreterSettings;
+use hayro_interpret::hayro_syntax::object::Rect as PdfRect;
+use hayro_interpret::hayro_syntax::page::{Page, Rotation};
+use hayro_interpret::util::{RectExt, TransformExt};
+
+/// Longest side, in pixels, of the rendering trimming looks for content in. Finer renderings
+/// would only move the edges of the content by a fraction of a point.
+const TRIM_PROBE_SIZE: f32 = 2048.0;
+
+/// A page boundary defined by the PDF (`--box`).
+///
+/// Bleed, trim and art boxes that a page does not define fall back to its crop box, as the
+/// PDF specification prescribes. Every box is limited to the media box.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[value(rename_all = "lower")]
+pub enum PageBox {
+    /// The full sheet, including printer's marks.
+    Media,
+    /// The area shown by PDF viewers.
+    #[default]
+    Crop,
+    /// The printed area including bleed.
+    Bleed,
+    /// The finished page after trimming.
+    Trim,
+    /// The meaningful content, such as a figure.
+    Art,
+}
+
+impl PageBox {
+    /// Key of the box in the page dictionary.
+    fn key(self) -> &'static [u8] {
+        match self {
+            PageBox::Media => b"MediaBox",
+            PageBox::Crop => b"CropBox",
+            PageBox::Bleed => b"BleedBox",
+            PageBox::Trim => b"TrimBox",
+            PageBox::Art => b"ArtBox",
+        }
+    }
+}
+
+/// A rectangle in PDF points (`--crop X,Y,W,H`), measured from the lower-left corner of the
+/// selected page box, with `y` pointing up as in PDF user space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CropRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// A `--rotate` rule: turn pages clockwise by `angle` degrees, on top of the rotation the
+/// page defines with `/Rotate`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageRotation {
+    /// 0, 90, 180 or 270.
+    pub angle: u16,
+    /// The pages the rule applies to, in the `--page` syntax. Empty for every page.
+    pub pages: Vec<String>,
+}
+
+impl PageRotation {
+    /// Rotate every page by `angle` degrees.
+    pub fn all(angle: u16) -> Self {
+        Self {
+            angle,
+            pages: Vec::new(),
+        }
+    }
+}
+
+/// Which part of each page is rendered, and how it is oriented.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Region {
+    /// The page box to start from.
+    pub page_box: PageBox,
+    /// A rectangle within the page box to limit the output to.
+    pub crop: Option<CropRect>,
+    /// Trim the output to the bounding box of the content that stands out from the
+    /// background, keeping this margin in points around it.
+    pub autotrim: Option<f32>,
+    /// Ignore the `/Rotate` entry of pages.
+    pub ignore_rotate: bool,
+}
+
+/// The area of a page that ends up in the output, and how it is placed there.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct PageView {
+    /// Transform from PDF user space to the output in points, before scaling, with `y`
+    /// pointing down.
+    pub transform: Affine,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl PageView {
+    /// The whole page as hayro renders it: its crop box, rotated by `/Rotate`.
+    pub fn full(page: &Page) -> Self {
+        let (width, height) = page.render_dimensions();
+        Self {
+            transform: page.initial_transform(true).to_kurbo(),
+            width,
+            height,
+        }
+    }
+
+    /// The rectangle `rect` of PDF user space, turned clockwise by `degrees`.
+    fn of_rect(rect: Rect, degrees: u16) -> Self {
+        // Exact matrices, so nothing is lost to rounding in SVG transforms.
+        let rotation = match degrees {
+            90 => Affine::new([0.0, 1.0, -1.0, 0.0, 0.0, 0.0]),
+            180 => Affine::new([-1.0, 0.0, 0.0, -1.0, 0.0, 0.0]),
+            270 => Affine::new([0.0, -1.0, 1.0, 0.0, 0.0, 0.0]),
+            _ => Affine::IDENTITY,
+        };
+        let transform = rotation * Affine::FLIP_Y;
+        let bounds = transform.transform_rect_bbox(rect);
+        Self {
+            transform: Affine::translate((-bounds.x0, -bounds.y0)) * transform,
+            width: bounds.width() as f32,
+            height: bounds.height() as f32,
+        }
+    }
+
+    /// Transform from the page as hayro renders it by default to this view.
+    pub fn relative_to_full(&self, page: &Page) -> Affine {
+        self.transform * page.initial_transform(true).to_kurbo().inverse()
+    }
+}
+
+impl Region {
+    /// The view of `page` selected by the page box, the crop rectangle and trimming, turned
+    /// clockwise by `rotate` degrees.
+    ///
+    /// Trimming renders the page once onto `background`, at the scale `sizing` gives the untrimmed view but at
+    /// most [`TRIM_PROBE_SIZE`] pixels a side and `budget` bytes, and keeps the whole view when
+    /// the page is blank.
+    pub(crate) fn page_view(
+        &self,
+        page: &Page,
+        rotate: u16,
+        background: Background,
+        sizing: &Sizing,
+        budget: Option<u64>,
+        settings: &InterpreterSettings,
+    ) -> Result<PageView, String> {
+        if *self == Region::default() && rotate == 0 {
+            return Ok(PageView::full(page));
+        }
+
+        let media_box = page.media_box();
+        let mut rect = match self.page_box {
+            PageBox::Media => media_box,
+            PageBox::Crop => page.crop_box(),
+            other => page
+                .raw()
+                .get::<PdfRect>(other.key())
+                .unwrap_or_else(|| page.crop_box()),
+        }
+        .intersect(media_box);
+        let box_name = format!("{:?}", self.page_box).to_lowercase();
+        if rect.width() < 1.0 || rect.height() < 1.0 {
+            return Err(format!("The {box_name} box of the page is empty"));
+        }
+        if let Some(crop) = self.crop {
+            let x0 = rect.x0 + crop.x as f64;
+            let y0 = rect.y0 + crop.y as f64;
+            let requested = PdfRect::new(x0, y0, x0 + crop.width as f64, y0 + crop.height as f64);
+            rect = requested.intersect(rect);
+            if rect.width() < 1.0 || rect.height() < 1.0 {
+                return Err(format!(
+                    "The crop rectangle lies outside the {box_name} box of the page"
+                ));
+            }
+        }
+
+        let page_rotation = match page.rotation() {
+            _ if self.ignore_rotate => 0,
+            Rotation::None => 0,
+            Rotation::Horizontal => 90,
+            Rotation::Flipped => 180,
+            Rotation::FlippedHorizontal => 270,
+        };
+        let view = PageView::of_rect(rect.to_kurbo(), (page_rotation + rotate) % 360);
+
+        match self.autotrim {
+            Some(margin) => Ok(trim(
+                page, view, margin, background, sizing, budget, settings,
+            )),
+            None => Ok(view),
+        }
+    }
+}
+
+/// Shrink `view` to the bounding box of the content of `page` that stands out from
+/// `background`, plus `margin` points.
+///
+/// The content is found in a rendering that may be coarser than the output, so its bounds are
+/// widened by a pixel of that rendering to keep every partly covered pixel.
+fn trim(
+    page: &Page,
+    view: PageView,
+    margin: f32,
+    background: Background,
+    sizing: &Sizing,
+    budget: Option<u64>,
+    settings: &InterpreterSettings,
+) -> PageView {
+    let scale = probe_scale(&view, sizing, budget);
+    let (pixmap, scale) = rasterize(page, &view, &Sizing::Scale(scale), background, settings);
+    let Some(content) = content_bounds(&pixmap, background) else {
+        return view;
+    };
+
+    let margin = margin as f64;
+    let scale = scale as f64;
+    let bounds = Rect::new(
+        (content.x0 - 1.0) / scale - margin,
+        (content.y0 - 1.0) / scale - margin,
+        (content.x1 + 1.0) / scale + margin,
+        (content.y1 + 1.0) / scale + margin,
+    )
+    .intersect(Rect::new(0.0, 0.0, view.width as f64, view.height as f64));
+    PageView {
+        transform: Affine::translate((-bounds.x0, -bounds.y0)) * view.transform,
+        width: bounds.width() as f32,
+        height: bounds.height() as f32,
+    }
+}
+
+/// Scale of the rendering [`trim`] looks for content in: that of the output, but at most
+/// [`TRIM_PROBE_SIZE`] pixels a side and within `budget` bytes.
+fn probe_scale(view: &PageView, sizing: &Sizing, budget: Option<u64>) -> f32 {
+    let scale = sizing
+        .scale_for(view.width, view.height)
+        .min(TRIM_PROBE_SIZE / view.width.max(view.height));
+    match budget {
+        Some(budget) => {
+            let pixels = budget as f32 / PAGE_BYTES_PER_PIXEL as f32;
+            scale.min((pixels / (view.width * view.height)).sqrt())
+        }
+        None => scale,
+    }
+}
+
+/// Bounding box, in pixels, of the pixels of `pixmap`, rendered onto `background`, that
+/// differ from it: those of another colour, or any painted pixel on a transparent
+/// background. `None` for a blank pixmap.
+fn content_bounds(pixmap: &Pixmap, background: Background) -> Option<Rect> {
+    // Anti-aliasing leaves faint pixels around every edge; ignore those nearly the background.
+    const TOLERANCE: u8 = 8;
+    let width = pixmap.width() as usize;
+    let (mut x0, mut y0, mut x1, mut y1) = (usize::MAX, usize::MAX, 0, 0);
+    for (i, px) in pixmap.data_as_u8_slice().chunks_exact(4).enumerate() {
+        let ink = match background {
+            Background::Color(color) => (0..3).map(|c| px[c].abs_diff(color[c])).max().unwrap_or(0),
+            Background::Transparent => px[3],
+        };
+        if ink > TOLERANCE {
+            let (x, y) = (i % width, i / width);
+            x0 = x0.min(x);
+            y0 = y0.min(y);
+            x1 = x1.max(x + 1);
+            y1 = y1.max(y + 1);
+        }
+    }
+    (x0 < x1).then(|| Rect::new(x0 as f64, y0 as f64, x1 as f64, y1 as f64))
+}
+
+/// Parse a `X,Y,W,H` rectangle in points, such as `72,72,200,100`.
+pub fn parse_crop(s: &str) -> Result<CropRect, String> {
+    let invalid = || format!("invalid crop '{s}': expected X,Y,WIDTH,HEIGHT in points");
+    let values = s
+        .split(',')
+        .map(|part| part.trim().parse::<f32>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let [x, y, width, height] = values[..] else {
+        return Err(invalid());
+    };
+    if !values.iter().all(|v| v.is_finite()) || width <= 0.0 || height <= 0.0 {
+        return Err(invalid());
+    }
+    Ok(CropRect {
+        x,
+        y,
+        width,
+        height,
+    })
+}
+
+/// Parse a `--rotate` rule: an angle, optionally followed by `:` and the pages it applies to
+/// in the `--page` syntax, such as `90` or `270:2-4,!3`.
+pub fn parse_rotation(s: &str) -> Result<PageRotation, String> {
+    let (angle, pages) = match s.split_once(':') {
+        Some((angle, pages)) => (angle, pages.split(',').map(str::to_string).collect()),
+        None => (s, Vec::new()),
+    };
+    let angle = match angle.trim().parse::<i32>() {
+        Ok(angle) if angle % 90 == 0 => angle.rem_euclid(360) as u16,
+        _ => {
+            return Err(format!(
+                "invalid rotation '{s}': expected 90, 180 or 270, optionally followed by \
+                 :PAGES (e.g. 90:2-4)"
+            ));
+        }
+    };
+    Ok(PageRotation { angle, pages })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::content_page_pdf;
+    use hayro::hayro_syntax::Pdf;
+
+    /// A page 30000 points wide, more than a pixmap can hold at 300 DPI, with a 1000 x 100
+    /// point rectangle at (20000, 50).
+    fn wide_page() -> Pdf {
+        let pdf = content_page_pdf(30000, 200, "0 0 0 rg 20000 50 1000 100 re f");
+        Pdf::new(pdf).unwrap()
+    }
+
+    fn trimmed(pdf: &Pdf, background: Background, budget: Option<u64>) -> PageView {
+        let region = Region {
+            autotrim: Some(0.0),
+            ..Region::default()
+        };
+        let settings = InterpreterSettings::default();
+        region
+            .page_view(
+                &pdf.pages()[0],
+                0,
+                background,
+                &Sizing::Dpi(300.0),
+                budget,
+                &settings,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn trims_pages_wider_than_a_pixmap() {
+        let view = trimmed(&wide_page(), Background::WHITE, None);
+        // A pixel of the 2048 pixel wide probe is about 15 points.
+        assert!((1000.0..1060.0).contains(&view.width), "{view:?}");
+        assert!((100.0..160.0).contains(&view.height), "{view:?}");
+        let corner = view.transform * hayro::kurbo::Point::new(20000.0, 150.0);
+        assert!((0.0..30.0).contains(&corner.x) && (0.0..30.0).contains(&corner.y));
+    }
+
+    #[test]
+    fn trim_probe_fits_in_memory_budget() {
+        let view = PageView::of_rect(Rect::new(0.0, 0.0, 30000.0, 200.0), 0);
+        let budget = 1 << 20;
+        let scale = probe_scale(&view, &Sizing::Dpi(300.0), Some(budget));
+        let pixels = (view.width * scale) as u64 * (view.height * scale) as u64;
+        assert!(pixels * PAGE_BYTES_PER_PIXEL <= budget);
+
+        let view = trimmed(&wide_page(), Background::WHITE, Some(budget));
+        assert!((1000.0..1100.0).contains(&view.width), "{view:?}");
+    }
+
+    #[test]
+    fn trims_against_the_background() {
+        // A yellow page with a white 100 x 50 point rectangle at (50, 100).
+        let content = "1 1 0 rg 0 0 400 300 re f 1 1 1 rg 50 100 100 50 re f";
+        let pdf = Pdf::new(content_page_pdf(400, 300, content)).unwrap();
+
+        let view = trimmed(&pdf, Background::Color([255, 255, 0]), None);
+        assert!((100.0..102.0).contains(&view.width), "{view:?}");
+        assert!((50.0..52.0).contains(&view.height), "{view:?}");
+
+        // On white, the yellow stands out and nothing is trimmed.
+        let view = trimmed(&pdf, Background::WHITE, None);
+        assert_eq!((view.width, view.height), (400.0, 300.0));
+
+        // On a transparent background, everything painted counts, white included.
+        let pdf = Pdf::new(content_page_pdf(400, 300, "1 1 1 rg 50 100 100 50 re f")).unwrap();
+        let view = trimmed(&pdf, Background::Transparent, None);
+        assert!((100.0..102.0).contains(&view.width), "{view:?}");
+        let view = trimmed(&pdf, Background::WHITE, None);
+        assert_eq!((view.width, view.height), (400.0, 300.0));
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+
+/// The category of an [`Error`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Reading an input or writing an output failed.
+    FileSystem,
+    /// The input is not a PDF file.
+    FileType,
+    /// The PDF could not be parsed.
+    Pdf,
+    /// The PDF is encrypted and no valid password was given, or its encryption scheme is
+    /// not supported.
+    Encrypted,
+    /// The page selection does not match the document.
+    PageValidation,
+    /// An output file already exists and may not be replaced.
+    OutputExists,
+    /// The output name template is invalid for the document.
+    NameTemplate,
+    /// The page box, crop rectangle or trimming selects nothing.
+    Crop,
+    /// A page is too large to render within the memory limit, and the output format
+    /// cannot be written in tiles.
+    Memory,
+    /// A rendered page could not be encoded.
+    Encode,
+    /// The input arguments do not name any usable document.
+    Input,
+    /// The worker threads could not be started.
+    Threads,
+    /// One or more documents of a batch failed.
+    Batch,
+}
+
+impl ErrorKind {
+    /// Short tag used when logging errors of this kind.
+    pub fn tag(&self) -> &'static str {
+        match self {
+            ErrorKind::FileSystem => "FileSystem",
+            ErrorKind::FileType => "FileType",
+            ErrorKind::Pdf => "PDF",
+            ErrorKind::Encrypted => "Encrypted",
+            ErrorKind::PageValidation => "PageValidation",
+            ErrorKind::OutputExists => "OutputExists",
+            ErrorKind::NameTemplate => "NameTemplate",
+            ErrorKind::Crop => "Crop",
+            ErrorKind::Memory => "Memory",
+            ErrorKind::Encode => "Encode",
+            ErrorKind::Input => "Input",
+            ErrorKind::Threads => "Threads",
+            ErrorKind::Batch => "Batch",
+        }
+    }
+}
+
+/// An error raised while converting a document.
+pub struct Error {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl Error {
+    /// Create an error of the given kind with a human-readable message.
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// The category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The human-readable message, without the kind tag.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Error")
+            .field("kind", &self.kind)
+            .field("message", &self.message)
+            .finish()
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,331 @@
+use hayro::kurbo::{BezPath, Rect};
+use hayro_interpret::font::GlyphRun;
+use hayro_interpret::hayro_syntax::object::{Array, Dict, Name};
+use hayro_interpret::hayro_syntax::page::Page;
+use hayro_interpret::{
+    BlendMode, ClipPath, Context, Device, DrawMode, DrawProps, Image, ImageData, ImageDrawProps,
+    InterpreterCache, InterpreterSettings, LumaData, SoftMask, TransformExt, interpret_page,
+};
+use png::ColorType;
+use std::collections::HashSet;
+
+/// An image embedded in a page, extracted into a file of its own.
+pub(crate) struct EmbeddedImage {
+    pub file_name: String,
+    pub width: u32,
+    pub height: u32,
+    /// The file contents, empty when only the names were asked for.
+    pub bytes: Vec<u8>,
+}
+
+/// The raster images `page` draws, each once, in the order they are first drawn.
+///
+/// They are named after the page output `name`: `doc-3.png` gives `doc-3-obj12.jpg` for
+/// image object 12 and `doc-3-inline1.png` for the first inline image. JPEG and JPEG 2000
+/// streams are copied unchanged; other images are decoded to PNG, with their soft mask as
+/// alpha. Image masks are left out, since they only give a shape to paint.
+///
+/// With `decode` false only the names and sizes are worked out, which is cheap.
+pub(crate) fn page_images(
+    page: &Page,
+    name: &str,
+    settings: &InterpreterSettings,
+    decode: bool,
+) -> Result<Vec<EmbeddedImage>, String> {
+    let cache = InterpreterCache::new();
+    let (width, height) = page.render_dimensions();
+    let mut context = Context::new(
+        page.initial_transform(true).to_kurbo(),
+        Rect::new(0.0, 0.0, width as f64, height as f64),
+        &cache,
+        page.xref(),
+        settings.clone(),
+    );
+    let mut device = ImageDevice {
+        stem: name.rsplit_once('.').map_or(name, |(stem, _)| stem),
+        decode,
+        objects: HashSet::new(),
+        inline: 0,
+        images: Vec::new(),
+        error: None,
+    };
+    interpret_page(page, &mut context, &mut device);
+    match device.error {
+        Some(msg) => Err(msg),
+        None => Ok(device.images),
+    }
+}
+
+/// A device that only records the images drawn on a page.
+struct ImageDevice<'n> {
+    stem: &'n str,
+    decode: bool,
+    /// Numbers of the image objects already recorded, since pages often draw an image more
+    /// than once.
+    objects: HashSet<i32>,
+    /// Inline images seen so far.
+    inline: usize,
+    images: Vec<EmbeddedImage>,
+    /// The first image that could not be decoded.
+    error: Option<String>,
+}
+
+impl<'a> Device<'a> for ImageDevice<'_> {
+    fn draw_path(&mut self, _: &BezPath, _: DrawProps<'a>, _: &DrawMode) {}
+
+    fn push_clip_path(&mut self, _: &ClipPath) {}
+
+    fn push_transparency_group(&mut self, _: f32, _: Option<SoftMask<'a>>, _: BlendMode) {}
+
+    fn draw_glyph_run(&mut self, _: &GlyphRun<'_, 'a>, _: DrawProps<'a>, _: &DrawMode) {}
+
+    fn draw_image(&mut self, image: Image<'a, '_>, _: ImageDrawProps<'a>) {
+        let Image::Raster(raster) = image else {
+            return;
+        };
+        if self.error.is_some() {
+            return;
+        }
+        let stream = raster.stream();
+        // Inline images are not objects of their own.
+        let object = stream.obj_id().obj_number;
+        let label = if object > 0 {
+            if !self.objects.insert(object) {
+                return;
+            }
+            format!("obj{object}")
+        } else {
+            self.inline += 1;
+            format!("inline{}", self.inline)
+        };
+        let extension = native_extension(stream.dict()).unwrap_or("png");
+
+        let (mut width, mut height) = (raster.width(), raster.height());
+        let bytes = if !self.decode {
+            Vec::new()
+        } else if extension != "png" {
+            stream.raw_data().into_owned()
+        } else {
+            let mut encoded = None;
+            raster.with_rgba(
+                |data, alpha| {
+                    (width, height) = (data.width(), data.height());
+                    encoded = Some(encode_png(data, alpha));
+                },
+                None,
+            );
+            match encoded {
+                Some(Ok(bytes)) => bytes,
+                Some(Err(msg)) => {
+                    self.error = Some(msg);
+                    return;
+                }
+                None => {
+                    self.error = Some(format!("Failed to decode image {label}"));
+                    return;
+                }
+            }
+        };
+        self.images.push(EmbeddedImage {
+            file_name: format!("{}-{label}.{extension}", self.stem),
+            width,
+            height,
+            bytes,
+        });
+    }
+
+    fn pop_clip(&mut self) {}
+
+    fn pop_transparency_group(&mut self) {}
+}
+
+/// Extension of the file format an image stream is stored in, when it is a format of its
+/// own: a JPEG or JPEG 2000 stream without further filters.
+fn native_extension(dict: &Dict) -> Option<&'static str> {
+    // Inline images abbreviate their keys and filter names.
+    let filter = dict
+        .get::<Name>(b"Filter")
+        .or_else(|| dict.get::<Name>(b"F"));
+    let filter = filter.or_else(|| {
+        let filters = dict
+            .get::<Array>(b"Filter")
+            .or_else(|| dict.get::<Array>(b"F"))?;
+        let mut filters = filters.iter::<Name>();
+        let filter = filters.next()?;
+        filters.next().is_none().then_some(filter)
+    })?;
+    match filter.as_ref() {
+        b"DCTDecode" | b"DCT" => Some("jpg"),
+        b"JPXDecode" => Some("jp2"),
+        _ => None,
+    }
+}
+
+/// Encode decoded image samples as PNG, with `alpha` as a transparency channel.
+fn encode_png(data: ImageData, alpha: Option<LumaData>) -> Result<Vec<u8>, String> {
+    let (width, height) = (data.width(), data.height());
+    let (samples, channels) = match data {
+        ImageData::Rgb(rgb) => (rgb.data, 3),
+        ImageData::Luma(luma) => (luma.data, 1),
+    };
+    let (samples, color) = match (alpha, channels) {
+        (None, 3) => (samples, ColorType::Rgb),
+        (None, _) => (samples, ColorType::Grayscale),
+        (Some(alpha), _) => {
+            let alpha = alpha_samples(&alpha, width, height);
+            let samples = samples
+                .chunks_exact(channels)
+                .zip(alpha)
+                .flat_map(|(px, a)| px.iter().copied().chain([a]))
+                .collect();
+            let color = if channels == 3 {
+                ColorType::Rgba
+            } else {
+                ColorType::GrayscaleAlpha
+            };
+            (samples, color)
+        }
+    };
+
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(color);
+    encoder
+        .write_header()
+        .and_then(|mut writer| {
+            writer.write_image_data(&samples)?;
+            writer.finish()
+        })
+        .map_err(|e| format!("Failed to encode PNG: {e}"))?;
+    Ok(out)
+}
+
+/// The samples of a soft mask at `width` x `height`, taking the nearest sample when the
+/// mask has another resolution than the image.
+fn alpha_samples(alpha: &LumaData, width: u32, height: u32) -> Vec<u8> {
+    if (alpha.width, alpha.height) == (width, height) {
+        return alpha.data.clone();
+    }
+    let sample = |v: u32, size: u32, mask_size: u32| {
+        ((v as u64 * mask_size as u64 / size as u64) as u32).min(mask_size.saturating_sub(1))
+    };
+    (0..height)
+        .flat_map(|y| {
+            let row = sample(y, height, alpha.height) as usize * alpha.width as usize;
+            (0..width).map(move |x| {
+                let x = sample(x, width, alpha.width) as usize;
+                alpha.data.get(row + x).copied().unwrap_or(255)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converter::load_pdf;
+    use crate::testing::build_pdf;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{ExtendedColorType, ImageEncoder};
+
+    /// A stream object of `dict` entries holding `data`.
+    fn stream(dict: &str, data: &[u8]) -> Vec<u8> {
+        let mut object = format!("<< {dict} /Length {} >>\nstream\n", data.len()).into_bytes();
+        object.extend_from_slice(data);
+        object.extend_from_slice(b"\nendstream");
+        object
+    }
+
+    /// `data` in a zlib stream of one stored block, as a `FlateDecode` filter reads it.
+    fn zlib_stored(data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u16;
+        let mut out = vec![0x78, 0x01, 0x01];
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(data);
+        let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+            let a = (a + byte as u32) % 65521;
+            (a, (b + a) % 65521)
+        });
+        out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+        out
+    }
+
+    /// A page that draws a JPEG image (object 5) twice, a Flate-compressed grey image
+    /// (object 6) and an inline image.
+    fn fixture(jpeg: &[u8]) -> Vec<u8> {
+        let content = "q 40 0 0 40 0 0 cm /Im1 Do Q q 40 0 0 40 50 0 cm /Im2 Do Q \
+                       q 40 0 0 40 0 50 cm /Im1 Do Q \
+                       q 10 0 0 10 50 50 cm BI /W 1 /H 1 /CS /G /BPC 8 ID A EI Q";
+        let objects = [
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+            b"<< /Type /Page /Parent 2 0 R /MediaBox [0 0 100 100] /Contents 4 0 R \
+              /Resources << /XObject << /Im1 5 0 R /Im2 6 0 R >> >> >>"
+                .to_vec(),
+            stream("", content.as_bytes()),
+            stream(
+                "/Type /XObject /Subtype /Image /Width 4 /Height 4 /ColorSpace /DeviceRGB \
+                 /BitsPerComponent 8 /Filter /DCTDecode",
+                jpeg,
+            ),
+            stream(
+                "/Type /XObject /Subtype /Image /Width 2 /Height 2 /ColorSpace /DeviceGray \
+                 /BitsPerComponent 8 /Filter /FlateDecode",
+                &zlib_stored(&[0, 255, 255, 0]),
+            ),
+        ];
+        build_pdf(&objects, "")
+    }
+
+    fn jpeg() -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 90)
+            .write_image(&[200; 4 * 4 * 3], 4, 4, ExtendedColorType::Rgb8)
+            .unwrap();
+        jpeg
+    }
+
+    fn images(pdf: Vec<u8>, decode: bool) -> Vec<EmbeddedImage> {
+        let pdf = load_pdf(pdf, None).unwrap();
+        let settings = InterpreterSettings::default();
+        page_images(&pdf.pages()[0], "doc-1.png", &settings, decode).unwrap()
+    }
+
+    #[test]
+    fn extracts_each_image_once_named_after_its_object() {
+        let jpeg = jpeg();
+        let images = images(fixture(&jpeg), true);
+        let names: Vec<_> = images
+            .iter()
+            .map(|image| image.file_name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            ["doc-1-obj5.jpg", "doc-1-obj6.png", "doc-1-inline1.png"]
+        );
+
+        // JPEG streams are copied byte for byte.
+        assert_eq!(images[0].bytes, jpeg);
+        assert_eq!((images[0].width, images[0].height), (4, 4));
+
+        let png = image::load_from_memory(&images[1].bytes).unwrap();
+        assert_eq!((png.width(), png.height()), (2, 2));
+        assert_eq!(png.to_luma8().into_raw(), [0, 255, 255, 0]);
+        assert_eq!(
+            image::load_from_memory(&images[2].bytes)
+                .unwrap()
+                .to_luma8()
+                .into_raw(),
+            [b'A']
+        );
+    }
+
+    #[test]
+    fn names_images_without_decoding_them() {
+        let images = images(fixture(&jpeg()), false);
+        assert_eq!(images.len(), 3);
+        assert!(images.iter().all(|image| image.bytes.is_empty()));
+        assert_eq!((images[1].width, images[1].height), (2, 2));
+    }
+}
@@ -0,0 +1,571 @@
+use crate::converter::{load_pdf, read_input};
+use crate::error::Error;
+use crate::labels::{decode_text_string, page_labels};
+use crate::trailer::Trailer;
+use hayro::hayro_syntax::Pdf;
+use hayro::hayro_syntax::PdfVersion;
+use hayro::hayro_syntax::object::{
+    Dict, Name, ObjectIdentifier, Rect, Stream, String as PdfString,
+};
+use hayro::hayro_syntax::page::Rotation;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// What a PDF says about itself and its pages, as reported by [`inspect`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DocumentInfo {
+    pub page_count: usize,
+    /// PDF version from the file header, such as `1.7`.
+    pub version: String,
+    pub encrypted: bool,
+    /// Entries of the document information dictionary, such as `Title` and `Producer`. Text
+    /// is decoded, and dates are given in ISO 8601 when they can be read.
+    pub info: BTreeMap<String, String>,
+    /// Simple properties of the XMP metadata stream by qualified name, such as `dc:title`.
+    /// The items of lists and alternatives are joined with `; `.
+    pub xmp: BTreeMap<String, String>,
+    pub pages: Vec<PageDetails>,
+}
+
+/// The boxes and rotation of a page, in points.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PageDetails {
+    /// 1-based page number.
+    pub page: usize,
+    /// Page label, the number printed on the page, such as `iv`.
+    pub label: String,
+    /// Size of the page as it is rendered: its visible area, turned by `rotate`.
+    pub width: f32,
+    pub height: f32,
+    /// Boxes as `[x0, y0, x1, y1]` in PDF user space, with `y` pointing up.
+    pub media_box: [f32; 4],
+    pub crop_box: [f32; 4],
+    /// Clockwise rotation the page is shown with, in degrees.
+    pub rotate: u16,
+}
+
+/// Read the page count, version, metadata, encryption status and page boxes of the PDF at
+/// `input`, decrypting it with `password` when it is encrypted.
+pub fn inspect(input: &Path, password: Option<&str>) -> Result<DocumentInfo, Error> {
+    inspect_bytes(read_input(input)?, password)
+}
+
+/// Like [`inspect`], for a PDF held in memory.
+pub fn inspect_bytes(data: Vec<u8>, password: Option<&str>) -> Result<DocumentInfo, Error> {
+    let pdf = load_pdf(data, password)?;
+    let trailer = Trailer::read(pdf.data().as_ref());
+    let labels = page_labels(&pdf);
+
+    let pages = pdf
+        .pages()
+        .iter()
+        .zip(labels)
+        .enumerate()
+        .map(|(idx, (page, label))| {
+            let (width, height) = page.render_dimensions();
+            PageDetails {
+                page: idx + 1,
+                label,
+                width: round(width as f64),
+                height: round(height as f64),
+                media_box: rect(page.media_box()),
+                crop_box: rect(page.crop_box()),
+                rotate: match page.rotation() {
+                    Rotation::None => 0,
+                    Rotation::Horizontal => 90,
+                    Rotation::Flipped => 180,
+                    Rotation::FlippedHorizontal => 270,
+                },
+            }
+        })
+        .collect();
+
+    Ok(DocumentInfo {
+        page_count: pdf.pages().len(),
+        version: version_string(pdf.version()).to_string(),
+        encrypted: trailer
+            .as_ref()
+            .is_some_and(|trailer| trailer.with_key(b"Encrypt").is_some()),
+        info: info_entries(&pdf, trailer.and_then(|trailer| trailer.reference(b"Info"))),
+        xmp: xmp_metadata(&pdf),
+        pages,
+    })
+}
+
+impl fmt::Display for DocumentInfo {
+    /// A human-readable report: the document properties, then a table of the pages.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Pages:        {}", self.page_count)?;
+        writeln!(f, "PDF version:  {}", self.version)?;
+        writeln!(
+            f,
+            "Encrypted:    {}",
+            if self.encrypted { "yes" } else { "no" }
+        )?;
+        for (title, entries) in [("Info", &self.info), ("XMP", &self.xmp)] {
+            if entries.is_empty() {
+                continue;
+            }
+            writeln!(f, "{title}:")?;
+            let width = entries
+                .keys()
+                .map(|key| key.chars().count())
+                .max()
+                .unwrap_or(0);
+            for (key, value) in entries {
+                // Keep multi-line values such as keywords on one line of the report.
+                let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+                writeln!(
+                    f,
+                    "  {:<width$}  {value}",
+                    format!("{key}:"),
+                    width = width + 1
+                )?;
+            }
+        }
+
+        let rows: Vec<[String; 6]> = self
+            .pages
+            .iter()
+            .map(|page| {
+                [
+                    page.page.to_string(),
+                    page.label.clone(),
+                    format!("{} x {}", page.width, page.height),
+                    format_box(page.media_box),
+                    format_box(page.crop_box),
+                    page.rotate.to_string(),
+                ]
+            })
+            .collect();
+        let header = [
+            "Page",
+            "Label",
+            "Size (pt)",
+            "Media box",
+            "Crop box",
+            "Rotate",
+        ];
+        let widths: Vec<usize> = (0..header.len())
+            .map(|col| {
+                rows.iter()
+                    .map(|row| row[col].chars().count())
+                    .chain([header[col].len()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        for row in std::iter::once(header.map(String::from)).chain(rows) {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, &width)| format!("{cell:<width$}"))
+                .collect::<Vec<_>>()
+                .join("  ");
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// The entries of the information dictionary `id`, or the standard ones hayro found when
+/// the trailer could not be read.
+fn info_entries(pdf: &Pdf, id: Option<ObjectIdentifier>) -> BTreeMap<String, String> {
+    let Some(dict) = id.and_then(|id| pdf.xref().get::<Dict>(id)) else {
+        let metadata = pdf.metadata();
+        let text = [
+            ("Title", &metadata.title),
+            ("Author", &metadata.author),
+            ("Subject", &metadata.subject),
+            ("Keywords", &metadata.keywords),
+            ("Creator", &metadata.creator),
+            ("Producer", &metadata.producer),
+        ];
+        let dates = [
+            ("CreationDate", &metadata.creation_date),
+            ("ModDate", &metadata.modification_date),
+        ];
+        return text
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.to_string(), decode_text_string(value.as_ref()?))))
+            .chain(dates.into_iter().filter_map(|(key, date)| {
+                let date = date.as_ref()?;
+                let offset = if date.utc_offset_hour == 0 && date.utc_offset_minute == 0 {
+                    "Z".to_string()
+                } else {
+                    format!("{:+03}:{:02}", date.utc_offset_hour, date.utc_offset_minute)
+                };
+                let iso = format!(
+                    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{offset}",
+                    date.year, date.month, date.day, date.hour, date.minute, date.second
+                );
+                Some((key.to_string(), iso))
+            }))
+            .collect();
+    };
+
+    dict.keys()
+        .filter_map(|key| {
+            let value = if let Some(text) = dict.get::<PdfString>(key.as_ref()) {
+                let text = decode_text_string(text.as_bytes());
+                iso_date(&text).unwrap_or(text)
+            } else if let Some(name) = dict.get::<Name>(key.as_ref()) {
+                String::from_utf8_lossy(name.as_ref()).into_owned()
+            } else if let Some(number) = dict.get::<f64>(key.as_ref()) {
+                number.to_string()
+            } else {
+                dict.get::<bool>(key.as_ref())?.to_string()
+            };
+            Some((String::from_utf8_lossy(key.as_ref()).into_owned(), value))
+        })
+        .collect()
+}
+
+/// A PDF date such as `D:20240131143000+01'00'` in ISO 8601: `2024-01-31T14:30:00+01:00`.
+/// Fields left out at the end take their earliest value, as the PDF specification says; a
+/// date without a time zone is given without one.
+fn iso_date(text: &str) -> Option<String> {
+    let text = text.strip_prefix("D:")?;
+    let digits = text.bytes().take_while(u8::is_ascii_digit).count();
+    if digits < 4 || digits % 2 != 0 || digits > 14 {
+        return None;
+    }
+    let field = |start: usize, default| {
+        if start + 2 <= digits {
+            &text[start..start + 2]
+        } else {
+            default
+        }
+    };
+    let date = format!(
+        "{}-{}-{}T{}:{}:{}",
+        &text[..4],
+        field(4, "01"),
+        field(6, "01"),
+        field(8, "00"),
+        field(10, "00"),
+        field(12, "00")
+    );
+    let zone = &text[digits..];
+    let offset = match zone.as_bytes().first() {
+        None => String::new(),
+        Some(b'Z') => "Z".to_string(),
+        Some(sign @ (b'+' | b'-')) => {
+            let parts: Vec<&str> = zone[1..]
+                .split('\'')
+                .filter(|part| !part.is_empty())
+                .collect();
+            let (hours, minutes) = match parts.as_slice() {
+                [hours] => (*hours, "00"),
+                [hours, minutes] => (*hours, *minutes),
+                _ => return None,
+            };
+            let valid = |part: &str| part.len() == 2 && part.bytes().all(|b| b.is_ascii_digit());
+            if !valid(hours) || !valid(minutes) {
+                return None;
+            }
+            format!("{}{hours}:{minutes}", *sign as char)
+        }
+        Some(_) => return None,
+    };
+    Some(date + &offset)
+}
+
+/// The properties of the XMP packet the document catalog points to.
+fn xmp_metadata(pdf: &Pdf) -> BTreeMap<String, String> {
+    let xref = pdf.xref();
+    xref.get::<Dict>(xref.root_id())
+        .and_then(|catalog| catalog.get::<Stream>(b"Metadata"))
+        .and_then(|stream| stream.decoded().ok())
+        .map(|xml| xmp_properties(&String::from_utf8_lossy(&xml)))
+        .unwrap_or_default()
+}
+
+/// The simple properties of an XMP packet: those given as attributes of a top-level
+/// `rdf:Description`, and its child elements that hold text, directly or in `rdf:li`
+/// items. Structured properties, which only hold further descriptions, are left out.
+///
+/// This reads just enough XML for XMP as PDF writers produce it; it does not resolve
+/// namespaces, so properties keep the prefixes the packet uses.
+fn xmp_properties(xml: &str) -> BTreeMap<String, String> {
+    let mut properties = BTreeMap::new();
+    let mut open: Vec<&str> = Vec::new();
+    // The property element being read: its name, how many elements enclose it, and the
+    // pieces of text found in it so far.
+    let mut current: Option<(&str, usize, Vec<String>)> = None;
+
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        if let Some((_, _, values)) = &mut current {
+            push_text(values, &rest[..start]);
+        }
+        rest = &rest[start..];
+
+        let (skip, close) = if rest.starts_with("<?") {
+            (0, "?>")
+        } else if rest.starts_with("<!--") {
+            (0, "-->")
+        } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").unwrap_or(cdata.len());
+            if let Some((_, _, values)) = &mut current {
+                let text = cdata[..end].trim();
+                if !text.is_empty() {
+                    values.push(text.to_string());
+                }
+            }
+            ("<![CDATA[".len() + end, "]]>")
+        } else if rest.starts_with("<!") {
+            (0, ">")
+        } else {
+            let Some(end) = rest.find('>') else {
+                break;
+            };
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+
+            if tag.starts_with('/') {
+                open.pop();
+                if let Some((name, _, values)) =
+                    current.take_if(|(_, depth, _)| *depth == open.len())
+                    && !values.is_empty()
+                {
+                    properties.insert(name.to_string(), values.join("; "));
+                }
+                continue;
+            }
+
+            let empty = tag.ends_with('/');
+            let tag = tag.trim_end_matches('/');
+            let (name, attributes) = tag
+                .split_once(|c: char| c.is_ascii_whitespace())
+                .unwrap_or((tag, ""));
+            let top_level = |open: &[&str]| matches!(open, [.., "rdf:RDF", "rdf:Description"]);
+
+            if name == "rdf:Description" && open.last() == Some(&"rdf:RDF") {
+                for (key, value) in parse_attributes(attributes) {
+                    if !["rdf:", "xmlns", "xml:"].iter().any(|p| key.starts_with(p)) {
+                        properties.insert(key.to_string(), unescape(value));
+                    }
+                }
+            } else if current.is_none() && top_level(&open) {
+                let mut values = Vec::new();
+                if let Some((_, resource)) = parse_attributes(attributes)
+                    .into_iter()
+                    .find(|(key, _)| *key == "rdf:resource")
+                {
+                    values.push(unescape(resource));
+                }
+                if !empty {
+                    current = Some((name, open.len(), values));
+                } else if !values.is_empty() {
+                    properties.insert(name.to_string(), values.join("; "));
+                }
+            }
+            if !empty {
+                open.push(name);
+            }
+            continue;
+        };
+
+        let Some(end) = rest[skip..].find(close) else {
+            break;
+        };
+        rest = &rest[skip + end + close.len()..];
+    }
+    properties
+}
+
+/// Add the text between two tags to `values`, unless it is only whitespace.
+fn push_text(values: &mut Vec<String>, text: &str) {
+    let text = text.trim();
+    if !text.is_empty() {
+        values.push(unescape(text));
+    }
+}
+
+/// The `name="value"` pairs of a start tag, with either kind of quotes.
+fn parse_attributes(mut attributes: &str) -> Vec<(&str, &str)> {
+    let mut pairs = Vec::new();
+    while let Some((name, rest)) = attributes.split_once('=') {
+        let rest = rest.trim_start();
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            break;
+        };
+        let Some(end) = rest[1..].find(quote) else {
+            break;
+        };
+        pairs.push((name.trim(), &rest[1..1 + end]));
+        attributes = &rest[end + 2..];
+    }
+    pairs
+}
+
+/// Replace the predefined XML entities and character references in `text`.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+        let c = entity.and_then(|entity| match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => {
+                let code = match entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#')?.parse().ok(),
+                };
+                char::from_u32(code?)
+            }
+        });
+        match (c, entity) {
+            (Some(c), Some(entity)) => {
+                out.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn version_string(version: PdfVersion) -> &'static str {
+    match version {
+        PdfVersion::Pdf10 => "1.0",
+        PdfVersion::Pdf11 => "1.1",
+        PdfVersion::Pdf12 => "1.2",
+        PdfVersion::Pdf13 => "1.3",
+        PdfVersion::Pdf14 => "1.4",
+        PdfVersion::Pdf15 => "1.5",
+        PdfVersion::Pdf16 => "1.6",
+        PdfVersion::Pdf17 => "1.7",
+        PdfVersion::Pdf20 => "2.0",
+    }
+}
+
+fn rect(rect: Rect) -> [f32; 4] {
+    [rect.x0, rect.y0, rect.x1, rect.y1].map(round)
+}
+
+fn format_box([x0, y0, x1, y1]: [f32; 4]) -> String {
+    format!("{x0} {y0} {x1} {y1}")
+}
+
+/// Round a coordinate or size to a hundredth of a point.
+fn round(value: f64) -> f32 {
+    ((value * 100.0).round() / 100.0) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::blank_pages_pdf;
+
+    #[test]
+    fn reads_info_and_encryption_from_trailer_only() {
+        // A string and a dictionary that look like trailer entries.
+        let decoy = "<< /Note (/Encrypt 9 0 R) /Info 6 0 R >>".to_string();
+        let info = "<< /Title (Real) >>".to_string();
+        let other = "<< /Title (Decoy) >>".to_string();
+        let pdf = blank_pages_pdf(&[(200, 300)], &[decoy, info, other], "/Info 5 0 R");
+        let info = inspect_bytes(pdf, None).unwrap();
+        assert!(!info.encrypted);
+        assert_eq!(info.info.get("Title").map(String::as_str), Some("Real"));
+    }
+
+    #[test]
+    fn reads_trailer_of_cross_reference_stream() {
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>",
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 100 100] >>",
+            "<< /Title (Streamed) >>",
+        ];
+        let mut pdf = b"%PDF-1.5\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n{object}\nendobj\n", i + 1).as_bytes());
+        }
+        let xref = pdf.len();
+        offsets.push(xref);
+        let mut entries = vec![0, 0, 0, 0];
+        for offset in &offsets {
+            entries.extend([1, (offset >> 8) as u8, *offset as u8, 0]);
+        }
+        pdf.extend_from_slice(
+            format!(
+                "5 0 obj\n<< /Type /XRef /Size 6 /W [1 2 1] /Root 1 0 R /Info 4 0 R /Length {} >>\nstream\n",
+                entries.len()
+            )
+            .as_bytes(),
+        );
+        pdf.extend_from_slice(&entries);
+        pdf.extend_from_slice(
+            format!("\nendstream\nendobj\nstartxref\n{xref}\n%%EOF\n").as_bytes(),
+        );
+
+        let info = inspect_bytes(pdf, None).unwrap();
+        assert_eq!(info.version, "1.5");
+        assert_eq!(info.info.get("Title").map(String::as_str), Some("Streamed"));
+    }
+
+    #[test]
+    fn reports_page_boxes_and_rotation() {
+        let pdf = blank_pages_pdf(&[(200, 300), (400, 100)], &[], "");
+        let info = inspect_bytes(pdf, None).unwrap();
+        assert_eq!(info.page_count, 2);
+        assert_eq!(info.pages[1].media_box, [0.0, 0.0, 400.0, 100.0]);
+        assert_eq!(info.pages[1].rotate, 0);
+        assert_eq!((info.pages[1].width, info.pages[1].height), (400.0, 100.0));
+        assert_eq!(info.pages[0].label, "1");
+    }
+
+    #[test]
+    fn converts_pdf_dates() {
+        assert_eq!(
+            iso_date("D:20240131143000+01'00'").as_deref(),
+            Some("2024-01-31T14:30:00+01:00")
+        );
+        assert_eq!(iso_date("D:2023").as_deref(), Some("2023-01-01T00:00:00"));
+        assert_eq!(
+            iso_date("D:19991231235959Z").as_deref(),
+            Some("1999-12-31T23:59:59Z")
+        );
+        assert_eq!(iso_date("D:202"), None);
+        assert_eq!(iso_date("yesterday"), None);
+    }
+
+    #[test]
+    fn reads_xmp_properties() {
+        let xml = r#"<x:xmpmeta><rdf:RDF>
+            <rdf:Description pdf:Producer="A &amp; B" xmlns:pdf="ns">
+              <dc:title><rdf:Alt><rdf:li xml:lang="x-default">R&#233;sum&#xE9;</rdf:li></rdf:Alt></dc:title>
+              <dc:creator><rdf:Seq><rdf:li>Ann</rdf:li><rdf:li>Bob</rdf:li></rdf:Seq></dc:creator>
+              <xmpMM:History><rdf:Seq><rdf:li><rdf:Description stEvt:action="saved"/></rdf:li></rdf:Seq></xmpMM:History>
+              <xmpMM:DerivedFrom rdf:resource="uuid:1"/>
+            </rdf:Description>
+        </rdf:RDF></x:xmpmeta>"#;
+        let properties = xmp_properties(xml);
+        let get = |key: &str| properties.get(key).map(String::as_str);
+        assert_eq!(get("pdf:Producer"), Some("A & B"));
+        assert_eq!(get("dc:title"), Some("Résumé"));
+        assert_eq!(get("dc:creator"), Some("Ann; Bob"));
+        assert_eq!(get("xmpMM:DerivedFrom"), Some("uuid:1"));
+        assert_eq!(get("xmpMM:History"), None);
+        assert_eq!(get("stEvt:action"), None);
+    }
+}
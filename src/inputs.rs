@@ -0,0 +1,330 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// A PDF file to convert, with the directory its outputs go to relative to the output root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputDocument {
+    pub path: PathBuf,
+    /// Subdirectory of the output directory that mirrors where the file was found.
+    pub relative_dir: PathBuf,
+}
+
+/// Whether `arg` is `-`, which reads the document from standard input.
+pub fn is_stdin(arg: &Path) -> bool {
+    arg.as_os_str() == "-"
+}
+
+/// Whether `arg` contains glob metacharacters.
+pub fn is_glob_pattern(arg: &Path) -> bool {
+    arg.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// Whether `path` has a `.pdf` extension (case-insensitive).
+pub fn has_pdf_extension(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+}
+
+/// Expand the input arguments into the list of documents to convert.
+///
+/// - Files, and `-` for standard input, are used as given and write to the output root,
+///   unless they come from different directories (see below).
+/// - Directories contribute the `.pdf` files directly inside them, or every `.pdf` file
+///   below them when `recursive` is set. Outputs mirror the layout below the directory.
+/// - Glob patterns contribute every matching file (and, like directories, the PDFs inside
+///   matching directories). Outputs mirror the layout below the pattern's fixed prefix.
+///
+/// When the arguments start from different directories, each output directory also
+/// mirrors where that directory lies below the one they share, so that `a/doc.pdf` and
+/// `b/doc.pdf` write to `a` and `b` rather than both to the output root.
+///
+/// Documents are returned in argument order, files within a directory sorted by path, and
+/// a file reached through several arguments is only listed once.
+pub fn collect_inputs(args: &[PathBuf], recursive: bool) -> Result<Vec<InputDocument>, String> {
+    let mut documents = Vec::new();
+    // The directory each document's `relative_dir` is relative to, by index.
+    let mut bases = Vec::new();
+    let mut seen = HashSet::new();
+    let mut push = |doc: InputDocument, base: &Path, documents: &mut Vec<InputDocument>| {
+        if seen.insert(doc.path.clone()) {
+            documents.push(doc);
+            bases.push(base.to_path_buf());
+        }
+    };
+
+    for arg in args {
+        if is_stdin(arg) || arg.exists() || !is_glob_pattern(arg) {
+            if arg.is_dir() {
+                for doc in collect_directory(arg, recursive)? {
+                    push(doc, arg, &mut documents);
+                }
+            } else {
+                // Missing files are reported when they are read, like a single input.
+                let doc = InputDocument {
+                    path: arg.clone(),
+                    relative_dir: PathBuf::new(),
+                };
+                let base = match arg.parent() {
+                    Some(parent) if !is_stdin(arg) => parent,
+                    _ => Path::new(""),
+                };
+                push(doc, base, &mut documents);
+            }
+            continue;
+        }
+
+        let pattern = arg.to_string_lossy();
+        let matches = glob::glob(&pattern)
+            .map_err(|e| format!("Invalid glob pattern '{pattern}': {e}"))?;
+        let base = glob_base(arg);
+        let mut matched_any = false;
+        for entry in matches {
+            let path = entry.map_err(|e| format!("Failed to read glob match: {e}"))?;
+            matched_any = true;
+            if path.is_dir() {
+                let relative = path.strip_prefix(&base).unwrap_or(&path).to_path_buf();
+                for mut doc in collect_directory(&path, recursive)? {
+                    doc.relative_dir = relative.join(&doc.relative_dir);
+                    push(doc, &base, &mut documents);
+                }
+            } else if has_pdf_extension(&path) {
+                let relative_dir = path
+                    .parent()
+                    .and_then(|parent| parent.strip_prefix(&base).ok())
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default();
+                push(InputDocument { path, relative_dir }, &base, &mut documents);
+            }
+        }
+        if !matched_any {
+            return Err(format!("No files match the pattern '{pattern}'"));
+        }
+    }
+
+    if documents.is_empty() {
+        return Err("No PDF files found in the given inputs".to_string());
+    }
+    mirror_bases(&mut documents, &bases);
+    Ok(documents)
+}
+
+/// Prefix the `relative_dir` of each document with where its base directory lies below
+/// the deepest directory all bases share. Nothing changes when there is a single base.
+/// Bases are compared as canonical paths; one that cannot be resolved, such as the
+/// directory of a missing file or standard input, keeps its documents where they are.
+fn mirror_bases(documents: &mut [InputDocument], bases: &[PathBuf]) {
+    let canonical: Vec<Option<PathBuf>> = bases
+        .iter()
+        .map(|base| {
+            let dir = if base.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                base
+            };
+            fs::canonicalize(dir).ok()
+        })
+        .collect();
+    let mut resolved = canonical.iter().flatten();
+    let Some(first) = resolved.next() else {
+        return;
+    };
+    let mut shared = first.clone();
+    for base in resolved {
+        while !base.starts_with(&shared) {
+            if !shared.pop() {
+                return;
+            }
+        }
+    }
+
+    for (doc, base) in documents.iter_mut().zip(&canonical) {
+        if let Some(below) = base
+            .as_ref()
+            .and_then(|base| base.strip_prefix(&shared).ok())
+            && !below.as_os_str().is_empty()
+        {
+            doc.relative_dir = if doc.relative_dir.as_os_str().is_empty() {
+                below.to_path_buf()
+            } else {
+                below.join(&doc.relative_dir)
+            };
+        }
+    }
+}
+
+/// List the PDF files in `dir`, descending into subdirectories when `recursive` is set.
+fn collect_directory(dir: &Path, recursive: bool) -> Result<Vec<InputDocument>, String> {
+    let mut documents = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+
+    while let Some(relative) = pending.pop() {
+        let current = dir.join(&relative);
+        let entries = fs::read_dir(&current).map_err(|e| {
+            format!("Failed to read directory {}: {e}", current.display())
+        })?;
+        let mut paths = entries
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Failed to read directory {}: {e}", current.display()))?;
+        paths.sort();
+
+        let mut subdirs = Vec::new();
+        for path in paths {
+            if path.is_dir() {
+                if recursive && let Some(name) = path.file_name() {
+                    subdirs.push(relative.join(name));
+                }
+            } else if has_pdf_extension(&path) {
+                documents.push(InputDocument {
+                    path,
+                    relative_dir: relative.clone(),
+                });
+            }
+        }
+        // Push in reverse so subdirectories are visited in sorted order.
+        pending.extend(subdirs.into_iter().rev());
+    }
+
+    documents.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(documents)
+}
+
+/// The leading components of a glob pattern that contain no metacharacters.
+fn glob_base(pattern: &Path) -> PathBuf {
+    let mut base = PathBuf::new();
+    for component in pattern.components() {
+        if let Component::Normal(part) = component
+            && is_glob_pattern(Path::new(part))
+        {
+            break;
+        }
+        base.push(component);
+    }
+    base
+}
+
+/// Choose the `--prefix` value for one document of a run.
+///
+/// A single document uses `prefix` as given. When several documents are converted, a
+/// user-supplied prefix is combined with each document's stem so that documents written to
+/// the same directory do not overwrite each other; without one, the stem is used as usual.
+pub fn batch_prefix(prefix: Option<&str>, input: &Path, batch: bool) -> Option<String> {
+    let prefix = prefix?;
+    if !batch {
+        return Some(prefix.to_string());
+    }
+    let stem = input
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Some(format!("{prefix}-{stem}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test; the library's test helpers are not visible here.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pdf-converter-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Create empty files at `paths` below `dir`.
+    fn touch(dir: &Path, paths: &[&str]) {
+        for path in paths {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+    }
+
+    fn relative_dirs(documents: &[InputDocument]) -> Vec<&str> {
+        documents
+            .iter()
+            .map(|doc| doc.relative_dir.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn lists_directories_in_path_order() {
+        let dir = test_dir("inputs-directory");
+        touch(&dir, &["b.pdf", "a.PDF", "notes.txt", "sub/c.pdf"]);
+
+        let args = [dir.clone()];
+        let documents = collect_inputs(&args, false).unwrap();
+        let paths: Vec<_> = documents.iter().map(|doc| doc.path.clone()).collect();
+        assert_eq!(paths, [dir.join("a.PDF"), dir.join("b.pdf")]);
+
+        let documents = collect_inputs(&args, true).unwrap();
+        assert_eq!(relative_dirs(&documents), ["", "", "sub"]);
+        assert_eq!(documents[2].path, dir.join("sub/c.pdf"));
+    }
+
+    #[test]
+    fn mirrors_files_with_the_same_name_in_different_directories() {
+        let dir = test_dir("inputs-same-name");
+        touch(&dir, &["a/doc.pdf", "b/doc.pdf", "b/other.pdf"]);
+
+        let files = [dir.join("a/doc.pdf"), dir.join("b/doc.pdf")];
+        assert_eq!(
+            relative_dirs(&collect_inputs(&files, false).unwrap()),
+            ["a", "b"]
+        );
+
+        let dirs = [dir.join("a"), dir.join("b")];
+        assert_eq!(
+            relative_dirs(&collect_inputs(&dirs, false).unwrap()),
+            ["a", "b", "b"]
+        );
+
+        let pattern = dir.join("*/doc.pdf");
+        let documents = collect_inputs(&[pattern], false).unwrap();
+        assert_eq!(relative_dirs(&documents), ["a", "b"]);
+    }
+
+    #[test]
+    fn keeps_files_from_one_directory_at_the_root() {
+        let dir = test_dir("inputs-one-directory");
+        touch(&dir, &["a/doc.pdf", "a/other.pdf"]);
+
+        let files = [
+            dir.join("a/doc.pdf"),
+            dir.join("a/other.pdf"),
+            dir.join("a/doc.pdf"),
+        ];
+        let documents = collect_inputs(&files, false).unwrap();
+        assert_eq!(relative_dirs(&documents), ["", ""]);
+
+        let pattern = dir.join("a/*.pdf");
+        assert_eq!(
+            relative_dirs(&collect_inputs(&[pattern], false).unwrap()),
+            ["", ""]
+        );
+    }
+
+    #[test]
+    fn reports_patterns_without_matches() {
+        let dir = test_dir("inputs-no-match");
+        let pattern = dir.join("*.pdf");
+        assert!(collect_inputs(&[pattern], false).is_err());
+        assert!(collect_inputs(&[dir], false).is_err());
+    }
+
+    #[test]
+    fn combines_prefixes_with_document_stems_in_batches() {
+        let input = Path::new("a/report.pdf");
+        assert_eq!(batch_prefix(None, input, true), None);
+        assert_eq!(
+            batch_prefix(Some("page"), input, false).as_deref(),
+            Some("page")
+        );
+        assert_eq!(
+            batch_prefix(Some("page"), input, true).as_deref(),
+            Some("page-report")
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use hayro::hayro_syntax::Pdf;
+use hayro::hayro_syntax::object::{Array, Dict, Name, String as PdfString};
+
+/// The largest number written as a Roman numeral; larger ones are written in decimal.
+const MAX_ROMAN: usize = 4999;
+
+/// The largest number written in letters, repeating a letter 500 times; larger ones are
+/// written in decimal.
+const MAX_LETTERS: usize = 26 * 500;
+
+/// Numbering style of a page label range (`/S` entry).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LabelStyle {
+    Decimal,
+    UpperRoman,
+    LowerRoman,
+    UpperLetters,
+    LowerLetters,
+    /// Only the prefix, without a number.
+    None,
+}
+
+/// One range of the `/PageLabels` number tree, starting at 0-based page `start`.
+#[derive(Clone, Debug)]
+struct LabelRange {
+    start: usize,
+    style: LabelStyle,
+    prefix: String,
+    first: usize,
+}
+
+/// The page labels of `pdf` (the numbers printed on the pages, such as `iv` or `A-3`), one
+/// per page. Pages without a label, and documents without `/PageLabels`, use their 1-based
+/// page number.
+pub fn page_labels(pdf: &Pdf) -> Vec<String> {
+    let total = pdf.pages().len();
+    let mut ranges = Vec::new();
+    let xref = pdf.xref();
+    if let Some(tree) = xref
+        .get::<Dict>(xref.root_id())
+        .and_then(|catalog| catalog.get::<Dict>(b"PageLabels"))
+    {
+        collect_ranges(&tree, &mut ranges, 0);
+    }
+    ranges.sort_by_key(|range| range.start);
+
+    (0..total)
+        .map(|idx| match ranges.iter().rev().find(|range| range.start <= idx) {
+            Some(range) => format_label(range, idx - range.start),
+            None => (idx + 1).to_string(),
+        })
+        .collect()
+}
+
+/// Walk a number tree node, collecting its `/Nums` entries and those of its `/Kids`.
+fn collect_ranges(node: &Dict, ranges: &mut Vec<LabelRange>, depth: usize) {
+    // Guard against reference cycles in malformed files.
+    if depth > 32 {
+        return;
+    }
+    if let Some(nums) = node.get::<Array>(b"Nums") {
+        let mut items = nums.flex_iter();
+        while let Some(start) = items.next::<i64>() {
+            let Some(dict) = items.next::<Dict>() else {
+                break;
+            };
+            if let Ok(start) = usize::try_from(start) {
+                ranges.push(parse_range(start, &dict));
+            }
+        }
+    }
+    if let Some(kids) = node.get::<Array>(b"Kids") {
+        for kid in kids.iter::<Dict>() {
+            collect_ranges(&kid, ranges, depth + 1);
+        }
+    }
+}
+
+fn parse_range(start: usize, dict: &Dict) -> LabelRange {
+    let style = match dict.get::<Name>(b"S").as_deref() {
+        Some(b"D") => LabelStyle::Decimal,
+        Some(b"R") => LabelStyle::UpperRoman,
+        Some(b"r") => LabelStyle::LowerRoman,
+        Some(b"A") => LabelStyle::UpperLetters,
+        Some(b"a") => LabelStyle::LowerLetters,
+        _ => LabelStyle::None,
+    };
+    let prefix = dict
+        .get::<PdfString>(b"P")
+        .map(|p| decode_text_string(p.as_bytes()))
+        .unwrap_or_default();
+    let first = dict
+        .get::<i64>(b"St")
+        .and_then(|st| usize::try_from(st).ok())
+        .filter(|&st| st >= 1)
+        .unwrap_or(1);
+    LabelRange {
+        start,
+        style,
+        prefix,
+        first,
+    }
+}
+
+/// The label of the page `offset` pages after the start of `range`.
+///
+/// `/St` can be any number, so numbers too large for Roman numerals or letters, which would
+/// take millions of characters, are written in decimal.
+fn format_label(range: &LabelRange, offset: usize) -> String {
+    let Some(n) = range.first.checked_add(offset) else {
+        return format!("{}{}", range.prefix, range.first as u128 + offset as u128);
+    };
+    let number = match range.style {
+        LabelStyle::UpperRoman if n <= MAX_ROMAN => roman(n),
+        LabelStyle::LowerRoman if n <= MAX_ROMAN => roman(n).to_ascii_lowercase(),
+        LabelStyle::UpperLetters if n <= MAX_LETTERS => letters(n),
+        LabelStyle::LowerLetters if n <= MAX_LETTERS => letters(n).to_ascii_lowercase(),
+        LabelStyle::None => String::new(),
+        _ => n.to_string(),
+    };
+    format!("{}{}", range.prefix, number)
+}
+
+/// Upper-case Roman numeral for `n` (values above 3999 repeat `M`).
+fn roman(mut n: usize) -> String {
+    const NUMERALS: [(usize, &str); 13] = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    let mut out = String::new();
+    for (value, numeral) in NUMERALS {
+        while n >= value {
+            out.push_str(numeral);
+            n -= value;
+        }
+    }
+    out
+}
+
+/// Letter label for `n` as defined by the PDF specification: A to Z, then AA to ZZ, and so on.
+fn letters(n: usize) -> String {
+    let letter = (b'A' + ((n - 1) % 26) as u8) as char;
+    letter.to_string().repeat((n - 1) / 26 + 1)
+}
+
+/// Decode a PDF text string, either UTF-16BE with a byte order mark or PDFDocEncoding
+/// (approximated as Latin-1).
+pub fn decode_text_string(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        let units: Vec<u16> = utf16
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else if let Some(utf8) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        String::from_utf8_lossy(utf8).into_owned()
+    } else {
+        bytes.iter().map(|&b| b as char).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(style: LabelStyle, first: usize, offset: usize) -> String {
+        let range = LabelRange {
+            start: 0,
+            style,
+            prefix: "A-".to_string(),
+            first,
+        };
+        format_label(&range, offset)
+    }
+
+    #[test]
+    fn formats_each_style() {
+        assert_eq!(label(LabelStyle::Decimal, 1, 2), "A-3");
+        assert_eq!(label(LabelStyle::UpperRoman, 1, 1993), "A-MCMXCIV");
+        assert_eq!(label(LabelStyle::LowerRoman, 4, 0), "A-iv");
+        assert_eq!(label(LabelStyle::UpperLetters, 1, 27), "A-BB");
+        assert_eq!(label(LabelStyle::LowerLetters, 26, 0), "A-z");
+        assert_eq!(label(LabelStyle::None, 1, 5), "A-");
+    }
+
+    #[test]
+    fn writes_large_numbers_in_decimal() {
+        assert_eq!(label(LabelStyle::UpperRoman, 4999, 0), "A-MMMMCMXCIX");
+        assert_eq!(label(LabelStyle::UpperRoman, 5000, 0), "A-5000");
+        assert_eq!(label(LabelStyle::LowerLetters, 1, MAX_LETTERS), "A-13001");
+        assert_eq!(
+            label(LabelStyle::UpperRoman, usize::MAX, 0),
+            format!("A-{}", usize::MAX)
+        );
+        assert_eq!(
+            label(LabelStyle::Decimal, usize::MAX, 2),
+            format!("A-{}", usize::MAX as u128 + 2)
+        );
+    }
+}
@@ -0,0 +1,47 @@
+//! Convert PDF documents to PNG, JPEG, WebP, TIFF, SVG or text, into zoomable tile pyramids or
+//! onto contact sheets, and extract the images embedded in them.
+//!
+//! The [`Converter`] builder holds the conversion settings and can either write the
+//! outputs of a document to a directory or return them in memory together with metadata
+//! about each rendered page, and [`inspect`] reports the metadata and page boxes of a
+//! document without rendering it. The `pdf-converter` command-line tool is a thin wrapper
+//! around them.
+
+mod cache;
+mod color;
+mod converter;
+mod crop;
+mod error;
+mod images;
+mod info;
+mod labels;
+mod montage;
+mod naming;
+mod nup;
+mod output;
+mod password;
+mod pyramid;
+mod raster;
+mod render;
+mod sizing;
+mod svg;
+#[cfg(test)]
+mod testing;
+mod text;
+mod tiff_writer;
+mod tiles;
+mod trailer;
+mod utils;
+
+pub use color::{ColorMode, Dither, parse_background, parse_color};
+pub use converter::{Conversion, Converter, OutputFormat, PageInfo, RenderedOutput};
+pub use crop::{CropRect, PageBox, PageRotation, parse_crop, parse_rotation};
+pub use error::{Error, ErrorKind};
+pub use info::{DocumentInfo, PageDetails, inspect, inspect_bytes};
+pub use naming::NameTemplate;
+pub use nup::{Nup, ReadingOrder, parse_nup};
+pub use output::ExistingFiles;
+pub use render::Background;
+pub use sizing::{Sizing, parse_dpi, parse_fit};
+pub use tiff_writer::TiffCompression;
+pub use tiles::parse_memory;
@@ -1,24 +1,28 @@
-mod utils;
+mod inputs;
 
 use clap::{
-    Parser, ValueEnum,
+    Args, Parser, Subcommand, ValueEnum,
     builder::styling::{AnsiColor, Style, Styles},
     value_parser,
 };
-use hayro::{Pdf, RenderSettings, render};
-use hayro_interpret::InterpreterSettings;
-use hayro_svg::convert;
-use file_format::FileFormat;
-use std::collections::HashSet;
-use std::error::Error;
-use std::fmt;
+use pdf_converter::{
+    Background, ColorMode, Converter, CropRect, Dither, DocumentInfo, Error, ErrorKind,
+    ExistingFiles, NameTemplate, Nup, OutputFormat, PageBox, PageRotation, ReadingOrder,
+    Sizing, TiffCompression, inspect, inspect_bytes, parse_background, parse_color,
+    parse_crop, parse_dpi, parse_fit, parse_memory, parse_nup, parse_rotation,
+};
+use serde::Serialize;
 use std::fs;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, OnceLock};
+use std::sync::OnceLock;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 use colourful_logger::Logger;
 
+/// Environment variable holding the password for encrypted PDFs.
+const PASSWORD_ENV: &str = "PDF_CONVERTER_PASSWORD";
+
 const STYLES: Styles = Styles::styled()
     .header(AnsiColor::Cyan.on_default().bold())
     .usage(AnsiColor::Yellow.on_default().bold())
@@ -29,73 +33,488 @@ const STYLES: Styles = Styles::styled()
 #[value(rename_all = "lower")]
 enum Format {
     Png,
+    Jpeg,
+    Webp,
+    Tiff,
     Svg,
+    Dzi,
+    Xyz,
+    Montage,
+    Text,
+    Images,
 }
 
 #[derive(Parser)]
-#[command(name = "pdf-converter", version, about = "Convert PDF files to PNG or SVG", max_term_width = 79, styles = STYLES)]
+#[command(
+    name = "pdf-converter",
+    version,
+    about = "Convert PDF files to PNG, JPEG, WebP, TIFF, SVG, text, tile pyramids or contact sheets, extract their images or inspect them",
+    max_term_width = 79,
+    styles = STYLES,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    disable_help_subcommand = true
+)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Suppress informational logging (only errors printed)
-    #[arg(short = 'q', long = "quiet", global = true)]
+    #[arg(short = 'q', long = "quiet")]
     quiet: bool,
 
-    /// Choose pages to convert. You can provide multiple page numbers separated by commas
+    /// Choose pages to convert. Accepts comma-separated page numbers, ranges (3-10), open
+    /// ranges (5-), the last N pages (-3), odd, even and last. Prefix an item with ! to
+    /// exclude it (1-20,!7)
     #[arg(
         short = 'p',
         long = "page",
         value_name = "PAGE",
-        value_parser = value_parser!(usize),
         num_args = 1,
         value_delimiter = ',',
-        action = clap::ArgAction::Append,
-        global = true
+        allow_hyphen_values = true,
+        action = clap::ArgAction::Append
     )]
-    pages: Vec<usize>,
+    pages: Vec<String>,
+
+    /// Convert pages in the order given to --page, keeping repeated pages. Output names
+    /// include the output position and the source page
+    #[arg(long = "ordered")]
+    ordered: bool,
 
     /// Scale factor applied to outputs
-    #[arg(short = 's', long = "scale", default_value = "1.0", global = true)]
+    #[arg(short = 's', long = "scale", default_value = "1.0")]
     scale: f32,
 
+    /// Render at this resolution in dots per inch instead of a scale factor
+    #[arg(
+        long = "dpi",
+        value_parser = parse_dpi,
+        conflicts_with_all = ["scale", "width", "height", "fit"]
+    )]
+    dpi: Option<f32>,
+
+    /// Scale each page to this width in pixels, keeping its aspect ratio
+    #[arg(
+        long = "width",
+        value_name = "PIXELS",
+        value_parser = value_parser!(u32).range(1..),
+        conflicts_with_all = ["scale", "height", "fit"]
+    )]
+    width: Option<u32>,
+
+    /// Scale each page to this height in pixels, keeping its aspect ratio
+    #[arg(
+        long = "height",
+        value_name = "PIXELS",
+        value_parser = value_parser!(u32).range(1..),
+        conflicts_with_all = ["scale", "fit"]
+    )]
+    height: Option<u32>,
+
+    /// Scale each page to the largest size that fits in a WxH pixel box (e.g. 1920x1080)
+    #[arg(
+        long = "fit",
+        value_name = "WxH",
+        value_parser = parse_fit,
+        conflicts_with = "scale"
+    )]
+    fit: Option<(u32, u32)>,
+
+    /// Page box to render: the whole sheet (media), the visible page (crop), or the bleed,
+    /// trim or art box. Missing boxes fall back to the crop box
+    #[arg(
+        long = "box",
+        value_enum,
+        value_name = "BOX",
+        default_value = "crop",
+        ignore_case = true
+    )]
+    page_box: PageBox,
+
+    /// Only render this rectangle of the page box, in points from its lower-left corner
+    /// (e.g. 72,72,200,100)
+    #[arg(
+        long = "crop",
+        value_name = "X,Y,W,H",
+        value_parser = parse_crop,
+        allow_hyphen_values = true
+    )]
+    crop: Option<CropRect>,
+
+    /// Turn pages clockwise by 90, 180 or 270 degrees, on top of their own rotation. Append
+    /// :PAGES to only turn some pages (e.g. 90:2-4,!3). Can be repeated; later rules win
+    #[arg(
+        long = "rotate",
+        value_name = "ANGLE[:PAGES]",
+        value_parser = parse_rotation,
+        allow_hyphen_values = true,
+        action = clap::ArgAction::Append
+    )]
+    rotate: Vec<PageRotation>,
+
+    /// Ignore the rotation pages define in the PDF (/Rotate), so only --rotate turns them
+    #[arg(long = "ignore-rotate")]
+    ignore_rotate: bool,
+
+    /// Trim each page to the bounding box of its visible content
+    #[arg(long = "autotrim")]
+    autotrim: bool,
+
+    /// Margin in points kept around the content with --autotrim
+    #[arg(
+        long = "trim-margin",
+        value_name = "POINTS",
+        default_value = "0",
+        value_parser = parse_margin,
+        requires = "autotrim"
+    )]
+    trim_margin: f32,
+
+    /// Write each page of PNG, JPEG and WebP outputs as a grid of tiles named
+    /// <NAME>_<ROW>_<COL>, counted from 0, instead of one image
+    #[arg(long = "tiles")]
+    tiles: bool,
+
+    /// Edge of square tiles in pixels, for --tiles, tile pyramids and pages stitched together
+    /// under --max-memory [default: 1024 with --tiles, 254 for dzi, 256 for xyz, bands across
+    /// the page otherwise]
+    #[arg(
+        long = "tile-size",
+        value_name = "PIXELS",
+        value_parser = value_parser!(u32).range(1..=65535)
+    )]
+    tile_size: Option<u32>,
+
+    /// Limit the memory used for page pixels to about SIZE (e.g. 512M or 2G), shared between
+    /// the --jobs. Larger PNG pages are rendered in tiles and stitched together; other formats
+    /// fail for them unless written with --tiles
+    #[arg(
+        long = "max-memory",
+        value_name = "SIZE",
+        value_parser = parse_memory
+    )]
+    max_memory: Option<u64>,
+
+    /// Pixels each Deep Zoom tile shares with its neighbours
+    #[arg(
+        long = "tile-overlap",
+        value_name = "PIXELS",
+        default_value = "1",
+        value_parser = value_parser!(u32).range(0..=256)
+    )]
+    tile_overlap: u32,
+
     /// Prefix for output files. If omitted, inferred from the input name
-    #[arg(long = "prefix", global = true)]
+    #[arg(long = "prefix")]
     prefix: Option<String>,
 
+    /// Name outputs with a template instead of the prefix, e.g. '{stem}-{page:04}'.
+    /// Placeholders: {stem}, {page}, {seq}, {total}, {label}, {width}, {height}, {format}.
+    /// The file extension is added automatically
+    #[arg(
+        long = "name-template",
+        value_name = "TEMPLATE",
+        value_parser = NameTemplate::parse,
+        conflicts_with = "prefix"
+    )]
+    name_template: Option<NameTemplate>,
+
+    /// Background pages are rendered onto: a colour (#rrggbb, #rgb or a name such as white or
+    /// navy) or transparent. Raster outputs default to white; SVG outputs have no background
+    /// unless a colour is given
+    #[arg(
+        long = "background",
+        value_name = "COLOR",
+        value_parser = parse_background
+    )]
+    background: Option<Background>,
+
+    /// Encoding quality for JPEG and lossy WebP outputs (1-100)
+    #[arg(
+        long = "quality",
+        default_value = "90",
+        value_parser = value_parser!(u8).range(1..=100)
+    )]
+    quality: u8,
+
+    /// Encode WebP outputs losslessly (--quality is ignored)
+    #[arg(long = "lossless")]
+    lossless: bool,
+
+    /// Colour that transparent areas are flattened onto in JPEG outputs, which have no
+    /// transparency (used with --background transparent)
+    #[arg(
+        long = "jpeg-background",
+        value_name = "COLOR",
+        default_value = "white",
+        value_parser = parse_color
+    )]
+    jpeg_background: [u8; 3],
+
+    /// Colour model of raster outputs: full colour, 8- or 16-bit greyscale, or 1-bit black and
+    /// white
+    #[arg(
+        long = "color",
+        value_enum,
+        value_name = "MODE",
+        default_value = "rgb",
+        ignore_case = true
+    )]
+    color: ColorMode,
+
+    /// How --color mono and bilevel TIFF pages are reduced to black and white
+    #[arg(
+        long = "dither",
+        value_enum,
+        value_name = "METHOD",
+        default_value = "threshold",
+        ignore_case = true
+    )]
+    dither: Dither,
+
+    /// Compression used for TIFF outputs. G4 implies --bilevel
+    #[arg(
+        long = "tiff-compression",
+        value_enum,
+        value_name = "METHOD",
+        default_value = "lzw",
+        ignore_case = true
+    )]
+    tiff_compression: TiffCompression,
+
+    /// Write TIFF pages as 1-bit black and white (fax-style), like --color mono
+    #[arg(long = "bilevel")]
+    bilevel: bool,
+
+    /// Put consecutive pages side by side into each PNG, JPEG, WebP or SVG output: COLSxROWS
+    /// pages at full size (e.g. 2x1 for spreads). Outputs are named after their first and last
+    /// page
+    #[arg(
+        long = "nup",
+        value_name = "COLSxROWS",
+        value_parser = parse_nup
+    )]
+    nup: Option<Nup>,
+
+    /// Order pages for a saddle-stitched booklet, two per output (--nup 2x1 unless given).
+    /// Outputs are the sides of the sheets, such as doc-sheet1-front and doc-sheet1-back
+    #[arg(long = "booklet")]
+    booklet: bool,
+
+    /// Order of the pages along each row with --nup: left to right, or right to left for
+    /// right-bound publications
+    #[arg(
+        long = "reading-order",
+        value_enum,
+        value_name = "ORDER",
+        default_value = "ltr",
+        ignore_case = true
+    )]
+    reading_order: ReadingOrder,
+
+    /// Thumbnails per row of montage sheets
+    #[arg(
+        long = "columns",
+        value_name = "N",
+        default_value = "4",
+        value_parser = value_parser!(u32).range(1..=1000)
+    )]
+    columns: u32,
+
+    /// Pixels between montage thumbnails and around the edge of the sheet
+    #[arg(
+        long = "spacing",
+        value_name = "PIXELS",
+        default_value = "16",
+        value_parser = value_parser!(u32).range(0..=1000)
+    )]
+    spacing: u32,
+
+    /// Print the page number under each montage thumbnail
+    #[arg(long = "captions")]
+    captions: bool,
+
+    /// Largest montage sheet in pixels (e.g. 2480x3508). Pages that do not fit go onto more
+    /// sheets, named <NAME>-sheet<N>; thumbnails shrink when not even one row fits. Size the
+    /// thumbnails with --fit, --width, --height, --dpi or --scale
+    #[arg(
+        long = "sheet-size",
+        value_name = "WxH",
+        value_parser = parse_fit
+    )]
+    sheet_size: Option<(u32, u32)>,
+
+    /// Write text outputs as JSON: every run of text with its font, size and bounding box in
+    /// points from the top-left corner of the page, next to the page size
+    #[arg(long = "json")]
+    json: bool,
+
+    #[command(flatten)]
+    password: PasswordArgs,
+
     /// Output format
-    #[arg(value_enum, value_name = "FORMAT", ignore_case = true)]
-    format: Format,
+    #[arg(value_enum, value_name = "FORMAT", ignore_case = true, required = true)]
+    format: Option<Format>,
+
+    /// Input PDF files, directories or glob patterns, or - to read from standard input. When
+    /// exactly two are given and the second is neither a PDF nor a directory with files in it,
+    /// it is used as the output directory (deprecated: use --output)
+    #[arg(value_parser = value_parser!(PathBuf), value_name = "INPUT", required = true, num_args = 1..)]
+    inputs: Vec<PathBuf>,
+
+    /// Output directory [default: .]. Use - to write a single output file to standard
+    /// output
+    #[arg(short = 'o', long = "output", value_parser = value_parser!(PathBuf), value_name = "OUTPUT")]
+    output: Option<PathBuf>,
 
-    /// Input PDF file
-    #[arg(value_parser = value_parser!(PathBuf), value_name = "INPUT")]
-    input: PathBuf,
+    /// Replace output files that already exist
+    #[arg(long = "overwrite", conflicts_with_all = ["skip_existing", "no_clobber"])]
+    overwrite: bool,
 
-    /// Output directory
-    #[arg(value_parser = value_parser!(PathBuf), value_name = "OUTPUT", default_value = ".")]
-    output: PathBuf,
+    /// Keep output files that already exist and only render the missing pages
+    #[arg(long = "skip-existing", conflicts_with = "no_clobber")]
+    skip_existing: bool,
+
+    /// Stop with an error if an output file already exists (the default)
+    #[arg(long = "no-clobber")]
+    no_clobber: bool,
+
+    /// Keep a cache manifest in the output directory and only render pages whose input or
+    /// settings changed since the last run
+    #[arg(long = "incremental")]
+    incremental: bool,
+
+    /// Number of pages rendered in parallel. Defaults to the number of CPU cores
+    #[arg(
+        short = 'j',
+        long = "jobs",
+        value_name = "N",
+        value_parser = value_parser!(u32).range(1..)
+    )]
+    jobs: Option<u32>,
+
+    /// Also convert PDFs in subdirectories of input directories
+    #[arg(short = 'r', long = "recursive")]
+    recursive: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the page count, PDF version, metadata, encryption status and page boxes of PDF
+    /// files, to plan a conversion
+    Info(InfoArgs),
+}
+
+#[derive(Args)]
+struct InfoArgs {
+    /// Print a JSON object for the document, or an array of them for several documents,
+    /// instead of a report
+    #[arg(long = "json")]
+    json: bool,
+
+    #[command(flatten)]
+    password: PasswordArgs,
+
+    /// Input PDF files, directories or glob patterns, or - to read from standard input
+    #[arg(value_parser = value_parser!(PathBuf), value_name = "INPUT", required = true, num_args = 1..)]
+    inputs: Vec<PathBuf>,
+
+    /// Also inspect PDFs in subdirectories of input directories
+    #[arg(short = 'r', long = "recursive")]
+    recursive: bool,
+}
+
+#[derive(Args)]
+struct PasswordArgs {
+    /// Password for encrypted PDFs, either the user or the owner password. Defaults to the
+    /// PDF_CONVERTER_PASSWORD environment variable
+    #[arg(long = "password", value_name = "PASSWORD")]
+    password: Option<String>,
+
+    /// Read the password for encrypted PDFs from the first line of FILE
+    #[arg(
+        long = "password-file",
+        value_name = "FILE",
+        value_parser = value_parser!(PathBuf),
+        conflicts_with = "password"
+    )]
+    password_file: Option<PathBuf>,
+}
+
+impl PasswordArgs {
+    /// The password given on the command line, in a file or in the environment.
+    fn resolve(self) -> Result<Option<String>, Error> {
+        Ok(match (self.password, self.password_file) {
+            (Some(password), _) => Some(password),
+            (None, Some(path)) => Some(read_password_file(&path)?),
+            (None, None) => std::env::var(PASSWORD_ENV).ok(),
+        })
+    }
 }
 
 // Provide a global colourful logger instance (user requested global usage).
 static LOGGER: OnceLock<Logger> = OnceLock::new();
 static QUIET: AtomicBool = AtomicBool::new(false);
+// Set while an output is streamed to stdout, where the logger would corrupt the data.
+static LOG_TO_STDERR: AtomicBool = AtomicBool::new(false);
 fn get_logger() -> &'static Logger {
     LOGGER.get_or_init(Logger::default)
 }
 
 enum LogLevel {
     Info,
+    Warn,
     Error,
 }
 
 fn log_event(level: LogLevel, message: &str, tag: &'static str) {
+    if LOG_TO_STDERR.load(Ordering::SeqCst) {
+        match level {
+            LogLevel::Info if QUIET.load(Ordering::SeqCst) => {}
+            LogLevel::Info => eprintln!("info: [{tag}] {message}"),
+            LogLevel::Warn => eprintln!("warning: [{tag}] {message}"),
+            LogLevel::Error => eprintln!("error: [{tag}] {message}"),
+        }
+        return;
+    }
     match level {
         LogLevel::Info => {
             if !QUIET.load(Ordering::SeqCst) {
                 get_logger().info_single(message, tag)
             }
         }
+        LogLevel::Warn => get_logger().warn_single(message, tag),
         LogLevel::Error => get_logger().error_single(message, tag),
     }
 }
 
+fn log_batch_summary(kind: &str, documents: usize, files: usize, failed: usize, output: &Path) {
+    let message = format!(
+        "Converted {} of {} documents: wrote {} {} file{} to {}",
+        documents - failed,
+        documents,
+        files,
+        kind,
+        if files == 1 { "" } else { "s" },
+        output.display()
+    );
+    log_event(LogLevel::Info, &message, "Summary");
+}
+
+fn log_cache_summary(rendered_pages: usize, up_to_date: usize, unchanged: usize, documents: usize) {
+    let message = format!(
+        "Rendered {} page{}; {} file{} up to date, {} of {} documents unchanged",
+        rendered_pages,
+        if rendered_pages == 1 { "" } else { "s" },
+        up_to_date,
+        if up_to_date == 1 { "" } else { "s" },
+        unchanged,
+        documents
+    );
+    log_event(LogLevel::Info, &message, "Cache");
+}
+
 fn log_render_summary(kind: &str, count: usize, output: &Path, input: &Path) {
     let suffix = if count == 1 { "" } else { "s" };
     let message = format!(
@@ -109,64 +528,219 @@ fn log_render_summary(kind: &str, count: usize, output: &Path, input: &Path) {
     log_event(LogLevel::Info, &message, "Output");
 }
 
-struct AppError {
-    tag: &'static str,
-    message: String,
-}
-
-impl AppError {
-    fn new(tag: &'static str, message: String) -> Self {
-        Self { tag, message }
-    }
-}
-
-impl fmt::Display for AppError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.message)
-    }
-}
-
-impl fmt::Debug for AppError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("AppError")
-            .field("tag", &self.tag)
-            .field("message", &self.message)
-            .finish()
-    }
-}
-
-impl Error for AppError {}
-
 fn main() {
     // Initialize the global logger
     let _ = get_logger();
 
     if let Err(err) = run() {
-        log_event(LogLevel::Error, &err.to_string(), err.tag);
+        log_event(LogLevel::Error, &err.to_string(), err.kind().tag());
         std::process::exit(1);
     }
 }
 
-fn run() -> Result<(), AppError> {
+fn run() -> Result<(), Error> {
     let Cli {
         quiet,
         pages,
+        ordered,
         scale,
+        dpi,
+        width,
+        height,
+        fit,
+        page_box,
+        crop,
+        autotrim,
+        trim_margin,
+        rotate,
+        ignore_rotate,
+        tiles,
+        tile_size,
+        max_memory,
+        tile_overlap,
         prefix,
+        name_template,
+        quality,
+        background,
+        color,
+        dither,
+        lossless,
+        jpeg_background,
+        tiff_compression,
+        bilevel,
+        nup,
+        booklet,
+        reading_order,
+        columns,
+        spacing,
+        captions,
+        sheet_size,
+        json,
+        password,
         format,
-        input,
+        mut inputs,
         output,
+        overwrite,
+        skip_existing,
+        no_clobber: _,
+        incremental,
+        jobs,
+        recursive,
+        command,
     } = Cli::parse();
 
+    if let Some(Command::Info(args)) = command {
+        return print_info(args);
+    }
+    let format = format.expect("FORMAT is required without a subcommand");
+
     // Apply quiet setting globally
     QUIET.store(quiet, Ordering::SeqCst);
 
-    let interpreter_settings = InterpreterSettings::default();
+    // Keep `pdf-converter <FORMAT> <INPUT> [OUTPUT]` working.
+    let output = match output {
+        Some(output) => output,
+        None if inputs.len() == 2 && is_legacy_output(&inputs[0], &inputs[1]) => {
+            let output = inputs.pop().unwrap();
+            log_event(
+                LogLevel::Warn,
+                &format!(
+                    "Using '{0}' as the output directory. Giving it after the input is \
+                     deprecated; use -o '{0}' instead",
+                    output.display()
+                ),
+                "Arguments",
+            );
+            output
+        }
+        None => PathBuf::from("."),
+    };
+
+    let stdout = output.as_os_str() == "-";
+    if stdout {
+        LOG_TO_STDERR.store(true, Ordering::SeqCst);
+    }
+
+    let documents = inputs::collect_inputs(&inputs, recursive)
+        .map_err(|msg| Error::new(ErrorKind::Input, msg))?;
+
+    let sizing = if let Some(dpi) = dpi {
+        Sizing::Dpi(dpi)
+    } else if let Some(width) = width {
+        Sizing::Width(width)
+    } else if let Some(height) = height {
+        Sizing::Height(height)
+    } else if let Some((w, h)) = fit {
+        Sizing::Fit(w, h)
+    } else {
+        Sizing::Scale(scale)
+    };
+
+    let output_format = match format {
+        Format::Png => OutputFormat::Png,
+        Format::Jpeg => OutputFormat::Jpeg {
+            quality,
+            background: jpeg_background,
+        },
+        Format::Webp => OutputFormat::Webp { quality, lossless },
+        Format::Tiff => OutputFormat::Tiff {
+            compression: tiff_compression,
+            bilevel,
+        },
+        Format::Svg => OutputFormat::Svg,
+        Format::Dzi => OutputFormat::Dzi {
+            tile_size: tile_size.unwrap_or(254),
+            overlap: tile_overlap,
+        },
+        Format::Xyz => OutputFormat::Xyz {
+            tile_size: tile_size.unwrap_or(256),
+        },
+        Format::Montage => OutputFormat::Montage {
+            columns,
+            spacing,
+            captions,
+            max_sheet: sheet_size,
+        },
+        Format::Text => OutputFormat::Text { json },
+        Format::Images => OutputFormat::Images,
+    };
+    let existing_files = if overwrite {
+        ExistingFiles::Overwrite
+    } else if skip_existing {
+        ExistingFiles::Skip
+    } else {
+        ExistingFiles::Fail
+    };
+    let mut converter = Converter::new(output_format)
+        .sizing(sizing)
+        .pages(pages)
+        .ordered(ordered)
+        .existing_files(existing_files)
+        .incremental(incremental)
+        .color(color)
+        .dither(dither)
+        .page_box(page_box)
+        .ignore_page_rotation(ignore_rotate)
+        .tiles(tiles);
+    for rotation in rotate {
+        converter = converter.rotate(rotation);
+    }
+    if let Some(crop) = crop {
+        converter = converter.crop(crop);
+    }
+    if autotrim {
+        converter = converter.autotrim(trim_margin);
+    }
+    if let Some(background) = background {
+        converter = converter.background(background);
+    }
+    if let Some(size) = tile_size {
+        converter = converter.tile_size(size);
+    }
+    if let Some(bytes) = max_memory {
+        converter = converter.max_memory(bytes);
+    }
+    if let Some(nup) = nup.or_else(|| booklet.then(|| Nup::new(2, 1))) {
+        converter = converter.nup(Nup {
+            booklet,
+            order: reading_order,
+            ..nup
+        });
+    }
+    if let Some(password) = password.resolve()? {
+        converter = converter.password(password);
+    }
+    if let Some(template) = name_template {
+        if documents.len() > 1 && !template.uses_stem() {
+            return Err(Error::new(
+                ErrorKind::NameTemplate,
+                format!(
+                    "The name template '{template}' must contain {{stem}} when converting \
+                     several documents"
+                ),
+            ));
+        }
+        converter = converter.name_template(template);
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.unwrap_or(0) as usize)
+        .build()
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Threads,
+                format!("Failed to start worker threads: {e}"),
+            )
+        })?;
+
+    if stdout {
+        return pool.install(|| write_to_stdout(&converter, &documents));
+    }
 
     let output_existed = output.exists();
     fs::create_dir_all(&output).map_err(|e| {
-        AppError::new(
-            "FileSystem",
+        Error::new(
+            ErrorKind::FileSystem,
             format!("Failed to create output directory: {e}"),
         )
     })?;
@@ -175,143 +749,295 @@ fn run() -> Result<(), AppError> {
         log_event(LogLevel::Info, &msg, "Output");
     }
 
-    match format {
-        Format::Png => process_png(&input, &output, scale, prefix.as_deref(), &pages, &interpreter_settings)?,
-        Format::Svg => process_svg(&input, &output, scale, prefix.as_deref(), &pages, &interpreter_settings)?,
-    }
+    let batch = documents.len() > 1;
+    let mut files_written = 0usize;
+    let mut failed = 0usize;
+    let mut pages_rendered = 0usize;
+    let mut files_up_to_date = 0usize;
+    let mut unchanged = 0usize;
 
-    Ok(())
-}
+    for document in &documents {
+        let doc_output = if document.relative_dir.as_os_str().is_empty() {
+            output.clone()
+        } else {
+            output.join(&document.relative_dir)
+        };
+        let mut doc_converter = converter.clone();
+        if let Some(doc_prefix) = inputs::batch_prefix(prefix.as_deref(), &document.path, batch) {
+            doc_converter = doc_converter.prefix(doc_prefix);
+        }
 
-fn load_pdf_and_pages(
-    input: &Path,
-    pages: &[usize],
-) -> Result<(Pdf, Option<HashSet<usize>>), AppError> {
-    let bytes = fs::read(input)
-        .map_err(|e| AppError::new("FileSystem", format!("Failed to read input file: {e}")))?;
-
-    // Detect file format and ensure it's a PDF
-    let fmt = FileFormat::from_bytes(&bytes);
-    if fmt != FileFormat::PortableDocumentFormat {
-        return Err(AppError::new(
-            "FileType",
-            "Input file is not a PDF".to_string(),
-        ));
-    }
+        let result = fs::create_dir_all(&doc_output)
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::FileSystem,
+                    format!("Failed to create output directory: {e}"),
+                )
+            })
+            .and_then(|_| {
+                pool.install(|| {
+                    if inputs::is_stdin(&document.path) {
+                        doc_converter.convert_bytes(read_stdin()?, &doc_output)
+                    } else {
+                        doc_converter.convert(&document.path, &doc_output)
+                    }
+                })
+            })
+            .map_err(|err| match err.kind() {
+                ErrorKind::OutputExists => Error::new(
+                    err.kind(),
+                    format!(
+                        "{err}. Use --overwrite to replace it or --skip-existing to keep it"
+                    ),
+                ),
+                _ => err,
+            });
 
-    let data = Arc::new(bytes);
-    let pdf =
-        Pdf::new(data).map_err(|e| AppError::new("PDF", format!("Failed to read PDF: {e:?}")))?;
+        match result {
+            Ok(conversion) => {
+                if output_format.is_container() && conversion.files.len() <= 1 {
+                    for file in &conversion.files {
+                        let message = format!(
+                            "Wrote {} page{} to {} (input: {})",
+                            conversion.pages,
+                            if conversion.pages == 1 { "" } else { "s" },
+                            file.display(),
+                            document.path.display()
+                        );
+                        log_event(LogLevel::Info, &message, "Output");
+                    }
+                } else if !conversion.files.is_empty()
+                    || (conversion.skipped == 0 && conversion.up_to_date == 0)
+                {
+                    log_render_summary(
+                        output_format.label(),
+                        conversion.files.len(),
+                        &doc_output,
+                        &document.path,
+                    );
+                }
+                if conversion.skipped > 0 {
+                    let message = format!(
+                        "Skipped {} existing file{} in {} (input: {})",
+                        conversion.skipped,
+                        if conversion.skipped == 1 { "" } else { "s" },
+                        doc_output.display(),
+                        document.path.display()
+                    );
+                    log_event(LogLevel::Info, &message, "Output");
+                }
+                if conversion.up_to_date > 0 {
+                    let message = format!(
+                        "{} file{} up to date in {} (input: {})",
+                        conversion.up_to_date,
+                        if conversion.up_to_date == 1 { "" } else { "s" },
+                        doc_output.display(),
+                        document.path.display()
+                    );
+                    log_event(LogLevel::Info, &message, "Cache");
+                }
+                if conversion.files.is_empty() && conversion.up_to_date > 0 {
+                    unchanged += 1;
+                }
+                files_written += conversion.files.len();
+                pages_rendered += conversion.pages;
+                files_up_to_date += conversion.up_to_date;
+            }
+            Err(err) if batch => {
+                let message = format!("{}: {}", document.path.display(), err);
+                log_event(LogLevel::Error, &message, err.kind().tag());
+                failed += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
 
-    let page_set = if pages.is_empty() {
-        None
-    } else {
-        let validated_set = utils::validate_requested_pages(pages, pdf.pages().len())
-            .map_err(|msg| AppError::new("PageValidation", msg))?;
-        Some(validated_set)
-    };
+    if batch {
+        log_batch_summary(output_format.label(), documents.len(), files_written, failed, &output);
+        if incremental {
+            log_cache_summary(pages_rendered, files_up_to_date, unchanged, documents.len());
+        }
+        if failed > 0 {
+            return Err(Error::new(
+                ErrorKind::Batch,
+                format!("{} of {} documents failed to convert", failed, documents.len()),
+            ));
+        }
+    }
 
-    Ok((pdf, page_set))
+    Ok(())
 }
 
-fn process_png(
-    input: &Path,
-    output: &Path,
-    scale: f32,
-    prefix: Option<&str>,
-    pages: &[usize],
-    interpreter_settings: &InterpreterSettings,
-) -> Result<(), AppError> {
-    let (pdf, page_set) = load_pdf_and_pages(input, pages)?;
-
-    let render_settings = RenderSettings {
-        x_scale: scale,
-        y_scale: scale,
-        ..Default::default()
+/// Convert a single document into a single output file and write it to standard output.
+fn write_to_stdout(converter: &Converter, documents: &[inputs::InputDocument]) -> Result<(), Error> {
+    let [document] = documents else {
+        return Err(Error::new(
+            ErrorKind::Input,
+            format!(
+                "Writing to standard output needs a single input document, but {} were given",
+                documents.len()
+            ),
+        ));
     };
-    let prefix = utils::resolve_prefix(prefix, input);
-
-    let files_written = pdf
-        .pages()
-        .iter()
-        .enumerate()
-        .filter(|(idx, _)| {
-            page_set
-                .as_ref()
-                .map(|set| set.contains(idx))
-                .unwrap_or(true)
-        })
-        .map(|(idx, page)| {
-            let pixmap = render(page, interpreter_settings, &render_settings);
-            let out_name = format!("{}{}.png", prefix, idx + 1);
-            let out_path = output.join(out_name);
-            let png_bytes = pixmap.take_png();
-            fs::write(out_path, png_bytes)
-                .map_err(|e| AppError::new("FileSystem", format!("Failed to write PNG: {e}")))?;
-            Ok(())
-        })
-        .collect::<Result<Vec<_>, AppError>>()?
-        .len();
 
-    log_render_summary("PNG", files_written, output, input);
+    // The converter counts the output files before rendering, so a selection that gives
+    // several fails at once.
+    let rendered = if inputs::is_stdin(&document.path) {
+        converter.render_single_bytes(read_stdin()?)
+    } else {
+        converter.render_single(&document.path)
+    }
+    .map_err(|err| match err.kind() {
+        ErrorKind::Input if matches!(converter.output_format(), OutputFormat::Montage { .. }) => {
+            Error::new(
+                err.kind(),
+                format!("{err}. Raise --sheet-size or choose fewer pages with --page"),
+            )
+        }
+        _ => err,
+    })?;
+
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(&rendered.bytes)
+        .and_then(|_| stdout.flush())
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::FileSystem,
+                format!("Failed to write to standard output: {e}"),
+            )
+        })?;
 
+    let message = format!(
+        "Wrote {} to standard output (input: {})",
+        converter.output_format().label(),
+        document.path.display()
+    );
+    log_event(LogLevel::Info, &message, "Output");
     Ok(())
 }
 
-fn process_svg(
-    input: &Path,
-    output: &Path,
-    scale: f32,
-    prefix: Option<&str>,
-    pages: &[usize],
-    interpreter_settings: &InterpreterSettings,
-) -> Result<(), AppError> {
-    let (pdf, page_set) = load_pdf_and_pages(input, pages)?;
-
-    let prefix = utils::resolve_prefix(prefix, input);
-
-    let mut files_written = 0usize;
+/// Print what each document says about itself and its pages to standard output. With
+/// `--json` that is a JSON object, or an array of them for several documents.
+fn print_info(args: InfoArgs) -> Result<(), Error> {
+    /// A document report for JSON output, with the file it is about.
+    #[derive(Serialize)]
+    struct Report<'a> {
+        file: String,
+        #[serde(flatten)]
+        info: &'a DocumentInfo,
+    }
 
-    for (idx, page) in pdf.pages().iter().enumerate() {
-        if let Some(ref set) = page_set
-            && !set.contains(&idx)
-        {
-            continue;
-        }
+    // Standard output carries the report.
+    LOG_TO_STDERR.store(true, Ordering::SeqCst);
+    let documents = inputs::collect_inputs(&args.inputs, args.recursive)
+        .map_err(|msg| Error::new(ErrorKind::Input, msg))?;
+    let password = args.password.resolve()?;
+    let password = password.as_deref();
 
-        let svg = convert(page, interpreter_settings);
-        let mut out_svg = svg;
-        if (scale - 1.0).abs() > f32::EPSILON {
-            if let Some(w_pos) = out_svg.find("width=\"") {
-                let start = w_pos + 7;
-                if let Some(rel_end) = out_svg[start..].find('"') {
-                    let end = start + rel_end;
-                    if let Ok(old_w) = out_svg[start..end].parse::<f32>() {
-                        let new_w = old_w * scale;
-                        out_svg.replace_range(start..end, &format!("{:.6}", new_w));
-                    }
-                }
-            }
-            if let Some(h_pos) = out_svg.find("height=\"") {
-                let start = h_pos + 8;
-                if let Some(rel_end) = out_svg[start..].find('"') {
-                    let end = start + rel_end;
-                    if let Ok(old_h) = out_svg[start..end].parse::<f32>() {
-                        let new_h = old_h * scale;
-                        out_svg.replace_range(start..end, &format!("{:.6}", new_h));
-                    }
-                }
+    let batch = documents.len() > 1;
+    let mut reports = Vec::new();
+    let mut failed = 0usize;
+    for document in &documents {
+        let result = if inputs::is_stdin(&document.path) {
+            read_stdin().and_then(|data| inspect_bytes(data, password))
+        } else {
+            inspect(&document.path, password)
+        };
+        match result {
+            Ok(info) => reports.push((document.path.display().to_string(), info)),
+            Err(err) if batch => {
+                let message = format!("{}: {}", document.path.display(), err);
+                log_event(LogLevel::Error, &message, err.kind().tag());
+                failed += 1;
             }
+            Err(err) => return Err(err),
         }
-
-        let out_name = format!("{}{}.svg", prefix, idx + 1);
-        let out_path = output.join(out_name);
-        fs::write(out_path, out_svg)
-            .map_err(|e| AppError::new("FileSystem", format!("Failed to write SVG: {e}")))?;
-        files_written += 1;
     }
 
-    log_render_summary("SVG", files_written, output, input);
+    let text = if args.json {
+        let reports: Vec<Report> = reports
+            .iter()
+            .map(|(file, info)| Report {
+                file: file.clone(),
+                info,
+            })
+            .collect();
+        // Serializing plain structs and strings cannot fail.
+        let json = match reports.as_slice() {
+            [report] if !batch => serde_json::to_string_pretty(report),
+            reports => serde_json::to_string_pretty(reports),
+        };
+        format!("{}\n", json.unwrap_or_default())
+    } else {
+        reports
+            .iter()
+            .map(|(file, info)| format!("File:         {file}\n{info}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(text.as_bytes())
+        .and_then(|_| stdout.flush())
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::FileSystem,
+                format!("Failed to write to standard output: {e}"),
+            )
+        })?;
 
+    if failed > 0 {
+        return Err(Error::new(
+            ErrorKind::Batch,
+            format!(
+                "{failed} of {} documents could not be read",
+                documents.len()
+            ),
+        ));
+    }
     Ok(())
 }
+
+/// Read the password stored on the first line of `path`.
+fn read_password_file(path: &Path) -> Result<String, Error> {
+    let contents = fs::read_to_string(path).map_err(|e| {
+        Error::new(
+            ErrorKind::FileSystem,
+            format!("Failed to read password file {}: {e}", path.display()),
+        )
+    })?;
+    Ok(contents.lines().next().unwrap_or_default().to_string())
+}
+
+/// Read a whole PDF from standard input.
+fn read_stdin() -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut data).map_err(|e| {
+        Error::new(
+            ErrorKind::FileSystem,
+            format!("Failed to read standard input: {e}"),
+        )
+    })?;
+    Ok(data)
+}
+
+/// Whether `[input, arg]` is the legacy `<FORMAT> <INPUT> <OUTPUT>` form: `input` is a
+/// single file (or `-`), and `arg` is not one, nor a PDF or a pattern. The answer depends only
+/// on what the arguments are, never on what a directory holds, so that a command does the
+/// same each time it is run; several inputs need `-o` for the output.
+fn is_legacy_output(input: &Path, arg: &Path) -> bool {
+    let file = |path: &Path| {
+        path.is_file() || inputs::has_pdf_extension(path) || inputs::is_glob_pattern(path)
+    };
+    (inputs::is_stdin(input) || input.is_file()) && !inputs::is_stdin(arg) && !file(arg)
+}
+
+/// Parse a margin in points: a number that is zero or larger.
+fn parse_margin(s: &str) -> Result<f32, String> {
+    match s.trim().parse::<f32>() {
+        Ok(margin) if margin.is_finite() && margin >= 0.0 => Ok(margin),
+        _ => Err(format!("invalid margin '{s}': expected a number of points, 0 or larger")),
+    }
+}
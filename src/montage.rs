@@ -0,0 +1,256 @@
+use crate::render::Background;
+use crate::tiles::{MAX_PIXMAP_SIZE, fill_rect, paste_pixmap};
+use hayro::vello_cpu::Pixmap;
+use std::ops::Range;
+
+/// Colour of the frame around each thumbnail, so white pages stand out from a white sheet.
+const FRAME: [u8; 4] = [176, 176, 176, 255];
+
+/// The digits of captions, 3 x 5 units each, as rows of 3 bits from the top.
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// How the pages of a document are laid out on contact sheets: a grid of equal cells, each
+/// holding a thumbnail centred above its caption, with `spacing` pixels between the cells
+/// and around the edge of the sheet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SheetLayout {
+    /// Size of each thumbnail, in output order.
+    thumbnails: Vec<(u32, u32)>,
+    /// Factor the thumbnails were scaled down by to fit on a sheet.
+    pub shrink: f32,
+    /// Size of a cell without its caption, which fits the largest thumbnail.
+    cell: (u32, u32),
+    columns: u32,
+    /// Rows of cells on a full sheet.
+    rows: u32,
+    spacing: u32,
+    /// Size of a unit of the caption digits in pixels, 0 without captions.
+    unit: u32,
+}
+
+impl SheetLayout {
+    /// Lay out thumbnails of the given `sizes` in rows of up to `columns`, on sheets no larger
+    /// than `max_sheet` and never larger than a single pixmap can be.
+    ///
+    /// Thumbnails are scaled down uniformly when a single row of them would not fit on a
+    /// sheet. Every sheet but the last holds as many rows as fit.
+    pub fn new(
+        sizes: &[(u32, u32)],
+        columns: u32,
+        spacing: u32,
+        captions: bool,
+        max_sheet: Option<(u32, u32)>,
+    ) -> Self {
+        let limit = |v: u32| v.clamp(1, MAX_PIXMAP_SIZE);
+        let (max_width, max_height) = max_sheet
+            .map_or((MAX_PIXMAP_SIZE, MAX_PIXMAP_SIZE), |(w, h)| {
+                (limit(w), limit(h))
+            });
+        let largest = |sizes: &[(u32, u32)]| {
+            sizes
+                .iter()
+                .fold((1, 1), |(w, h), &(tw, th)| (w.max(tw), h.max(th)))
+        };
+
+        let columns = columns
+            .clamp(1, sizes.len().clamp(1, u32::MAX as usize) as u32)
+            .min(max_width);
+        let (width, height) = largest(sizes);
+        // Digits a sixtieth of the widest thumbnail wide, within reason.
+        let unit = if captions {
+            (width / 60).clamp(2, 8).min((max_height - 1) / 7)
+        } else {
+            0
+        };
+        let caption = 7 * unit;
+        // Leave at least a pixel for each thumbnail.
+        let spacing = spacing
+            .min((max_width - columns) / (columns + 1))
+            .min((max_height - 1 - caption) / 2);
+
+        let fit_width =
+            (max_width - (columns + 1) * spacing) as f64 / (columns as f64 * width as f64);
+        let fit_height = (max_height - 2 * spacing - caption) as f64 / height as f64;
+        let shrink = fit_width.min(fit_height).min(1.0);
+        let thumbnails = if shrink < 1.0 {
+            let scale = |v: u32| ((v as f64 * shrink) as u32).max(1);
+            sizes.iter().map(|&(w, h)| (scale(w), scale(h))).collect()
+        } else {
+            sizes.to_vec()
+        };
+
+        let cell = largest(&thumbnails);
+        let rows = ((max_height - spacing) / (cell.1 + caption + spacing)).max(1);
+        Self {
+            thumbnails,
+            shrink: shrink as f32,
+            cell,
+            columns,
+            rows,
+            spacing,
+            unit,
+        }
+    }
+
+    /// Number of sheets the thumbnails take.
+    pub fn sheets(&self) -> usize {
+        self.thumbnails.len().div_ceil(self.per_sheet())
+    }
+
+    /// Positions of the thumbnails on `sheet`, counted from 0.
+    pub fn pages_on(&self, sheet: usize) -> Range<usize> {
+        let start = sheet * self.per_sheet();
+        start..(start + self.per_sheet()).min(self.thumbnails.len())
+    }
+
+    /// Size of the thumbnail at `seq`, in pixels.
+    pub fn thumbnail_size(&self, seq: usize) -> (u32, u32) {
+        self.thumbnails[seq]
+    }
+
+    /// Size of `sheet` in pixels. The last sheet only has the rows it uses.
+    pub fn sheet_size(&self, sheet: usize) -> (u32, u32) {
+        let rows = (self.pages_on(sheet).len() as u32).div_ceil(self.columns);
+        (
+            self.columns * (self.cell.0 + self.spacing) + self.spacing,
+            rows * (self.cell.1 + 7 * self.unit + self.spacing) + self.spacing,
+        )
+    }
+
+    /// Put `thumbnails` of the pages on `sheet` onto a sheet filled with `background`, each
+    /// in a thin grey frame and, with captions, above its page number.
+    pub fn compose(
+        &self,
+        sheet: usize,
+        thumbnails: &[(Pixmap, usize)],
+        background: Background,
+    ) -> Pixmap {
+        let (width, height) = self.sheet_size(sheet);
+        let mut pixmap = Pixmap::new(width as u16, height as u16);
+        // Captions are black, or white on dark backgrounds.
+        let (fill, ink) = match background {
+            Background::Color([r, g, b]) => {
+                let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+                let ink = if luma < 128.0 { 255 } else { 0 };
+                ([r, g, b, 255], [ink, ink, ink, 255])
+            }
+            Background::Transparent => ([0; 4], [0, 0, 0, 255]),
+        };
+        fill_rect(&mut pixmap, (0, 0, width as i64, height as i64), fill);
+
+        let (cell_width, cell_height) = (self.cell.0 as i64, self.cell.1 as i64);
+        let spacing = self.spacing as i64;
+        let unit = self.unit as i64;
+        for (slot, (thumbnail, number)) in thumbnails.iter().enumerate() {
+            let (col, row) = (
+                slot as i64 % self.columns as i64,
+                slot as i64 / self.columns as i64,
+            );
+            let x = spacing + col * (cell_width + spacing);
+            let y = spacing + row * (cell_height + 7 * unit + spacing);
+            let (w, h) = (thumbnail.width() as i64, thumbnail.height() as i64);
+            let (tx, ty) = (x + (cell_width - w) / 2, y + (cell_height - h) / 2);
+            fill_rect(&mut pixmap, (tx - 1, ty - 1, w + 2, h + 2), FRAME);
+            paste_pixmap(&mut pixmap, thumbnail, tx as u32, ty as u32);
+
+            if unit > 0 {
+                let text = number.to_string();
+                let text_width = (4 * text.len() as i64 - 1) * unit;
+                let mut dx = x + (cell_width - text_width) / 2;
+                for digit in text.bytes() {
+                    let glyph = DIGITS[(digit - b'0') as usize];
+                    for (r, bits) in glyph.iter().enumerate() {
+                        for c in 0..3 {
+                            if bits & (0b100 >> c) != 0 {
+                                let px = dx + c * unit;
+                                let py = y + cell_height + (1 + r as i64) * unit;
+                                fill_rect(&mut pixmap, (px, py, unit, unit), ink);
+                            }
+                        }
+                    }
+                    dx += 4 * unit;
+                }
+            }
+        }
+        pixmap
+    }
+
+    fn per_sheet(&self) -> usize {
+        self.columns as usize * self.rows as usize
+    }
+}
+
+/// File names of the `sheets` of the montage `name`: `name` itself for a single sheet,
+/// otherwise `doc.png` becomes `doc-sheet1.png`, `doc-sheet2.png` and so on.
+pub fn sheet_names(name: &str, sheets: usize) -> Vec<String> {
+    if sheets <= 1 {
+        return vec![name.to_string()];
+    }
+    (1..=sheets)
+        .map(|sheet| match name.rsplit_once('.') {
+            Some((stem, ext)) => format!("{stem}-sheet{sheet}.{ext}"),
+            None => format!("{name}-sheet{sheet}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lays_out_rows_of_equal_cells() {
+        let layout = SheetLayout::new(&[(100, 150), (120, 90), (100, 150)], 2, 10, false, None);
+        assert_eq!(layout.shrink, 1.0);
+        assert_eq!(layout.sheets(), 1);
+        assert_eq!(layout.pages_on(0), 0..3);
+        // Two columns of 120 pixels and two rows of 150, with 10 pixels around each.
+        assert_eq!(layout.sheet_size(0), (270, 330));
+    }
+
+    #[test]
+    fn splits_pages_over_sheets() {
+        let sizes = [(100, 100); 7];
+        let layout = SheetLayout::new(&sizes, 3, 0, false, Some((300, 200)));
+        assert_eq!(layout.sheets(), 2);
+        assert_eq!(layout.pages_on(1), 6..7);
+        assert_eq!(layout.sheet_size(0), (300, 200));
+        assert_eq!(layout.sheet_size(1), (300, 100));
+    }
+
+    #[test]
+    fn shrinks_thumbnails_that_do_not_fit() {
+        let layout = SheetLayout::new(&[(1000, 500), (500, 500)], 2, 0, false, Some((1000, 1000)));
+        assert_eq!(layout.shrink, 0.5);
+        assert_eq!(layout.thumbnail_size(0), (500, 250));
+        assert_eq!(layout.thumbnail_size(1), (250, 250));
+        assert!(layout.sheet_size(0).0 <= 1000);
+    }
+
+    #[test]
+    fn leaves_room_for_captions() {
+        let layout = SheetLayout::new(&[(600, 800)], 4, 0, true, None);
+        // Digits of 7 units of 600 / 60 pixels, clamped to 8.
+        assert_eq!(layout.sheet_size(0), (600, 800 + 7 * 8));
+    }
+
+    #[test]
+    fn names_sheets() {
+        assert_eq!(sheet_names("doc.png", 1), ["doc.png"]);
+        assert_eq!(
+            sheet_names("doc.png", 2),
+            ["doc-sheet1.png", "doc-sheet2.png"]
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use crate::utils;
+use std::fmt;
+use std::str::FromStr;
+
+/// A placeholder of a [`NameTemplate`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    /// Input file name without its extension.
+    Stem,
+    /// 1-based page number in the document.
+    Page,
+    /// 1-based position of the output (differs from `Page` with `--ordered`).
+    Seq,
+    /// Number of pages in the document.
+    Total,
+    /// Page label, such as `iv` or `A-3`.
+    Label,
+    /// Output width in pixels (SVG: user units).
+    Width,
+    /// Output height in pixels (SVG: user units).
+    Height,
+    /// Output file extension, such as `png`.
+    Format,
+}
+
+impl Field {
+    const ALL: [(&'static str, Field); 8] = [
+        ("stem", Field::Stem),
+        ("page", Field::Page),
+        ("seq", Field::Seq),
+        ("total", Field::Total),
+        ("label", Field::Label),
+        ("width", Field::Width),
+        ("height", Field::Height),
+        ("format", Field::Format),
+    ];
+
+    fn name(self) -> &'static str {
+        Field::ALL
+            .iter()
+            .find(|(_, field)| *field == self)
+            .map(|(name, _)| *name)
+            .unwrap_or_default()
+    }
+
+    fn is_numeric(self) -> bool {
+        !matches!(self, Field::Stem | Field::Label | Field::Format)
+    }
+
+    /// Whether the value differs between the pages of a document.
+    fn is_per_page(self) -> bool {
+        !matches!(self, Field::Stem | Field::Total | Field::Format)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    /// A placeholder, zero-padded to the given width.
+    Field(Field, usize),
+}
+
+/// An output file name pattern such as `{stem}-{page:04}`.
+///
+/// Placeholders are `{stem}`, `{page}`, `{seq}`, `{total}`, `{label}`, `{width}`,
+/// `{height}` and `{format}`. Numeric placeholders accept a zero-padding width, as in
+/// `{page:04}`. The expanded name is sanitized like `--prefix` and the file extension is
+/// appended to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTemplate {
+    source: String,
+    parts: Vec<Part>,
+}
+
+/// The values substituted into a [`NameTemplate`] for one output.
+pub(crate) struct NameFields<'a> {
+    pub stem: &'a str,
+    pub page: usize,
+    pub seq: usize,
+    pub total: usize,
+    pub label: &'a str,
+    pub width: u32,
+    pub height: u32,
+    pub format: &'a str,
+}
+
+impl NameTemplate {
+    /// Parse a template, rejecting unknown placeholders and unbalanced braces.
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => spec.push(c),
+                            None => {
+                                return Err(format!(
+                                    "invalid name template '{template}': unclosed '{{'"
+                                ));
+                            }
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(parse_placeholder(template, &spec)?);
+                }
+                '}' => {
+                    return Err(format!(
+                        "invalid name template '{template}': unmatched '}}'"
+                    ));
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Self {
+            source: template.to_string(),
+            parts,
+        })
+    }
+
+    /// Placeholders whose value differs between pages, in template order.
+    pub(crate) fn per_page_fields(&self) -> Vec<&'static str> {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Field(field, _) if field.is_per_page() => Some(field.name()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether the template contains `{stem}`.
+    pub fn uses_stem(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Field(Field::Stem, _)))
+    }
+
+    /// Expand the template for one output and append the extension `ext`.
+    pub(crate) fn file_name(&self, fields: &NameFields, ext: &str) -> String {
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => name.push_str(text),
+                Part::Field(field, width) => {
+                    let value = match field {
+                        Field::Stem => fields.stem.to_string(),
+                        Field::Page => fields.page.to_string(),
+                        Field::Seq => fields.seq.to_string(),
+                        Field::Total => fields.total.to_string(),
+                        Field::Label => fields.label.to_string(),
+                        Field::Width => fields.width.to_string(),
+                        Field::Height => fields.height.to_string(),
+                        Field::Format => fields.format.to_string(),
+                    };
+                    name.push_str(&format!("{value:0>width$}"));
+                }
+            }
+        }
+
+        let mut name = utils::sanitize_prefix_raw(&name);
+        if name.is_empty() {
+            name = "rendered".to_string();
+        }
+        format!("{name}.{ext}")
+    }
+}
+
+/// Parse the inside of a `{...}` placeholder: a field name and an optional `:WIDTH`.
+fn parse_placeholder(template: &str, spec: &str) -> Result<Part, String> {
+    let (name, width) = match spec.split_once(':') {
+        Some((name, width)) => (name.trim(), Some(width.trim())),
+        None => (spec.trim(), None),
+    };
+    let field = Field::ALL
+        .iter()
+        .find(|(candidate, _)| candidate.eq_ignore_ascii_case(name))
+        .map(|(_, field)| *field)
+        .ok_or_else(|| {
+            let valid = Field::ALL
+                .iter()
+                .map(|(name, _)| format!("{{{name}}}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("invalid name template '{template}': unknown placeholder '{{{spec}}}' (expected one of {valid})")
+        })?;
+
+    let width = match width {
+        None => 0,
+        Some(width) if field.is_numeric() => width.parse::<usize>().map_err(|_| {
+            format!("invalid name template '{template}': '{{{spec}}}' needs a numeric width such as {{{name}:04}}")
+        })?,
+        Some(_) => {
+            return Err(format!(
+                "invalid name template '{template}': '{{{name}}}' does not take a width"
+            ));
+        }
+    };
+    Ok(Part::Field(field, width))
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for NameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> NameFields<'static> {
+        NameFields {
+            stem: "report",
+            page: 7,
+            seq: 2,
+            total: 120,
+            label: "iv",
+            width: 800,
+            height: 600,
+            format: "png",
+        }
+    }
+
+    fn expand(template: &str) -> String {
+        NameTemplate::parse(template)
+            .unwrap()
+            .file_name(&fields(), "png")
+    }
+
+    #[test]
+    fn expands_placeholders_with_padding() {
+        assert_eq!(expand("{stem}-{page:04}"), "report-0007.png");
+        assert_eq!(expand("{seq:3}_of_{total}"), "002_of_120.png");
+        assert_eq!(
+            expand("{label}_{width}x{height}-{format}"),
+            "iv_800x600-png.png"
+        );
+        assert_eq!(expand("{PAGE}"), "7.png");
+    }
+
+    #[test]
+    fn sanitizes_expanded_names() {
+        assert_eq!(expand("a b/{page}"), "a-b-7.png");
+        assert_eq!(expand("///"), "rendered.png");
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        let error = |template| NameTemplate::parse(template).unwrap_err();
+        assert!(error("{page").contains("unclosed '{'"));
+        assert!(error("page}").contains("unmatched '}'"));
+        assert!(error("{pages}").contains("unknown placeholder '{pages}'"));
+        assert!(error("{page:x}").contains("numeric width"));
+        assert!(error("{stem:4}").contains("does not take a width"));
+    }
+
+    #[test]
+    fn lists_per_page_fields() {
+        let template = NameTemplate::parse("{stem}-{label}-{total}-{seq}").unwrap();
+        assert_eq!(template.per_page_fields(), ["label", "seq"]);
+        assert!(template.uses_stem());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::utils::sequence_number;
+use clap::ValueEnum;
+
+/// The order pages are placed in along each row of an N-up output.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[value(rename_all = "lower")]
+pub enum ReadingOrder {
+    /// Left to right.
+    #[default]
+    Ltr,
+    /// Right to left, as in Arabic, Hebrew or Japanese publications.
+    Rtl,
+}
+
+/// Consecutive pages put side by side into one output (`--nup`): `columns` x `rows` pages,
+/// filled row by row in reading `order`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Nup {
+    pub columns: u32,
+    pub rows: u32,
+    /// Order the pages for a saddle-stitched booklet instead. Each output is one side of a
+    /// sheet, so the sheets read in page order once printed on both sides, stacked and folded.
+    /// Needs two pages per output; the page count is padded to a multiple of four with
+    /// blank pages.
+    pub booklet: bool,
+    pub order: ReadingOrder,
+}
+
+impl Nup {
+    /// Put `columns` x `rows` consecutive pages into each output, left to right.
+    pub fn new(columns: u32, rows: u32) -> Self {
+        Self {
+            columns: columns.max(1),
+            rows: rows.max(1),
+            booklet: false,
+            order: ReadingOrder::Ltr,
+        }
+    }
+
+    pub(crate) fn pages_per_output(&self) -> usize {
+        self.columns as usize * self.rows as usize
+    }
+
+    /// The pages of every output for `count` selected pages, as positions in the selection in
+    /// reading order. `None` marks a blank page of a booklet.
+    pub(crate) fn groups(&self, count: usize) -> Vec<Vec<Option<usize>>> {
+        if self.booklet {
+            let total = count.div_ceil(4) * 4;
+            let page = |i: usize| (i < count).then_some(i);
+            // The outer sheet holds the last and first pages on its front, the second and
+            // second to last on its back, and so on inwards.
+            return (0..total / 4)
+                .flat_map(|sheet| {
+                    let (first, last) = (2 * sheet, total - 1 - 2 * sheet);
+                    [
+                        vec![page(last), page(first)],
+                        vec![page(first + 1), page(last - 1)],
+                    ]
+                })
+                .collect();
+        }
+        (0..count)
+            .collect::<Vec<_>>()
+            .chunks(self.pages_per_output())
+            .map(|chunk| chunk.iter().copied().map(Some).collect())
+            .collect()
+    }
+
+    /// Size of an output holding pages of the given sizes, keyed by their reading position,
+    /// and the top-left corner of each page. Every cell fits the largest page, and pages are
+    /// centred in their cells.
+    pub(crate) fn place(&self, pages: &[(usize, (u32, u32))]) -> ((u32, u32), Vec<(u32, u32)>) {
+        let (cell_width, cell_height) = pages
+            .iter()
+            .fold((1, 1), |(w, h), &(_, (pw, ph))| (w.max(pw), h.max(ph)));
+        let positions = pages
+            .iter()
+            .map(|&(slot, (width, height))| {
+                let (col, row) = self.cell(slot);
+                (
+                    col * cell_width + (cell_width - width) / 2,
+                    row * cell_height + (cell_height - height) / 2,
+                )
+            })
+            .collect();
+        (
+            (self.columns * cell_width, self.rows * cell_height),
+            positions,
+        )
+    }
+
+    /// File name of the `seq`-th of `total` outputs (0-based) holding the document pages
+    /// `pages`, like [`crate::utils::output_file_name`]: `<prefix><first>-<last>.<ext>`, or
+    /// `<prefix><seq>-p<first>-<last>.<ext>` in ordered mode. Booklets name the sides of
+    /// their sheets instead: `<prefix>sheet<n>-front.<ext>` and `-back`.
+    pub(crate) fn output_name(
+        &self,
+        prefix: &str,
+        seq: usize,
+        total: usize,
+        pages: &[usize],
+        ordered: bool,
+        ext: &str,
+    ) -> String {
+        if self.booklet {
+            let side = if seq.is_multiple_of(2) { "front" } else { "back" };
+            return format!("{prefix}sheet{}-{side}.{ext}", seq / 2 + 1);
+        }
+        let range = match pages {
+            [] => String::new(),
+            [page] => format!("{}", page + 1),
+            [first, .., last] => format!("{}-{}", first + 1, last + 1),
+        };
+        if ordered {
+            format!("{prefix}{}-p{range}.{ext}", sequence_number(seq, total))
+        } else {
+            format!("{prefix}{range}.{ext}")
+        }
+    }
+
+    /// Column and row of the page at reading position `slot`.
+    fn cell(&self, slot: usize) -> (u32, u32) {
+        let (col, row) = (slot as u32 % self.columns, slot as u32 / self.columns);
+        match self.order {
+            ReadingOrder::Ltr => (col, row),
+            ReadingOrder::Rtl => (self.columns - 1 - col, row),
+        }
+    }
+}
+
+/// Parse an N-up layout such as `2x1` (two pages side by side) or `2x2`: columns x rows.
+pub fn parse_nup(s: &str) -> Result<Nup, String> {
+    let invalid = || format!("invalid N-up layout '{s}': expected COLUMNSxROWS, e.g. 2x1");
+    let (columns, rows) = s.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+    let columns: u32 = columns.trim().parse().map_err(|_| invalid())?;
+    let rows: u32 = rows.trim().parse().map_err(|_| invalid())?;
+    if !(1..=64).contains(&columns) || !(1..=64).contains(&rows) {
+        return Err(invalid());
+    }
+    Ok(Nup::new(columns, rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_pages_in_reading_order() {
+        let groups = Nup::new(2, 2).groups(6);
+        assert_eq!(
+            groups,
+            [
+                vec![Some(0), Some(1), Some(2), Some(3)],
+                vec![Some(4), Some(5)]
+            ]
+        );
+    }
+
+    #[test]
+    fn booklets_pad_to_whole_sheets() {
+        let booklet = Nup {
+            booklet: true,
+            ..Nup::new(2, 1)
+        };
+        assert_eq!(
+            booklet.groups(6),
+            [
+                vec![None, Some(0)],
+                vec![Some(1), None],
+                vec![Some(5), Some(2)],
+                vec![Some(3), Some(4)],
+            ]
+        );
+        assert_eq!(
+            booklet.output_name("zine-", 3, 4, &[3, 4], false, "png"),
+            "zine-sheet2-back.png"
+        );
+    }
+
+    #[test]
+    fn centres_pages_in_cells_of_the_largest_page() {
+        let nup = Nup::new(2, 1);
+        let (size, positions) = nup.place(&[(0, (100, 200)), (1, (80, 100))]);
+        assert_eq!(size, (200, 200));
+        assert_eq!(positions, [(0, 0), (110, 50)]);
+
+        let rtl = Nup {
+            order: ReadingOrder::Rtl,
+            ..nup
+        };
+        let (_, positions) = rtl.place(&[(0, (100, 200)), (1, (80, 100))]);
+        assert_eq!(positions, [(100, 0), (10, 50)]);
+    }
+
+    #[test]
+    fn parses_layouts() {
+        assert_eq!(parse_nup("2x1"), Ok(Nup::new(2, 1)));
+        assert_eq!(parse_nup(" 3 X 2 "), Ok(Nup::new(3, 2)));
+        assert!(parse_nup("0x1").is_err());
+        assert!(parse_nup("65x1").is_err());
+        assert!(parse_nup("2").is_err());
+    }
+
+    #[test]
+    fn names_outputs_after_their_pages() {
+        let nup = Nup::new(2, 1);
+        assert_eq!(
+            nup.output_name("doc-", 0, 5, &[0, 1], false, "png"),
+            "doc-1-2.png"
+        );
+        assert_eq!(
+            nup.output_name("doc-", 4, 5, &[8], false, "png"),
+            "doc-9.png"
+        );
+        assert_eq!(
+            nup.output_name("doc-", 1, 12, &[6, 3], true, "png"),
+            "doc-02-p7-4.png"
+        );
+    }
+}
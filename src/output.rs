@@ -0,0 +1,238 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// What to do when an output file already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExistingFiles {
+    /// Fail before anything is written (`--no-clobber`). A file that appears while pages are
+    /// rendered is not replaced either: writing it fails.
+    #[default]
+    Fail,
+    /// Keep the existing file and do not render its page (`--skip-existing`).
+    Skip,
+    /// Replace the existing file (`--overwrite`).
+    Overwrite,
+}
+
+/// A file that only appears at its final path once it is completely written.
+///
+/// Data goes to a hidden temporary file next to the destination, which [`AtomicFile::commit`]
+/// renames into place. If the file is dropped without being committed, for example because
+/// encoding failed, the temporary file is removed and the destination is left untouched.
+/// Temporary files of runs that were killed are removed by [`remove_stale_temp_files`].
+pub struct AtomicFile {
+    path: PathBuf,
+    temp: PathBuf,
+    file: Option<BufWriter<File>>,
+    replace: bool,
+}
+
+impl AtomicFile {
+    /// Start writing the file that will end up at `path`, replacing any file there when
+    /// `replace` is set. Otherwise committing fails with [`io::ErrorKind::AlreadyExists`] if
+    /// a file has appeared at `path` in the meantime.
+    pub fn create(path: &Path, replace: bool) -> io::Result<Self> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let temp = path.with_file_name(format!(".{name}.{}.part", std::process::id()));
+        let file = File::create(&temp)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            temp,
+            file: Some(BufWriter::new(file)),
+            replace,
+        })
+    }
+
+    /// Flush the data and move the file to its final path.
+    pub fn commit(mut self) -> io::Result<()> {
+        let result = self.finish();
+        if result.is_err() {
+            let _ = fs::remove_file(&self.temp);
+        }
+        result
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(file) = self.file.take() {
+            let file = file.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+        }
+        if self.replace {
+            return fs::rename(&self.temp, &self.path);
+        }
+        // Linking fails if the destination exists, where renaming would replace it.
+        match fs::hard_link(&self.temp, &self.path) {
+            Ok(()) => fs::remove_file(&self.temp),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(e),
+            // File systems without hard links can only check first.
+            Err(_) if self.path.exists() => Err(io::ErrorKind::AlreadyExists.into()),
+            Err(_) => fs::rename(&self.temp, &self.path),
+        }
+    }
+
+    fn file(&mut self) -> &mut BufWriter<File> {
+        // Only `commit` takes the file, and it consumes `self`.
+        self.file.as_mut().expect("file is open until committed")
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file().flush()
+    }
+}
+
+impl Seek for AtomicFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file().seek(pos)
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// Write `bytes` to `path` through an [`AtomicFile`], replacing any file there when `replace`
+/// is set.
+pub fn write_atomic(path: &Path, bytes: &[u8], replace: bool) -> io::Result<()> {
+    let mut file = AtomicFile::create(path, replace)?;
+    file.write_all(bytes)?;
+    file.commit()
+}
+
+/// How long a temporary file must have been left untouched before it is taken as left
+/// behind, when it cannot be told whether the process that wrote it is still running.
+const STALE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Remove the temporary files that runs killed while writing the files `names` in `dir` left
+/// behind: those of processes that are no longer running, or untouched for [`STALE_AGE`].
+/// Files of processes that may still be writing them, this one included, are kept.
+pub fn remove_stale_temp_files(dir: &Path, names: &HashSet<&str>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        let Some((name, pid)) = file_name.to_str().and_then(temp_target) else {
+            continue;
+        };
+        if !names.contains(name) || pid == std::process::id() {
+            continue;
+        }
+        let untouched = entry
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok());
+        if process_running(pid) == Some(false) || untouched.is_some_and(|age| age >= STALE_AGE) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Whether the process `pid` of this machine is running, where `/proc` tells; `None`
+/// elsewhere.
+fn process_running(pid: u32) -> Option<bool> {
+    let proc = Path::new("/proc");
+    proc.join("self")
+        .exists()
+        .then(|| proc.join(pid.to_string()).exists())
+}
+
+/// The file name the temporary file of an [`AtomicFile`] is written for, and the process
+/// writing it: `page-1.png` and 1234 for `.page-1.png.1234.part`.
+fn temp_target(temp: &str) -> Option<(&str, u32)> {
+    let rest = temp.strip_prefix('.')?.strip_suffix(".part")?;
+    let (name, pid) = rest.rsplit_once('.')?;
+    if !pid.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((name, pid.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_dir;
+    use std::time::SystemTime;
+
+    #[test]
+    fn keeps_files_that_appear_while_writing() {
+        let dir = test_dir("no-replace");
+        let path = dir.join("page-1.png");
+        let mut file = AtomicFile::create(&path, false).unwrap();
+        file.write_all(b"new").unwrap();
+        fs::write(&path, b"other").unwrap();
+        let err = file.commit().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&path).unwrap(), b"other");
+
+        write_atomic(&path, b"new", true).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        write_atomic(&dir.join("page-2.png"), b"new", false).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn removes_temp_files_of_processes_that_are_gone() {
+        let dir = test_dir("stale");
+        // No process has a number this large; process 1 always runs.
+        let own = format!(".page-1.png.{}.part", std::process::id());
+        let files = [
+            ".page-1.png.4000000000.part",
+            ".page-2.png.4000000000.part",
+            ".page-2.png.1.part",
+            ".other.png.4000000000.part",
+            ".page-1.png.part",
+            &own,
+        ];
+        for name in files {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        let left = || {
+            fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect::<HashSet<_>>()
+        };
+        let names = HashSet::from(["page-1.png", "page-2.png"]);
+        remove_stale_temp_files(&dir, &names);
+        let mut kept = vec![
+            ".other.png.4000000000.part",
+            ".page-1.png.part",
+            ".page-2.png.1.part",
+            &own,
+        ];
+        // Without `/proc` only the age counts, and all the files are new.
+        if process_running(1).is_none() {
+            kept.extend([".page-1.png.4000000000.part", ".page-2.png.4000000000.part"]);
+        }
+        assert_eq!(left(), kept.iter().map(|name| name.to_string()).collect());
+
+        // A file left untouched for long enough goes whoever wrote it.
+        let file = File::options()
+            .write(true)
+            .open(dir.join(".page-2.png.1.part"))
+            .unwrap();
+        file.set_modified(SystemTime::now() - STALE_AGE).unwrap();
+        remove_stale_temp_files(&dir, &names);
+        assert!(!left().contains(".page-2.png.1.part"));
+        assert!(left().contains(&own));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
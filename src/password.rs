@@ -0,0 +1,274 @@
+use crate::trailer::Trailer;
+use hayro::hayro_syntax::object::{Array, Name, String as PdfString};
+use md5::{Digest, Md5};
+
+/// The string passwords are padded or cut to 32 bytes with before hashing.
+const PADDING: [u8; 32] = [
+    0x28, 0xBF, 0x4E, 0x5E, 0x4E, 0x75, 0x8A, 0x41, 0x64, 0x00, 0x4E, 0x56, 0xFF, 0xFA, 0x01, 0x08,
+    0x2E, 0x2E, 0x00, 0xB6, 0xD0, 0x68, 0x3E, 0x80, 0x2F, 0x0C, 0xA9, 0xFE, 0x64, 0x53, 0x69, 0x7A,
+];
+
+/// The user password of a document encrypted with RC4 or AES-128, recovered from its
+/// `owner` password (algorithm 7 of the PDF specification). hayro only checks passwords as
+/// user passwords for these schemes; AES-256 accepts the owner password by itself.
+///
+/// The password is returned as the bytes it was encrypted as, which need not be UTF-8.
+/// `None` when the document is not encrypted that way or `owner` is not its owner password.
+pub(crate) fn user_password_from_owner(data: &[u8], owner: &str) -> Option<Vec<u8>> {
+    let trailer = Trailer::read(data)?;
+    let encrypt = trailer.dict(b"Encrypt")?;
+    if encrypt.get::<Name>(b"Filter")?.as_ref() != b"Standard" {
+        return None;
+    }
+    let revision = encrypt.get::<u8>(b"R").filter(|r| (2..=4).contains(r))?;
+    // Key lengths in bits, with the defaults hayro uses.
+    let bits = match encrypt.get::<u8>(b"V")? {
+        1 => 40,
+        2 => encrypt.get::<u16>(b"Length").unwrap_or(40),
+        4 => encrypt.get::<u16>(b"Length").unwrap_or(128),
+        _ => return None,
+    };
+    let key_length = if revision == 2 { 5 } else { bits as usize / 8 };
+    let owner_entry = encrypt.get::<PdfString>(b"O")?;
+    let user_entry = encrypt.get::<PdfString>(b"U")?;
+    if !(5..=16).contains(&key_length)
+        || owner_entry.as_bytes().len() < 32
+        || user_entry.as_bytes().len() < 32
+    {
+        return None;
+    }
+    let security = Security {
+        revision,
+        key_length,
+        owner_entry: owner_entry.as_bytes()[..32].to_vec(),
+        permissions: encrypt.get::<i64>(b"P")? as i32,
+        id: trailer
+            .with_key(b"ID")
+            .and_then(|dict| dict.get::<Array>(b"ID"))
+            .and_then(|ids| ids.iter::<PdfString>().next())
+            .map(|id| id.as_bytes().to_vec())
+            .unwrap_or_default(),
+        encrypt_metadata: encrypt.get::<bool>(b"EncryptMetadata").unwrap_or(true),
+    };
+
+    let key = owner_key(owner.as_bytes(), revision, key_length);
+    let mut padded = security.owner_entry.clone();
+    if revision == 2 {
+        padded = rc4(&key, &padded);
+    } else {
+        for i in (0..20).rev() {
+            let round_key: Vec<u8> = key.iter().map(|b| b ^ i).collect();
+            padded = rc4(&round_key, &padded);
+        }
+    }
+
+    // The password is followed by as much of the padding as makes up 32 bytes. Any owner
+    // password decrypts `O` to something, so the result is checked against `U`.
+    let len = (0..=32).find(|&len| padded[len..] == PADDING[..32 - len])?;
+    padded.truncate(len);
+    let compared = if revision == 2 { 32 } else { 16 };
+    let expected = security.user_entry(&security.file_key(&padded));
+    (expected[..compared] == user_entry.as_bytes()[..compared]).then_some(padded)
+}
+
+/// What the keys of a document encrypted with RC4 or AES-128 are made from.
+struct Security {
+    revision: u8,
+    /// The key length in bytes.
+    key_length: usize,
+    /// The first 32 bytes of the `O` entry.
+    owner_entry: Vec<u8>,
+    permissions: i32,
+    /// The first element of the trailer's `ID`.
+    id: Vec<u8>,
+    encrypt_metadata: bool,
+}
+
+impl Security {
+    /// The key the document is encrypted with, from its user password (algorithm 2).
+    fn file_key(&self, user: &[u8]) -> Vec<u8> {
+        let mut input = pad(user).to_vec();
+        input.extend_from_slice(&self.owner_entry);
+        input.extend_from_slice(&self.permissions.to_le_bytes());
+        input.extend_from_slice(&self.id);
+        if self.revision >= 4 && !self.encrypt_metadata {
+            input.extend_from_slice(&[0xFF; 4]);
+        }
+        let mut hash = md5(&input);
+        if self.revision >= 3 {
+            for _ in 0..50 {
+                hash = md5(&hash[..self.key_length]);
+            }
+        }
+        hash[..self.key_length].to_vec()
+    }
+
+    /// The `U` entry for the file key `key` (algorithms 4 and 5). From revision 3 only its
+    /// first 16 bytes are defined.
+    fn user_entry(&self, key: &[u8]) -> Vec<u8> {
+        if self.revision == 2 {
+            return rc4(key, &PADDING);
+        }
+        let mut input = PADDING.to_vec();
+        input.extend_from_slice(&self.id);
+        let mut entry = rc4(key, &md5(&input));
+        for i in 1..20 {
+            let round_key: Vec<u8> = key.iter().map(|b| b ^ i).collect();
+            entry = rc4(&round_key, &entry);
+        }
+        entry.resize(32, 0);
+        entry
+    }
+}
+
+/// The RC4 key the `O` entry is encrypted with, from the owner password (algorithm 3,
+/// steps a to d).
+fn owner_key(owner: &[u8], revision: u8, key_length: usize) -> Vec<u8> {
+    let mut hash = md5(&pad(owner));
+    if revision >= 3 {
+        for _ in 0..50 {
+            hash = md5(&hash);
+        }
+    }
+    hash[..key_length].to_vec()
+}
+
+/// `password` padded or cut to 32 bytes.
+fn pad(password: &[u8]) -> [u8; 32] {
+    let len = password.len().min(32);
+    let mut padded = [0; 32];
+    padded[..len].copy_from_slice(&password[..len]);
+    padded[len..].copy_from_slice(&PADDING[..32 - len]);
+    padded
+}
+
+fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut state: [u8; 256] = std::array::from_fn(|i| i as u8);
+    let mut j = 0u8;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[i as usize]);
+            state.swap(i as usize, j as usize);
+            byte ^ state[state[i as usize].wrapping_add(state[j as usize]) as usize]
+        })
+        .collect()
+}
+
+fn md5(data: &[u8]) -> [u8; 16] {
+    Md5::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converter::load_pdf;
+    use crate::error::ErrorKind;
+    use crate::testing::{blank_pages_pdf, hex};
+
+    const ID: &[u8] = b"0123456789abcdef";
+    const PERMISSIONS: i32 = -3904;
+
+    /// A one-page PDF encrypted with 128-bit RC4 (revision 3), or 40-bit RC4 (revision 2),
+    /// made as algorithms 2, 3 and 5 of the PDF specification describe.
+    fn encrypted_pdf(user: &[u8], owner: &str, revision: u8) -> Vec<u8> {
+        let key_length = if revision == 2 { 5 } else { 16 };
+        let owner_key = owner_key(owner.as_bytes(), revision, key_length);
+        let mut o = rc4(&owner_key, &pad(user));
+        if revision >= 3 {
+            for i in 1..20u8 {
+                let round_key: Vec<u8> = owner_key.iter().map(|b| b ^ i).collect();
+                o = rc4(&round_key, &o);
+            }
+        }
+
+        let security = Security {
+            revision,
+            key_length,
+            owner_entry: o.clone(),
+            permissions: PERMISSIONS,
+            id: ID.to_vec(),
+            encrypt_metadata: true,
+        };
+        let u = security.user_entry(&security.file_key(user));
+
+        let (v, bits) = if revision == 2 { (1, 40) } else { (2, 128) };
+        let encrypt = format!(
+            "<< /Filter /Standard /V {v} /R {revision} /Length {bits} /O <{}> /U <{}> /P {PERMISSIONS} >>",
+            hex(&o),
+            hex(&u)
+        );
+        let id = hex(ID);
+        blank_pages_pdf(
+            &[(200, 300)],
+            &[encrypt],
+            &format!("/Encrypt 4 0 R /ID [<{id}> <{id}>]"),
+        )
+    }
+
+    fn open(pdf: Vec<u8>, password: Option<&str>) -> Result<usize, ErrorKind> {
+        load_pdf(pdf, password)
+            .map(|pdf| pdf.pages().len())
+            .map_err(|e| e.kind())
+    }
+
+    #[test]
+    fn opens_with_user_password() {
+        assert_eq!(
+            open(encrypted_pdf(b"secret", "owner", 3), Some("secret")),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn opens_with_owner_password() {
+        assert_eq!(
+            open(encrypted_pdf(b"secret", "owner", 3), Some("owner")),
+            Ok(1)
+        );
+        assert_eq!(
+            open(encrypted_pdf(b"secret", "owner", 2), Some("owner")),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn recovers_user_password_from_owner_password() {
+        let pdf = encrypted_pdf(b"secret", "owner", 3);
+        assert_eq!(
+            user_password_from_owner(&pdf, "owner").as_deref(),
+            Some(&b"secret"[..])
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_password() {
+        let pdf = encrypted_pdf(b"secret", "owner", 3);
+        assert_eq!(open(pdf.clone(), Some("guess")), Err(ErrorKind::Encrypted));
+        assert_eq!(open(pdf, None), Err(ErrorKind::Encrypted));
+    }
+
+    #[test]
+    fn empty_user_password_opens_with_any_password() {
+        let pdf = encrypted_pdf(b"", "owner", 3);
+        assert_eq!(open(pdf.clone(), None), Ok(1));
+        assert_eq!(open(pdf.clone(), Some("unrelated")), Ok(1));
+        assert_eq!(open(pdf, Some("owner")), Ok(1));
+    }
+
+    #[test]
+    fn keeps_user_passwords_that_are_not_utf8() {
+        let pdf = encrypted_pdf(b"caf\xE9", "owner", 3);
+        assert_eq!(
+            user_password_from_owner(&pdf, "owner").as_deref(),
+            Some(&b"caf\xE9"[..])
+        );
+        assert_eq!(user_password_from_owner(&pdf, "guess"), None);
+        assert_eq!(open(pdf, Some("owner")), Err(ErrorKind::Encrypted));
+    }
+}
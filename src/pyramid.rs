@@ -0,0 +1,206 @@
+use crate::tiles::MAX_PIXMAP_SIZE;
+use std::ops::RangeInclusive;
+
+/// How the tiles of a [`Pyramid`] are laid out on disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PyramidLayout {
+    /// Deep Zoom: a `.dzi` descriptor and `<name>_files/<level>/<col>_<row>.png`, with level 0
+    /// a single pixel and the last level the full image.
+    DeepZoom,
+    /// Map tiles: a JSON descriptor and `<name>/<z>/<x>/<y>.png`, with zoom 0 a single tile
+    /// and the last zoom the full image. Every tile is square, edge tiles included.
+    Xyz,
+}
+
+/// A multi-resolution tile pyramid of an image of `width` x `height` pixels. Each level
+/// halves the size of the one above it, rounding up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pyramid {
+    layout: PyramidLayout,
+    pub width: u32,
+    pub height: u32,
+    tile_size: u32,
+    overlap: u32,
+    /// The level holding the full image.
+    top: u32,
+}
+
+impl Pyramid {
+    /// The pyramid of an image with square tiles of `tile_size` pixels that share `overlap`
+    /// pixels with each neighbour.
+    pub fn new(
+        layout: PyramidLayout,
+        width: u32,
+        height: u32,
+        tile_size: u32,
+        overlap: u32,
+    ) -> Self {
+        let overlap = overlap.min(MAX_PIXMAP_SIZE / 4);
+        let tile_size = tile_size.clamp(1, MAX_PIXMAP_SIZE - 2 * overlap);
+        let size = width.max(height).max(1) as u64;
+        // Deep Zoom goes down to a single pixel, map tiles to a single tile.
+        let smallest = match layout {
+            PyramidLayout::DeepZoom => 1,
+            PyramidLayout::Xyz => tile_size as u64,
+        };
+        let mut top = 0;
+        while smallest << top < size {
+            top += 1;
+        }
+        Self {
+            layout,
+            width: width.max(1),
+            height: height.max(1),
+            tile_size,
+            overlap,
+            top,
+        }
+    }
+
+    pub fn levels(&self) -> RangeInclusive<u32> {
+        0..=self.top
+    }
+
+    /// Scale of `level` relative to the full image.
+    pub fn level_scale(&self, level: u32) -> f32 {
+        0.5f32.powi((self.top - level) as i32)
+    }
+
+    /// Size of the image at `level`, in pixels.
+    pub fn level_size(&self, level: u32) -> (u32, u32) {
+        let shrink = |v: u32| (v as u64).div_ceil(1 << (self.top - level)) as u32;
+        (shrink(self.width), shrink(self.height))
+    }
+
+    /// Number of tile columns and rows at `level`.
+    pub fn grid(&self, level: u32) -> (u32, u32) {
+        let (width, height) = self.level_size(level);
+        (
+            width.div_ceil(self.tile_size),
+            height.div_ceil(self.tile_size),
+        )
+    }
+
+    /// The pixel rectangle `(x, y, width, height)` of the tile at `col` and `row` of `level`,
+    /// including the pixels it shares with its neighbours.
+    pub fn tile(&self, level: u32, col: u32, row: u32) -> (u32, u32, u16, u16) {
+        let (width, height) = self.level_size(level);
+        let span = |index: u32, size: u32| {
+            let start = (index * self.tile_size).saturating_sub(self.overlap);
+            let end = ((index + 1) * self.tile_size + self.overlap).min(size);
+            (start, (end - start) as u16)
+        };
+        let (x, tile_width) = span(col, width);
+        let (y, tile_height) = span(row, height);
+        (x, y, tile_width, tile_height)
+    }
+
+    /// Size every tile is filled up to with the background, for map viewers that stretch
+    /// smaller tiles over a whole cell. `None` for Deep Zoom, whose edge tiles end with the
+    /// image.
+    pub fn padded_tile_size(&self) -> Option<u16> {
+        match self.layout {
+            PyramidLayout::DeepZoom => None,
+            PyramidLayout::Xyz => Some(self.tile_size as u16),
+        }
+    }
+
+    /// File name of a tile of the pyramid whose descriptor is `name`.
+    pub fn tile_name(&self, name: &str, level: u32, col: u32, row: u32) -> String {
+        let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+        match self.layout {
+            PyramidLayout::DeepZoom => format!("{stem}_files/{level}/{col}_{row}.png"),
+            PyramidLayout::Xyz => format!("{stem}/{level}/{col}/{row}.png"),
+        }
+    }
+
+    /// Names of every file of the pyramid whose descriptor is `name`: the tiles level by
+    /// level, then the descriptor.
+    pub fn file_names(&self, name: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        for level in self.levels() {
+            let (cols, rows) = self.grid(level);
+            for row in 0..rows {
+                for col in 0..cols {
+                    names.push(self.tile_name(name, level, col, row));
+                }
+            }
+        }
+        names.push(name.to_string());
+        names
+    }
+
+    /// Contents of the descriptor viewers load the pyramid from.
+    pub fn descriptor(&self) -> String {
+        match self.layout {
+            PyramidLayout::DeepZoom => format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" Format=\"png\" \
+                 Overlap=\"{}\" TileSize=\"{}\">\n  <Size Width=\"{}\" Height=\"{}\"/>\n</Image>\n",
+                self.overlap, self.tile_size, self.width, self.height
+            ),
+            PyramidLayout::Xyz => {
+                let descriptor = serde_json::json!({
+                    "width": self.width,
+                    "height": self.height,
+                    "tileSize": self.tile_size,
+                    "minZoom": 0,
+                    "maxZoom": self.top,
+                    "format": "png",
+                });
+                format!("{descriptor:#}\n")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deep_zoom_goes_down_to_a_single_pixel() {
+        let pyramid = Pyramid::new(PyramidLayout::DeepZoom, 1000, 600, 254, 1);
+        assert_eq!(pyramid.levels(), 0..=10);
+        assert_eq!(pyramid.level_size(0), (1, 1));
+        assert_eq!(pyramid.level_size(9), (500, 300));
+        assert_eq!(pyramid.level_size(10), (1000, 600));
+        assert_eq!(pyramid.grid(10), (4, 3));
+    }
+
+    #[test]
+    fn deep_zoom_tiles_overlap_and_stop_at_the_edge() {
+        let pyramid = Pyramid::new(PyramidLayout::DeepZoom, 1000, 600, 254, 1);
+        assert_eq!(pyramid.tile(10, 0, 0), (0, 0, 255, 255));
+        assert_eq!(pyramid.tile(10, 1, 0), (253, 0, 256, 255));
+        assert_eq!(pyramid.tile(10, 3, 2), (761, 507, 239, 93));
+        assert_eq!(pyramid.padded_tile_size(), None);
+        assert_eq!(
+            pyramid.tile_name("doc-1.dzi", 10, 3, 2),
+            "doc-1_files/10/3_2.png"
+        );
+    }
+
+    #[test]
+    fn map_tiles_start_from_a_single_tile() {
+        let pyramid = Pyramid::new(PyramidLayout::Xyz, 1000, 600, 256, 0);
+        assert_eq!(pyramid.levels(), 0..=2);
+        assert_eq!(pyramid.grid(0), (1, 1));
+        assert_eq!(pyramid.level_size(0), (250, 150));
+        assert_eq!(pyramid.grid(2), (4, 3));
+        assert_eq!(pyramid.tile(2, 3, 2), (768, 512, 232, 88));
+        assert_eq!(pyramid.padded_tile_size(), Some(256));
+        assert_eq!(pyramid.tile_name("doc-1.json", 2, 3, 1), "doc-1/2/3/1.png");
+        // 1 + 4 + 12 tiles and the descriptor.
+        assert_eq!(pyramid.file_names("doc-1.json").len(), 18);
+    }
+
+    #[test]
+    fn limits_tiles_to_a_pixmap() {
+        let pyramid = Pyramid::new(PyramidLayout::DeepZoom, 100, 100, u32::MAX, u32::MAX);
+        let (_, _, width, _) = pyramid.tile(pyramid.top, 0, 0);
+        assert_eq!(width, 100);
+        let pyramid = Pyramid::new(PyramidLayout::Xyz, 200_000, 10, 100_000, 0);
+        assert_eq!(pyramid.padded_tile_size(), Some(u16::MAX));
+    }
+}
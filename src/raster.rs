@@ -0,0 +1,251 @@
+use crate::color::{self, ColorMode, Dither};
+use hayro::vello_cpu::Pixmap;
+use image::codecs::jpeg::JpegEncoder;
+use image::{ExtendedColorType, ImageEncoder};
+use png::{BitDepth, ColorType};
+
+/// How a rendered pixmap is encoded into a raster file.
+#[derive(Clone, Copy, Debug)]
+pub enum RasterEncoding {
+    Png,
+    /// Lossy JPEG. Transparent areas are flattened onto `background`.
+    Jpeg { quality: u8, background: [u8; 3] },
+    /// WebP, either lossy with the given `quality` or lossless.
+    Webp { quality: u8, lossless: bool },
+}
+
+impl RasterEncoding {
+    /// Encode `pixmap` into the bytes of a complete image file in the colour model `color`.
+    pub fn encode(
+        &self,
+        pixmap: Pixmap,
+        color: ColorMode,
+        dither: Dither,
+    ) -> Result<Vec<u8>, String> {
+        let width = pixmap.width() as u32;
+        let height = pixmap.height() as u32;
+        match *self {
+            RasterEncoding::Png => match color {
+                ColorMode::Rgb => pixmap
+                    .into_png()
+                    .map_err(|e| format!("Failed to encode PNG: {e}")),
+                ColorMode::Gray => {
+                    encode_gray_png(&color::gray8(&pixmap), width, height, BitDepth::Eight)
+                }
+                ColorMode::Gray16 => {
+                    let samples: Vec<u8> = color::gray16(&pixmap)
+                        .iter()
+                        .flat_map(|value| value.to_be_bytes())
+                        .collect();
+                    encode_gray_png(&samples, width, height, BitDepth::Sixteen)
+                }
+                ColorMode::Mono => {
+                    let pixels = color::mono(&pixmap, dither);
+                    let samples = color::pack_bits(&pixels, width as usize, false);
+                    encode_gray_png(&samples, width, height, BitDepth::One)
+                }
+            },
+            RasterEncoding::Jpeg {
+                quality,
+                background,
+            } => {
+                let (samples, color_type) = match color {
+                    ColorMode::Rgb => (
+                        flatten_premultiplied(pixmap.data_as_u8_slice(), background),
+                        ExtendedColorType::Rgb8,
+                    ),
+                    _ => (gray_levels(&pixmap, color, dither), ExtendedColorType::L8),
+                };
+                let mut out = Vec::new();
+                JpegEncoder::new_with_quality(&mut out, quality)
+                    .write_image(&samples, width, height, color_type)
+                    .map_err(|e| format!("Failed to encode JPEG: {e}"))?;
+                Ok(out)
+            }
+            RasterEncoding::Webp { quality, lossless } => {
+                // WebP has no greyscale model, so grey pixels are stored as RGB.
+                let samples;
+                let encoder = match color {
+                    ColorMode::Rgb => {
+                        samples = unpremultiply(pixmap.data_as_u8_slice());
+                        webp::Encoder::from_rgba(&samples, width, height)
+                    }
+                    _ => {
+                        samples = gray_levels(&pixmap, color, dither)
+                            .iter()
+                            .flat_map(|&level| [level; 3])
+                            .collect();
+                        webp::Encoder::from_rgb(&samples, width, height)
+                    }
+                };
+                let encoded = encoder
+                    .encode_simple(lossless, quality as f32)
+                    .map_err(|e| format!("Failed to encode WebP: {e:?}"))?;
+                Ok(encoded.to_vec())
+            }
+        }
+    }
+}
+
+/// Encode greyscale samples, already packed for `depth`, as a PNG file.
+fn encode_gray_png(
+    samples: &[u8],
+    width: u32,
+    height: u32,
+    depth: BitDepth,
+) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(ColorType::Grayscale);
+    encoder.set_depth(depth);
+    encoder
+        .write_header()
+        .and_then(|mut writer| {
+            writer.write_image_data(samples)?;
+            writer.finish()
+        })
+        .map_err(|e| format!("Failed to encode PNG: {e}"))?;
+    Ok(out)
+}
+
+/// One byte per pixel for formats limited to 8-bit greyscale: grey levels, or black and
+/// white for [`ColorMode::Mono`].
+fn gray_levels(pixmap: &Pixmap, color: ColorMode, dither: Dither) -> Vec<u8> {
+    match color {
+        ColorMode::Mono => color::mono(pixmap, dither)
+            .iter()
+            .map(|&black| if black { 0 } else { 255 })
+            .collect(),
+        _ => color::gray8(pixmap),
+    }
+}
+
+/// Composite premultiplied RGBA8 pixels over an opaque `background`, producing RGB8.
+fn flatten_premultiplied(data: &[u8], background: [u8; 3]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 4 * 3);
+    for px in data.chunks_exact(4) {
+        let inv_alpha = 255 - px[3] as u32;
+        for c in 0..3 {
+            let value = px[c] as u32 + (background[c] as u32 * inv_alpha + 127) / 255;
+            out.push(value.min(255) as u8);
+        }
+    }
+    out
+}
+
+/// Convert premultiplied RGBA8 pixels to straight (non-premultiplied) RGBA8.
+fn unpremultiply(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for px in data.chunks_exact(4) {
+        let a = px[3] as u32;
+        if a == 0 {
+            out.extend_from_slice(&[0, 0, 0, 0]);
+        } else if a == 255 {
+            out.extend_from_slice(px);
+        } else {
+            for &c in &px[..3] {
+                out.push(((c as u32 * 255 + a / 2) / a).min(255) as u8);
+            }
+            out.push(a as u8);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::pixmap;
+    use image::ImageFormat;
+    use webp::{BitstreamFeatures, BitstreamFormat};
+
+    const CLEAR: [u8; 4] = [0; 4];
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+    /// 16 x 16 pixels: a transparent top half over an opaque pattern.
+    fn page() -> Pixmap {
+        let pixels: Vec<[u8; 4]> = (0..256u32)
+            .map(|i| match i {
+                0..128 => CLEAR,
+                255 => BLACK,
+                _ => [
+                    (i * 73 % 256) as u8,
+                    (i * 151 % 256) as u8,
+                    (i * 29 % 256) as u8,
+                    255,
+                ],
+            })
+            .collect();
+        pixmap(16, 16, &pixels)
+    }
+
+    fn jpeg(quality: u8, color: ColorMode) -> Vec<u8> {
+        let encoding = RasterEncoding::Jpeg {
+            quality,
+            background: [255, 128, 0],
+        };
+        encoding.encode(page(), color, Dither::Threshold).unwrap()
+    }
+
+    fn close(a: &[u8], b: &[u8]) -> bool {
+        a.iter().zip(b).all(|(a, b)| a.abs_diff(*b) <= 12)
+    }
+
+    #[test]
+    fn flattens_transparency_onto_the_jpeg_background() {
+        let data = jpeg(90, ColorMode::Rgb);
+        assert_eq!(image::guess_format(&data).unwrap(), ImageFormat::Jpeg);
+        let decoded = image::load_from_memory(&data).unwrap().to_rgb8();
+        assert_eq!(decoded.dimensions(), (16, 16));
+        assert!(close(&decoded.get_pixel(0, 0).0, &[255, 128, 0]));
+        assert!(close(&decoded.get_pixel(15, 0).0, &[255, 128, 0]));
+
+        let gray = image::load_from_memory(&jpeg(90, ColorMode::Gray)).unwrap();
+        assert_eq!(gray.color(), image::ColorType::L8);
+        assert_eq!((gray.width(), gray.height()), (16, 16));
+    }
+
+    #[test]
+    fn higher_jpeg_quality_gives_larger_files() {
+        let low = jpeg(20, ColorMode::Rgb);
+        let high = jpeg(95, ColorMode::Rgb);
+        assert!(high.len() > low.len(), "{} <= {}", high.len(), low.len());
+    }
+
+    #[test]
+    fn keeps_transparency_in_webp() {
+        let encode = |lossless, color| {
+            let encoding = RasterEncoding::Webp {
+                quality: 80,
+                lossless,
+            };
+            encoding.encode(page(), color, Dither::Threshold).unwrap()
+        };
+
+        let data = encode(true, ColorMode::Rgb);
+        assert_eq!(image::guess_format(&data).unwrap(), ImageFormat::WebP);
+        let features = BitstreamFeatures::new(&data).unwrap();
+        assert!(matches!(features.format(), Some(BitstreamFormat::Lossless)));
+        assert!(features.has_alpha());
+        let decoded = webp::Decoder::new(&data).decode().unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (16, 16));
+        assert_eq!(decoded[3], 0);
+        assert_eq!(decoded[255 * 4..], BLACK);
+        assert_eq!(
+            decoded[200 * 4..201 * 4],
+            page().data_as_u8_slice()[200 * 4..201 * 4]
+        );
+
+        let data = encode(false, ColorMode::Rgb);
+        let features = BitstreamFeatures::new(&data).unwrap();
+        assert!(matches!(features.format(), Some(BitstreamFormat::Lossy)));
+        assert_eq!((features.width(), features.height()), (16, 16));
+
+        // Grey pages have no alpha channel, and are composited onto white.
+        let data = encode(true, ColorMode::Gray);
+        let decoded = webp::Decoder::new(&data).decode().unwrap();
+        assert!(!decoded.is_alpha());
+        assert_eq!(decoded[..3], [255, 255, 255]);
+        assert_eq!(decoded[255 * 3..], [0, 0, 0]);
+    }
+}
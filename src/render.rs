@@ -0,0 +1,83 @@
+use crate::crop::PageView;
+use crate::sizing::Sizing;
+use hayro::kurbo::Affine;
+use hayro::vello_cpu::color::AlphaColor;
+use hayro::vello_cpu::color::palette::css::TRANSPARENT;
+use hayro::vello_cpu::{Pixmap, RasterizerSettings, RenderContext, Resources, TargetInit};
+use hayro::{RenderCache, RenderSettings, render_into};
+use hayro_interpret::InterpreterSettings;
+use hayro_interpret::hayro_syntax::page::Page;
+
+/// What pages are rendered onto.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Background {
+    /// An opaque colour, such as white paper.
+    Color([u8; 3]),
+    /// Nothing: areas the page does not paint stay transparent.
+    Transparent,
+}
+
+impl Background {
+    pub const WHITE: Background = Background::Color([255, 255, 255]);
+}
+
+/// Render the area `view` of `page` onto `background`, sized according to `sizing`.
+///
+/// Returns the pixmap together with the scale factor it was rendered at. Unlike
+/// `hayro::render`, the pixmap always has the exact size chosen by `sizing`, so pixel
+/// targets are never off by one.
+pub fn rasterize(
+    page: &Page,
+    view: &PageView,
+    sizing: &Sizing,
+    background: Background,
+    settings: &InterpreterSettings,
+) -> (Pixmap, f32) {
+    let scale = sizing.scale_for(view.width, view.height);
+    let (width, height) = sizing.pixel_size_for(view.width, view.height);
+    let clamp = |v: u32| v.min(u16::MAX as u32) as u16;
+    let tile = (0, 0, clamp(width), clamp(height));
+    let pixmap = rasterize_tile(page, view, scale, tile, background, settings);
+    (pixmap, scale)
+}
+
+/// Render the part of `view` scaled by `scale` that lies in the pixel rectangle `tile`,
+/// given as `(x, y, width, height)`.
+pub fn rasterize_tile(
+    page: &Page,
+    view: &PageView,
+    scale: f32,
+    (x, y, width, height): (u32, u32, u16, u16),
+    background: Background,
+    settings: &InterpreterSettings,
+) -> Pixmap {
+    let mut ctx = RenderContext::new(width, height);
+    let transform = Affine::translate((-(x as f64), -(y as f64)))
+        * Affine::scale(scale as f64)
+        * view.transform;
+    // The cache is not thread-safe, and pages are rendered on several threads.
+    let cache = RenderCache::new();
+    render_into(
+        page,
+        &cache,
+        settings,
+        &RenderSettings::default(),
+        &mut ctx,
+        transform,
+    );
+    ctx.flush();
+
+    let mut pixmap = Pixmap::new(width, height);
+    ctx.render_with(
+        &mut pixmap,
+        &mut Resources::default(),
+        RasterizerSettings {
+            target_init: match background {
+                Background::Color([r, g, b]) => TargetInit::Clear(AlphaColor::from_rgb8(r, g, b)),
+                Background::Transparent => TargetInit::Clear(TRANSPARENT),
+            },
+            ..Default::default()
+        },
+    );
+    pixmap
+}
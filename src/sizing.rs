@@ -0,0 +1,159 @@
+
+/// PDF user space units per inch.
+const POINTS_PER_INCH: f32 = 72.0;
+
+/// How the output size of each page is determined.
+///
+/// Pages in a document can differ in size, so everything except a plain scale factor is
+/// resolved per page from its dimensions in points.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sizing {
+    /// Fixed scale factor (`--scale`).
+    Scale(f32),
+    /// Target resolution in dots per inch (`--dpi`).
+    Dpi(f32),
+    /// Target width in pixels, height follows the aspect ratio (`--width`).
+    Width(u32),
+    /// Target height in pixels, width follows the aspect ratio (`--height`).
+    Height(u32),
+    /// Largest size that fits inside a `W`x`H` pixel box (`--fit`).
+    Fit(u32, u32),
+}
+
+impl Sizing {
+    /// Scale factor for a page that is `width` x `height` points large.
+    pub fn scale_for(&self, width: f32, height: f32) -> f32 {
+        match *self {
+            Sizing::Scale(scale) => scale,
+            Sizing::Dpi(dpi) => dpi / POINTS_PER_INCH,
+            Sizing::Width(w) => w as f32 / width,
+            Sizing::Height(h) => h as f32 / height,
+            Sizing::Fit(w, h) => (w as f32 / width).min(h as f32 / height),
+        }
+    }
+
+    /// Pixel dimensions for a page of `width` x `height` points.
+    ///
+    /// Plain scale factors and DPI truncate like the renderer always has. Pixel targets are
+    /// rounded so that `--width 800` yields exactly 800 pixels.
+    pub fn pixel_size_for(&self, width: f32, height: f32) -> (u32, u32) {
+        let scale = self.scale_for(width, height);
+        let round = |v: f32| v.round().clamp(1.0, u32::MAX as f32) as u32;
+        let truncate = |v: f32| v.clamp(1.0, u32::MAX as f32) as u32;
+        match *self {
+            Sizing::Scale(_) | Sizing::Dpi(_) => (truncate(width * scale), truncate(height * scale)),
+            Sizing::Width(w) => (round(w as f32), round(height * scale)),
+            Sizing::Height(h) => (round(width * scale), round(h as f32)),
+            Sizing::Fit(..) => (round(width * scale), round(height * scale)),
+        }
+    }
+}
+
+/// Parse a positive resolution in dots per inch.
+pub fn parse_dpi(s: &str) -> Result<f32, String> {
+    match s.trim().parse::<f32>() {
+        Ok(dpi) if dpi.is_finite() && dpi > 0.0 => Ok(dpi),
+        _ => Err(format!("invalid DPI '{s}': expected a positive number")),
+    }
+}
+
+/// Parse a `WxH` pixel box such as `1920x1080`.
+pub fn parse_fit(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid size '{s}': expected WIDTHxHEIGHT, e.g. 1920x1080");
+    let (w, h) = s
+        .trim()
+        .split_once(['x', 'X'])
+        .ok_or_else(invalid)?;
+    let w: u32 = w.trim().parse().map_err(|_| invalid())?;
+    let h: u32 = h.trim().parse().map_err(|_| invalid())?;
+    if w == 0 || h == 0 {
+        return Err(invalid());
+    }
+    Ok((w, h))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::build_pdf;
+    use crate::{Converter, OutputFormat};
+
+    #[test]
+    fn scales_by_resolution_or_factor() {
+        assert_eq!(Sizing::Dpi(144.0).scale_for(200.0, 300.0), 2.0);
+        assert_eq!(Sizing::Dpi(72.0).pixel_size_for(612.0, 792.0), (612, 792));
+        // Fractions of a pixel are cut off.
+        assert_eq!(Sizing::Scale(1.5).pixel_size_for(101.0, 33.0), (151, 49));
+        assert_eq!(Sizing::Scale(0.001).pixel_size_for(200.0, 300.0), (1, 1));
+    }
+
+    #[test]
+    fn scales_each_page_to_pixel_targets() {
+        let width = Sizing::Width(800);
+        assert_eq!(width.pixel_size_for(200.0, 300.0), (800, 1200));
+        assert_eq!(width.pixel_size_for(300.0, 200.0), (800, 533));
+
+        let height = Sizing::Height(600);
+        assert_eq!(height.pixel_size_for(300.0, 200.0), (900, 600));
+        assert_eq!(height.pixel_size_for(612.0, 792.0), (464, 600));
+
+        let fit = Sizing::Fit(1000, 1000);
+        assert_eq!(fit.scale_for(200.0, 300.0), 1000.0 / 300.0);
+        assert_eq!(fit.pixel_size_for(200.0, 300.0), (667, 1000));
+        assert_eq!(fit.pixel_size_for(300.0, 200.0), (1000, 667));
+        assert_eq!(fit.pixel_size_for(100.0, 100.0), (1000, 1000));
+    }
+
+    #[test]
+    fn sizes_rotated_and_mixed_pages_by_their_rendered_dimensions() {
+        let pages = [
+            "/MediaBox [0 0 200 300]",
+            "/MediaBox [0 0 200 300] /Rotate 90",
+            "/MediaBox [0 0 400 100]",
+        ];
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R 4 0 R 5 0 R] /Count 3 >>".to_string(),
+        ];
+        objects.extend(
+            pages
+                .iter()
+                .map(|entries| format!("<< /Type /Page /Parent 2 0 R {entries} >>")),
+        );
+        let pdf = build_pdf(&objects, "");
+
+        let sizes = |sizing| {
+            let outputs = Converter::new(OutputFormat::Png)
+                .sizing(sizing)
+                .render_bytes(pdf.clone())
+                .unwrap();
+            outputs
+                .iter()
+                .map(|output| (output.pages[0].width, output.pages[0].height))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            sizes(Sizing::Dpi(144.0)),
+            [(400, 600), (600, 400), (800, 200)]
+        );
+        assert_eq!(
+            sizes(Sizing::Width(300)),
+            [(300, 450), (300, 200), (300, 75)]
+        );
+        assert_eq!(
+            sizes(Sizing::Fit(300, 300)),
+            [(200, 300), (300, 200), (300, 75)]
+        );
+    }
+
+    #[test]
+    fn parses_resolutions_and_boxes() {
+        assert_eq!(parse_dpi(" 150 "), Ok(150.0));
+        assert!(parse_dpi("0").is_err());
+        assert!(parse_dpi("inf").is_err());
+        assert_eq!(parse_fit("1920x1080"), Ok((1920, 1080)));
+        assert_eq!(parse_fit("800 X 600"), Ok((800, 600)));
+        assert!(parse_fit("0x600").is_err());
+        assert!(parse_fit("800").is_err());
+    }
+}
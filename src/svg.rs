@@ -0,0 +1,509 @@
+use std::ops::Range;
+
+/// CSS pixels per inch, used to convert absolute SVG lengths into user units.
+const PX_PER_INCH: f32 = 96.0;
+
+/// Scale an SVG document by rewriting the size of its root `<svg>` element.
+///
+/// The root `width` and `height` are multiplied by `scale`, keeping their units. The
+/// drawing is kept intact through the `viewBox`: an existing one is left untouched, and one
+/// matching the original size is added when the root has none. Nested elements are never
+/// modified.
+pub fn scale_svg(svg: &str, scale: f32) -> Result<String, String> {
+    let tag = find_root_tag(svg).ok_or("SVG output has no root <svg> element")?;
+    let attrs = parse_attributes(svg, tag.clone())?;
+    let find = |name: &str| attrs.iter().find(|attr| attr.name == name);
+
+    let width = find("width").ok_or("SVG root element has no width")?;
+    let height = find("height").ok_or("SVG root element has no height")?;
+    let (w, w_unit) = parse_length(&svg[width.value.clone()])?;
+    let (h, h_unit) = parse_length(&svg[height.value.clone()])?;
+
+    // Edits are applied back to front so earlier ranges stay valid.
+    let mut edits = vec![
+        (
+            width.value.clone(),
+            format!("{}{w_unit}", format_number(w * scale)),
+        ),
+        (
+            height.value.clone(),
+            format!("{}{h_unit}", format_number(h * scale)),
+        ),
+    ];
+    if find("viewBox").is_none() {
+        // Without a viewBox, one user unit is one CSS pixel.
+        let view_box = format!(
+            " viewBox=\"0 0 {} {}\"",
+            format_number(w * unit_to_px(w_unit)),
+            format_number(h * unit_to_px(h_unit))
+        );
+        let at = tag.start + "<svg".len();
+        edits.push((at..at, view_box));
+    }
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+
+    let mut out = svg.to_string();
+    for (range, text) in edits {
+        out.replace_range(range, &text);
+    }
+    Ok(out)
+}
+
+/// Limit an SVG document to the area `width` x `height` at `x`, `y` of its drawing.
+///
+/// The root `viewBox` is replaced, and `width` and `height` are set to the size of the area
+/// in user units. Nested elements are never modified.
+pub fn set_view_box(svg: &str, x: f64, y: f64, width: f32, height: f32) -> Result<String, String> {
+    let tag = find_root_tag(svg).ok_or("SVG output has no root <svg> element")?;
+    let attrs = parse_attributes(svg, tag.clone())?;
+    let values = [
+        (
+            "viewBox",
+            format!(
+                "{} {} {} {}",
+                format_number(x as f32),
+                format_number(y as f32),
+                format_number(width),
+                format_number(height)
+            ),
+        ),
+        ("width", format_number(width)),
+        ("height", format_number(height)),
+    ];
+
+    // Edits are applied back to front so earlier ranges stay valid.
+    let mut edits = Vec::new();
+    for (name, value) in values {
+        match attrs.iter().find(|attr| attr.name == name) {
+            Some(attr) => edits.push((attr.value.clone(), value)),
+            None => {
+                let at = tag.start + "<svg".len();
+                edits.push((at..at, format!(" {name}=\"{value}\"")));
+            }
+        }
+    }
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+
+    let mut out = svg.to_string();
+    for (range, text) in edits {
+        out.replace_range(range, &text);
+    }
+    Ok(out)
+}
+
+/// Place the drawing of an SVG document into a `width` x `height` area through the affine
+/// `matrix` (`[a, b, c, d, e, f]` as in SVG's `matrix()`).
+///
+/// A plain translation only moves the `viewBox`. Anything else, such as a rotation, wraps
+/// the content of the root element in a transformed group.
+pub fn transform_svg(svg: &str, matrix: [f64; 6], width: f32, height: f32) -> Result<String, String> {
+    let [a, b, c, d, e, f] = matrix;
+    let near = |value: f64, target: f64| (value - target).abs() < 1e-9;
+    if near(a, 1.0) && near(b, 0.0) && near(c, 0.0) && near(d, 1.0) {
+        return set_view_box(svg, -e, -f, width, height);
+    }
+
+    let svg = set_view_box(svg, 0.0, 0.0, width, height)?;
+    let tag = find_root_tag(&svg).ok_or("SVG output has no root <svg> element")?;
+    let close = svg
+        .rfind("</svg>")
+        .filter(|&close| close > tag.end)
+        .ok_or("SVG root element is empty")?;
+    let group = format!(
+        "<g transform=\"matrix({})\">",
+        matrix
+            .iter()
+            .map(|&value| format_number(value as f32))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+
+    let mut out = svg.clone();
+    out.insert_str(close, "</g>");
+    out.insert_str(tag.end + 1, &group);
+    Ok(out)
+}
+
+/// Insert an opaque rectangle of colour `rgb` behind the drawing of an SVG document.
+///
+/// The rectangle covers the `viewBox` of the root element, or the whole viewport when there
+/// is none, and is inserted as its first child so everything else is painted over it.
+pub fn add_background(svg: &str, rgb: [u8; 3]) -> Result<String, String> {
+    let tag = find_root_tag(svg).ok_or("SVG output has no root <svg> element")?;
+    if svg[..tag.end].ends_with('/') {
+        return Err("SVG root element is empty".to_string());
+    }
+    let attrs = parse_attributes(svg, tag.clone())?;
+    let view_box = attrs
+        .iter()
+        .find(|attr| attr.name == "viewBox")
+        .map(|attr| {
+            svg[attr.value.clone()]
+                .split(|c: char| c == ',' || c.is_ascii_whitespace())
+                .filter(|part| !part.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .filter(|parts| parts.len() == 4);
+    let geometry = match view_box {
+        Some(parts) => format!(
+            "x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"",
+            parts[0], parts[1], parts[2], parts[3]
+        ),
+        None => "width=\"100%\" height=\"100%\"".to_string(),
+    };
+    let [r, g, b] = rgb;
+    let rect = format!("<rect {geometry} fill=\"#{r:02x}{g:02x}{b:02x}\"/>");
+
+    let mut out = svg.to_string();
+    out.insert_str(tag.end + 1, &rect);
+    Ok(out)
+}
+
+/// Put the drawings of several SVG documents side by side in a new `width` x `height`
+/// document, each given with the position of its top-left corner.
+///
+/// Every root element is embedded unchanged in a translated group, except that the ids it
+/// defines, and its references to them, get a prefix per page so pages cannot clash.
+pub fn impose(pages: &[(String, f32, f32)], width: f32, height: f32) -> Result<String, String> {
+    let (width, height) = (format_number(width), format_number(height));
+    let mut out = format!(
+        "<svg viewBox=\"0 0 {width} {height}\" width=\"{width}\" height=\"{height}\" \
+         xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\">\n"
+    );
+    for (i, (svg, x, y)) in pages.iter().enumerate() {
+        let tag = find_root_tag(svg).ok_or("SVG output has no root <svg> element")?;
+        let page = prefix_ids(&svg[tag.start..], &format!("p{}-", i + 1));
+        out.push_str(&format!(
+            "<g transform=\"translate({} {})\">\n{}\n</g>\n",
+            format_number(*x),
+            format_number(*y),
+            page.trim_end()
+        ));
+    }
+    out.push_str("</svg>\n");
+    Ok(out)
+}
+
+/// Prefix every id defined in an SVG document, and every local reference to one
+/// (`href="#id"` and `url(#id)`), with `prefix`.
+fn prefix_ids(svg: &str, prefix: &str) -> String {
+    const MARKERS: [&str; 5] = ["id=\"", "id='", "href=\"#", "href='#", "url(#"];
+    let mut out = String::with_capacity(svg.len());
+    let mut pos = 0;
+    while let Some((at, marker)) = MARKERS
+        .iter()
+        .filter_map(|marker| svg[pos..].find(marker).map(|at| (pos + at, marker)))
+        .min_by_key(|&(at, _)| at)
+    {
+        let end = at + marker.len();
+        out.push_str(&svg[pos..end]);
+        // `id=` must be a whole attribute name, not the end of one such as `data-id=`.
+        let whole =
+            !marker.starts_with("id") || svg[..at].ends_with(|c: char| c.is_ascii_whitespace());
+        if whole {
+            out.push_str(prefix);
+        }
+        pos = end;
+    }
+    out.push_str(&svg[pos..]);
+    out
+}
+
+/// An attribute of the root element, with the byte range of its unquoted value.
+struct Attribute<'a> {
+    name: &'a str,
+    value: Range<usize>,
+}
+
+/// Byte range of the root `<svg ...>` start tag, from `<` up to (excluding) its closing `>`.
+///
+/// The XML declaration, comments, processing instructions and a doctype may precede it.
+fn find_root_tag(svg: &str) -> Option<Range<usize>> {
+    let mut pos = 0;
+    loop {
+        let start = pos + svg[pos..].find('<')?;
+        let rest = &svg[start..];
+        if rest.starts_with("<!--") {
+            pos = start + rest.find("-->")? + 3;
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            pos = start + rest.find('>')? + 1;
+        } else if rest.starts_with("<svg")
+            && rest[4..].starts_with(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+        {
+            let end = start + tag_end(rest)?;
+            return Some(start..end);
+        } else {
+            return None;
+        }
+    }
+}
+
+/// Offset of the `>` closing a start tag, skipping over quoted attribute values.
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tag.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parse the attributes of the start tag at `tag` in `svg`.
+fn parse_attributes(svg: &str, tag: Range<usize>) -> Result<Vec<Attribute<'_>>, String> {
+    let invalid = || "SVG root element has malformed attributes".to_string();
+    let mut attrs = Vec::new();
+    let mut pos = tag.start + "<svg".len();
+
+    loop {
+        let rest = &svg[pos..tag.end];
+        let trimmed = rest.trim_start();
+        pos += rest.len() - trimmed.len();
+        if trimmed.is_empty() || trimmed == "/" {
+            return Ok(attrs);
+        }
+
+        let name_len = trimmed
+            .find(|c: char| c == '=' || c.is_ascii_whitespace())
+            .ok_or_else(invalid)?;
+        let name = &svg[pos..pos + name_len];
+        pos += name_len;
+
+        let rest = &svg[pos..tag.end];
+        let after_eq = rest.trim_start().strip_prefix('=').ok_or_else(invalid)?;
+        let value = after_eq.trim_start();
+        pos += rest.len() - value.len();
+
+        let quote = value
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '"' | '\''))
+            .ok_or_else(invalid)?;
+        let len = value[1..].find(quote).ok_or_else(invalid)?;
+        attrs.push(Attribute {
+            name,
+            value: pos + 1..pos + 1 + len,
+        });
+        pos += len + 2;
+    }
+}
+
+/// Split an SVG length such as `612`, `210mm` or `8.5in` into its number and unit.
+fn parse_length(value: &str) -> Result<(f32, &str), String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| c.is_ascii_alphabetic() || c == '%')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|n| n.is_finite() && *n >= 0.0);
+    match (number, unit) {
+        (Some(number), "" | "px" | "pt" | "pc" | "mm" | "cm" | "in") => Ok((number, unit)),
+        _ => Err(format!("Unsupported SVG root size '{value}'")),
+    }
+}
+
+/// CSS pixels per one `unit`, for the units accepted by [`parse_length`].
+fn unit_to_px(unit: &str) -> f32 {
+    match unit {
+        "pt" => PX_PER_INCH / 72.0,
+        "pc" => PX_PER_INCH / 6.0,
+        "mm" => PX_PER_INCH / 25.4,
+        "cm" => PX_PER_INCH / 2.54,
+        "in" => PX_PER_INCH,
+        _ => 1.0,
+    }
+}
+
+/// Format a length with at most four decimals and no trailing zeros.
+fn format_number(value: f32) -> String {
+    let text = format!("{value:.4}");
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    if text.is_empty() || text == "-" {
+        "0".to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::content_page_pdf;
+    use hayro::hayro_syntax::Pdf;
+    use hayro_interpret::InterpreterSettings;
+    use std::sync::Arc;
+
+    fn hayro_svg_output(width: u32, height: u32) -> String {
+        let pdf = content_page_pdf(width, height, "1 0 0 rg 10 10 50 50 re f");
+        let pdf = Pdf::new(Arc::new(pdf)).unwrap();
+        hayro_svg::convert(
+            &pdf.pages()[0],
+            &hayro_svg::RenderCache::new(),
+            &InterpreterSettings::default(),
+            &hayro_svg::SvgRenderSettings::default(),
+        )
+    }
+
+    fn root_attr(svg: &str, name: &str) -> Option<String> {
+        let tag = find_root_tag(svg).unwrap();
+        let attrs = parse_attributes(svg, tag).unwrap();
+        attrs
+            .iter()
+            .find(|attr| attr.name == name)
+            .map(|attr| svg[attr.value.clone()].to_string())
+    }
+
+    #[test]
+    fn scales_real_hayro_svg_output() {
+        let svg = hayro_svg_output(200, 300);
+        let view_box = root_attr(&svg, "viewBox");
+
+        let scaled = scale_svg(&svg, 2.0).unwrap();
+        assert_eq!(root_attr(&scaled, "width").as_deref(), Some("400"));
+        assert_eq!(root_attr(&scaled, "height").as_deref(), Some("600"));
+        assert_eq!(root_attr(&scaled, "viewBox"), view_box);
+        // Only the root tag changes.
+        let body = |s: &str| s[s.find('>').unwrap()..].to_string();
+        assert_eq!(body(&scaled), body(&svg));
+    }
+
+    #[test]
+    fn adds_view_box_from_original_size() {
+        let svg = hayro_svg_output(100, 50);
+        let tag = find_root_tag(&svg).unwrap();
+        let attrs = parse_attributes(&svg, tag).unwrap();
+        let view_box = attrs.iter().find(|attr| attr.name == "viewBox").unwrap();
+        // Strip the viewBox together with its leading space.
+        let mut stripped = svg.clone();
+        stripped.replace_range(
+            view_box.value.start - "viewBox=\"".len() - 1..view_box.value.end + 1,
+            "",
+        );
+        assert_eq!(root_attr(&stripped, "viewBox"), None);
+
+        let scaled = scale_svg(&stripped, 0.5).unwrap();
+        assert_eq!(root_attr(&scaled, "viewBox").as_deref(), Some("0 0 100 50"));
+        assert_eq!(root_attr(&scaled, "width").as_deref(), Some("50"));
+        assert_eq!(root_attr(&scaled, "height").as_deref(), Some("25"));
+    }
+
+    #[test]
+    fn ignores_nested_size_attributes() {
+        let svg = "<?xml version=\"1.0\"?>\n<!-- width=\"1\" -->\n<svg xmlns=\"http://www.w3.org/2000/svg\" height='20' width=\"10\"><rect width=\"5\" height=\"5\"/></svg>";
+        let scaled = scale_svg(svg, 3.0).unwrap();
+        assert!(scaled.contains("<!-- width=\"1\" -->"));
+        assert!(scaled.contains("<svg viewBox=\"0 0 10 20\" xmlns="));
+        assert!(scaled.contains("height='60' width=\"30\">"));
+        assert!(scaled.contains("<rect width=\"5\" height=\"5\"/>"));
+    }
+
+    #[test]
+    fn keeps_units_and_converts_them_for_view_box() {
+        let svg = "<svg width=\"72pt\" height=\"25.4mm\"></svg>";
+        let scaled = scale_svg(svg, 1.5).unwrap();
+        assert_eq!(
+            scaled,
+            "<svg viewBox=\"0 0 96 96\" width=\"108pt\" height=\"38.1mm\"></svg>"
+        );
+
+        let svg = "<svg viewBox=\"0 0 10 10\" width=\"10px\" height=\"10px\"/>";
+        assert_eq!(
+            scale_svg(svg, 2.0).unwrap(),
+            "<svg viewBox=\"0 0 10 10\" width=\"20px\" height=\"20px\"/>"
+        );
+    }
+
+    #[test]
+    fn inserts_background_behind_drawing() {
+        let svg = hayro_svg_output(200, 300);
+        let with_background = add_background(&svg, [0x12, 0x34, 0xab]).unwrap();
+
+        let tag = find_root_tag(&with_background).unwrap();
+        let rest = &with_background[tag.end + 1..];
+        assert!(
+            rest.starts_with(
+                "<rect x=\"0\" y=\"0\" width=\"200\" height=\"300\" fill=\"#1234ab\"/>"
+            )
+        );
+        // Scaling afterwards keeps the rectangle in user units.
+        let scaled = scale_svg(&with_background, 2.0).unwrap();
+        assert!(scaled.contains("width=\"200\" height=\"300\" fill=\"#1234ab\""));
+        assert!(add_background("<svg/>", [0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn limits_view_box_to_an_area() {
+        let svg = hayro_svg_output(200, 300);
+        let cropped = set_view_box(&svg, 10.0, 20.5, 50.0, 60.25).unwrap();
+        assert_eq!(
+            root_attr(&cropped, "viewBox").as_deref(),
+            Some("10 20.5 50 60.25")
+        );
+        assert_eq!(root_attr(&cropped, "width").as_deref(), Some("50"));
+        assert_eq!(root_attr(&cropped, "height").as_deref(), Some("60.25"));
+
+        let cropped = set_view_box("<svg><g/></svg>", 1.0, 2.0, 3.0, 4.0).unwrap();
+        assert!(cropped.starts_with("<svg"));
+        assert_eq!(root_attr(&cropped, "viewBox").as_deref(), Some("1 2 3 4"));
+    }
+
+    #[test]
+    fn wraps_rotated_content_in_a_group() {
+        let svg = hayro_svg_output(200, 300);
+        let moved = transform_svg(&svg, [1.0, 0.0, 0.0, 1.0, -10.0, -20.0], 50.0, 60.0).unwrap();
+        assert_eq!(root_attr(&moved, "viewBox").as_deref(), Some("10 20 50 60"));
+        assert!(!moved.contains("<g transform"));
+
+        let rotated = transform_svg(&svg, [0.0, 1.0, -1.0, 0.0, 300.0, 0.0], 300.0, 200.0).unwrap();
+        assert_eq!(root_attr(&rotated, "viewBox").as_deref(), Some("0 0 300 200"));
+        assert_eq!(root_attr(&rotated, "width").as_deref(), Some("300"));
+        let tag = find_root_tag(&rotated).unwrap();
+        assert!(rotated[tag.end + 1..].starts_with("<g transform=\"matrix(0 1 -1 0 300 0)\">"));
+        assert!(rotated.trim_end().ends_with("</g></svg>"));
+    }
+
+    #[test]
+    fn imposes_pages_with_separate_ids() {
+        let svg = hayro_svg_output(200, 300);
+        let page = "<?xml version=\"1.0\"?>\n<svg width=\"10\" height=\"10\"><defs><path id=\"g0\" \
+                    data-id=\"x\"/></defs><use xlink:href=\"#g0\" fill=\"url(#g0)\"/></svg>";
+        let imposed = impose(
+            &[(page.to_string(), 0.0, 0.0), (svg.clone(), 10.0, 2.5)],
+            210.0,
+            302.5,
+        )
+        .unwrap();
+        assert_eq!(
+            root_attr(&imposed, "viewBox").as_deref(),
+            Some("0 0 210 302.5")
+        );
+        assert!(!imposed.contains("<?xml"));
+        assert!(imposed.contains(
+            "<path id=\"p1-g0\" data-id=\"x\"/></defs><use xlink:href=\"#p1-g0\" fill=\"url(#p1-g0)\"/>"
+        ));
+        assert!(
+            imposed.contains("<g transform=\"translate(10 2.5)\">\n<svg viewBox=\"0 0 200 300\"")
+        );
+        assert_eq!(
+            imposed.matches("<svg").count(),
+            3,
+            "both pages are nested in the new root"
+        );
+    }
+
+    #[test]
+    fn rejects_unusable_roots() {
+        assert!(scale_svg("<svg width=\"100%\" height=\"10\"/>", 2.0).is_err());
+        assert!(scale_svg("<svg height=\"10\"/>", 2.0).is_err());
+        assert!(scale_svg("<html><svg width=\"1\" height=\"1\"/></html>", 2.0).is_err());
+    }
+}
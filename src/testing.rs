@@ -0,0 +1,96 @@
+//! Helpers shared by the unit tests.
+
+use hayro::vello_cpu::Pixmap;
+use std::fs;
+use std::path::PathBuf;
+
+/// A PDF of the given objects, numbered from 1, with a cross-reference table and a trailer
+/// holding `/Size`, `/Root 1 0 R` and the `trailer` entries. Objects are bytes so that
+/// streams may hold binary data.
+pub(crate) fn build_pdf(objects: &[impl AsRef<[u8]>], trailer: &str) -> Vec<u8> {
+    let mut pdf = b"%PDF-1.7\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+        pdf.extend_from_slice(object.as_ref());
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = pdf.len();
+    pdf.extend_from_slice(
+        format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+    );
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{offset:010} 00000 n \n").as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R {trailer} >>\nstartxref\n{xref}\n%%EOF\n",
+            objects.len() + 1
+        )
+        .as_bytes(),
+    );
+    pdf
+}
+
+/// A PDF with blank pages of the given sizes in points, and `extra` objects numbered from
+/// `pages.len() + 3` on.
+pub(crate) fn blank_pages_pdf(pages: &[(u32, u32)], extra: &[String], trailer: &str) -> Vec<u8> {
+    let kids: Vec<String> = (0..pages.len()).map(|i| format!("{} 0 R", i + 3)).collect();
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        ),
+    ];
+    for (width, height) in pages {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {width} {height}] >>"
+        ));
+    }
+    objects.extend_from_slice(extra);
+    build_pdf(&objects, trailer)
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+/// A PDF of one page of `width` x `height` points drawn by the content stream `content`,
+/// which may show text in the standard font Helvetica as `/F1`.
+pub(crate) fn content_page_pdf(width: u32, height: u32, content: &str) -> Vec<u8> {
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {width} {height}] /Contents 4 0 R \
+             /Resources << /Font << /F1 5 0 R >> >> >>"
+        ),
+        format!(
+            "<< /Length {} >>\nstream\n{content}\nendstream",
+            content.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+    ];
+    build_pdf(&objects, "")
+}
+
+/// An empty directory in the system temporary directory for the test `name`.
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pdf-converter-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A pixmap of `width` x `height` pixels with the premultiplied RGBA values `pixels`, row by
+/// row.
+pub(crate) fn pixmap(width: u16, height: u16, pixels: &[[u8; 4]]) -> Pixmap {
+    let mut pixmap = Pixmap::new(width, height);
+    pixmap
+        .data_as_u8_slice_mut()
+        .copy_from_slice(pixels.as_flattened());
+    pixmap
+}
@@ -0,0 +1,338 @@
+use crate::crop::PageView;
+use hayro::kurbo::{Affine, BezPath, Point, Rect};
+use hayro_interpret::font::{Glyph, GlyphRun};
+use hayro_interpret::hayro_cmap::BfString;
+use hayro_interpret::hayro_syntax::object::{Dict, Name, Stream};
+use hayro_interpret::hayro_syntax::page::{Page, Resources};
+use hayro_interpret::{
+    BlendMode, CacheKey, ClipPath, Context, Device, DrawMode, DrawProps, Image, ImageDrawProps,
+    InterpreterCache, InterpreterSettings, SoftMask, interpret_page,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Top and bottom of the box taken up by a glyph, in thousandths of an em above its baseline.
+/// Fonts do not reliably give their own, so every glyph gets the same.
+const ASCENT: f64 = 800.0;
+const DESCENT: f64 = -200.0;
+
+/// Gap between two glyphs, in thousandths of an em, from which it is read as a space.
+/// Justified text squeezes word spaces well below the quarter em of a normal space, while
+/// kerning moves glyphs of a word by a few hundredths, so the line lies close to a tenth.
+const SPACE_GAP: f64 = 120.0;
+
+/// Distance across the baseline, in thousandths of an em, from which text is read as
+/// starting a new line.
+const LINE_GAP: f64 = 500.0;
+
+/// Advance assumed for glyphs whose font gives none.
+const DEFAULT_ADVANCE: f64 = 500.0;
+
+/// A run of text shown by one text operator of a page, such as `Tj` or `TJ`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TextRun {
+    pub text: String,
+    /// Name of the font without its subset prefix, when the PDF gives one.
+    pub font: Option<String>,
+    /// Font size as it appears on the page, in points.
+    pub size: f32,
+    /// Bounding box `[x0, y0, x1, y1]` in points from the top-left corner of the page as it
+    /// is rendered, with `y` pointing down.
+    pub bbox: [f32; 4],
+    /// Where the first glyph starts, in page coordinates.
+    #[serde(skip)]
+    start: Point,
+    /// Where the run ends: page coordinates in the space of its last glyph, and the advance
+    /// of that glyph.
+    #[serde(skip)]
+    end: (Affine, f64),
+}
+
+/// The text of a page for JSON output.
+#[derive(Serialize)]
+struct PageText<'a> {
+    /// 1-based page number.
+    page: usize,
+    /// Size of the page as it is rendered, in points.
+    width: f32,
+    height: f32,
+    runs: &'a [TextRun],
+}
+
+/// The text runs of the area `view` of `page`, in content stream order, positioned in the
+/// coordinates of `view`. Runs outside the view are left out; invisible text, such as the
+/// OCR layer of a scan, is included.
+pub(crate) fn extract_text(
+    page: &Page,
+    view: &PageView,
+    settings: &InterpreterSettings,
+) -> Vec<TextRun> {
+    let mut fonts = HashMap::new();
+    collect_font_names(page.resources(), &mut fonts, 0);
+
+    let cache = InterpreterCache::new();
+    let mut context = Context::new(
+        view.transform,
+        Rect::new(0.0, 0.0, view.width as f64, view.height as f64),
+        &cache,
+        page.xref(),
+        settings.clone(),
+    );
+    let mut device = TextDevice {
+        fonts,
+        runs: Vec::new(),
+        last_fill: None,
+    };
+    interpret_page(page, &mut context, &mut device);
+    device.runs.retain(|run| {
+        let [x0, y0, x1, y1] = run.bbox;
+        x0 < view.width && x1 > 0.0 && y0 < view.height && y1 > 0.0
+    });
+    device.runs
+}
+
+/// The runs as plain text, one line per baseline, with a space wherever glyphs leave a gap.
+pub(crate) fn plain_text(runs: &[TextRun]) -> String {
+    let mut text = String::new();
+    let mut previous: Option<&TextRun> = None;
+    for run in runs {
+        if let Some(previous) = previous {
+            let (along, across) = offset_from_end(previous.end, run.start);
+            if across.abs() > LINE_GAP {
+                text.push('\n');
+            } else if along > SPACE_GAP && !ends_with_space(&text) {
+                text.push(' ');
+            }
+        }
+        text.push_str(&run.text);
+        previous = Some(run);
+    }
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text
+}
+
+/// The runs of the page with 0-based index `idx` as a JSON document, together with the size
+/// of `view` so positions can be scaled to a rendered image of the page.
+pub(crate) fn page_json(idx: usize, view: &PageView, runs: &[TextRun]) -> String {
+    let page = PageText {
+        page: idx + 1,
+        width: round(view.width as f64),
+        height: round(view.height as f64),
+        runs,
+    };
+    // Serializing plain structs and strings cannot fail.
+    let json = serde_json::to_string_pretty(&page).unwrap_or_default();
+    format!("{json}\n")
+}
+
+/// A device that only records the text drawn on a page.
+struct TextDevice {
+    /// Font names by the cache key of their font dictionary.
+    fonts: HashMap<u128, String>,
+    runs: Vec<TextRun>,
+    /// Transform of the first glyph and glyph count of the last filled run, since text that
+    /// is filled and stroked is drawn twice.
+    last_fill: Option<(Affine, usize)>,
+}
+
+impl<'a> Device<'a> for TextDevice {
+    fn draw_path(&mut self, _: &BezPath, _: DrawProps<'a>, _: &DrawMode) {}
+
+    fn push_clip_path(&mut self, _: &ClipPath) {}
+
+    fn push_transparency_group(&mut self, _: f32, _: Option<SoftMask<'a>>, _: BlendMode) {}
+
+    fn draw_glyph_run(&mut self, run: &GlyphRun<'_, 'a>, props: DrawProps<'a>, mode: &DrawMode) {
+        let glyphs = run.glyphs();
+        let Some(first) = glyphs.first() else {
+            return;
+        };
+        let key = (props.transform * first.transform(), glyphs.len());
+        if let DrawMode::Stroke(_) = mode {
+            if self.last_fill == Some(key) {
+                return;
+            }
+        } else {
+            self.last_fill = Some(key);
+        }
+
+        let font = match &**first {
+            Glyph::Outline(glyph) => self
+                .fonts
+                .get(&glyph.font_cache_key())
+                .cloned()
+                .or_else(|| glyph.font_data().and_then(|data| data.postscript_name))
+                .map(|name| strip_subset_prefix(&name).to_string()),
+            Glyph::Type3(_) => None,
+        };
+
+        let mut text = String::new();
+        let mut bbox = Rect::ZERO;
+        let mut size: f64 = 0.0;
+        let mut start = None;
+        let mut end: Option<(Affine, f64)> = None;
+        for glyph in glyphs {
+            let transform = props.transform * glyph.transform();
+            // Glyphs shown at size 0 take up no space and would not invert.
+            if transform.determinant().abs() < 1e-12 {
+                continue;
+            }
+            let origin = transform * Point::ORIGIN;
+            if let Some(end) = end
+                && offset_from_end(end, origin).0 > SPACE_GAP
+                && !ends_with_space(&text)
+            {
+                text.push(' ');
+            }
+            match glyph.as_unicode() {
+                Some(BfString::Char(c)) => push_visible(&mut text, c),
+                Some(BfString::String(s)) => s.chars().for_each(|c| push_visible(&mut text, c)),
+                None => text.push(char::REPLACEMENT_CHARACTER),
+            }
+
+            let advance = match &**glyph {
+                Glyph::Outline(glyph) => glyph
+                    .advance_width()
+                    .filter(|width| *width > 0.0)
+                    .map_or(DEFAULT_ADVANCE, f64::from),
+                Glyph::Type3(_) => DEFAULT_ADVANCE,
+            };
+            let glyph_box = transform.transform_rect_bbox(Rect::new(0.0, DESCENT, advance, ASCENT));
+            bbox = if start.is_none() {
+                glyph_box
+            } else {
+                bbox.union(glyph_box)
+            };
+            let [_, _, c, d, _, _] = transform.as_coeffs();
+            size = size.max(c.hypot(d) * 1000.0);
+            start.get_or_insert(origin);
+            end = Some((transform.inverse(), advance));
+        }
+
+        let (Some(start), Some(end)) = (start, end) else {
+            return;
+        };
+        if text.is_empty() {
+            return;
+        }
+        self.runs.push(TextRun {
+            text,
+            font,
+            size: round(size),
+            bbox: [
+                round(bbox.x0),
+                round(bbox.y0),
+                round(bbox.x1),
+                round(bbox.y1),
+            ],
+            start,
+            end,
+        });
+    }
+
+    fn draw_image(&mut self, _: Image<'a, '_>, _: ImageDrawProps<'a>) {}
+
+    fn pop_clip(&mut self) {}
+
+    fn pop_transparency_group(&mut self) {}
+}
+
+/// Where `point` lies after the end of a glyph, in thousandths of an em along and across
+/// its baseline. `end` maps page coordinates into the space of the glyph.
+fn offset_from_end((inverse, advance): (Affine, f64), point: Point) -> (f64, f64) {
+    let local = inverse * point;
+    (local.x - advance, local.y)
+}
+
+fn ends_with_space(text: &str) -> bool {
+    text.is_empty() || text.ends_with(char::is_whitespace)
+}
+
+/// Append `c` unless it is a control character, which some fonts map unused codes to.
+fn push_visible(text: &mut String, c: char) {
+    if !c.is_control() {
+        text.push(c);
+    }
+}
+
+/// Record the base font names of `resources` and of the forms they use, up to a depth that
+/// guards against reference cycles in malformed files.
+fn collect_font_names(resources: &Resources, fonts: &mut HashMap<u128, String>, depth: usize) {
+    if depth > 16 {
+        return;
+    }
+    for key in resources.fonts.keys() {
+        if let Some(font) = resources.fonts.get::<Dict>(key.as_ref())
+            && let Some(name) = font.get::<Name>(b"BaseFont")
+        {
+            let name = String::from_utf8_lossy(name.as_ref()).into_owned();
+            fonts.entry(font.cache_key()).or_insert(name);
+        }
+    }
+    for key in resources.x_objects.keys() {
+        if let Some(resources) = resources
+            .x_objects
+            .get::<Stream>(key.as_ref())
+            .and_then(|form| form.dict().get::<Dict>(b"Resources"))
+        {
+            collect_font_names(&Resources::new(resources), fonts, depth + 1);
+        }
+    }
+}
+
+/// `ABCDEF+Helvetica` becomes `Helvetica`: subset fonts carry six capital letters and a
+/// plus sign in front of their name.
+fn strip_subset_prefix(name: &str) -> &str {
+    match name.split_once('+') {
+        Some((prefix, rest))
+            if prefix.len() == 6 && prefix.bytes().all(|b| b.is_ascii_uppercase()) =>
+        {
+            rest
+        }
+        _ => name,
+    }
+}
+
+/// Round a coordinate or size to a hundredth of a point.
+fn round(value: f64) -> f32 {
+    ((value * 100.0).round() / 100.0) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::content_page_pdf;
+    use crate::{Converter, OutputFormat};
+
+    fn page_text(content: &str) -> String {
+        let pdf = content_page_pdf(300, 200, content);
+        let outputs = Converter::new(OutputFormat::Text { json: false })
+            .render_bytes(pdf)
+            .unwrap();
+        String::from_utf8(outputs[0].bytes.clone()).unwrap()
+    }
+
+    #[test]
+    fn reads_narrow_justified_gaps_as_spaces() {
+        // TeX sets words apart by moving the text position, often by less than a quarter em.
+        let text = page_text("BT /F1 10 Tf 20 150 Td [(ver)-30(sion)-180(0.21)-130(of)] TJ ET");
+        assert_eq!(text, "version 0.21 of\n");
+    }
+
+    #[test]
+    fn keeps_kerned_glyphs_together() {
+        let text = page_text("BT /F1 10 Tf 20 150 Td [(W)60(A)-40(VE)] TJ ET");
+        assert_eq!(text, "WAVE\n");
+    }
+
+    #[test]
+    fn separates_runs_by_their_gap() {
+        // "the" is 13.9 points wide in 10 point Helvetica.
+        let text = page_text(
+            "BT /F1 10 Tf 20 150 Td (the) Tj ET BT /F1 10 Tf 35.4 150 Td (Shared) Tj ET \
+             BT /F1 10 Tf 20 130 Td (the) Tj ET BT /F1 10 Tf 34.2 130 Td (se) Tj ET",
+        );
+        assert_eq!(text, "the Shared\nthese\n");
+    }
+}
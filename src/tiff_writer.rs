@@ -0,0 +1,338 @@
+use crate::color::{self, ColorMode, Dither};
+use clap::ValueEnum;
+use fax::{Color, VecWriter, encoder::Encoder as FaxEncoder};
+use hayro::vello_cpu::Pixmap;
+use std::io::{Seek, Write};
+use tiff::encoder::compression::{CompressionAlgorithm, Deflate, Lzw};
+use tiff::encoder::{Rational, TiffEncoder};
+use tiff::tags::{CompressionMethod, ExtraSamples, PhotometricInterpretation, ResolutionUnit, Tag};
+
+/// TIFF `PageNumber` tag, not named by the `tiff` crate.
+const TAG_PAGE_NUMBER: u16 = 297;
+/// `NewSubfileType` value marking a directory as one page of a multi-page document.
+const SUBFILE_PAGE: u32 = 2;
+
+/// Compression scheme used for the strips of a TIFF file. `G4` is CCITT Group 4 fax
+/// compression and is always bilevel.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[value(rename_all = "lower")]
+pub enum TiffCompression {
+    None,
+    Lzw,
+    Deflate,
+    G4,
+}
+
+/// Writes rendered pages as the directories of a single multi-page TIFF file.
+pub struct MultiPageTiff<W: Write + Seek> {
+    encoder: TiffEncoder<W>,
+    compression: TiffCompression,
+    color: ColorMode,
+    dither: Dither,
+    alpha: bool,
+    page_count: u16,
+    pages_written: u16,
+}
+
+impl<W: Write + Seek> MultiPageTiff<W> {
+    /// Start a TIFF file that will hold `page_count` pages.
+    ///
+    /// Pages are stored in the colour model `color`. RGB pages keep their transparency when
+    /// `alpha` is set. G4 compression implies [`ColorMode::Mono`], where pages are reduced
+    /// to 1-bit black and white with `dither`.
+    pub fn new(
+        writer: W,
+        compression: TiffCompression,
+        color: ColorMode,
+        dither: Dither,
+        alpha: bool,
+        page_count: usize,
+    ) -> Result<Self, String> {
+        let encoder =
+            TiffEncoder::new(writer).map_err(|e| format!("Failed to start TIFF file: {e}"))?;
+        Ok(Self {
+            encoder,
+            compression,
+            color: if compression == TiffCompression::G4 {
+                ColorMode::Mono
+            } else {
+                color
+            },
+            dither,
+            alpha,
+            page_count: page_count.min(u16::MAX as usize) as u16,
+            pages_written: 0,
+        })
+    }
+
+    /// Append `pixmap` as the next page, tagged with a resolution of `dpi`.
+    pub fn add_page(&mut self, pixmap: &Pixmap, dpi: f32) -> Result<(), String> {
+        let width = pixmap.width() as u32;
+        let height = pixmap.height() as u32;
+        // Black and white pixels, kept for G4 encoding.
+        let mut mono = None;
+        let (samples, bits_per_sample, photometric) = match self.color {
+            ColorMode::Mono => {
+                let pixels = color::mono(pixmap, self.dither);
+                let packed = color::pack_bits(&pixels, width as usize, true);
+                mono = Some(pixels);
+                (packed, &[1u16][..], PhotometricInterpretation::WhiteIsZero)
+            }
+            ColorMode::Gray => (
+                color::gray8(pixmap),
+                &[8u16][..],
+                PhotometricInterpretation::BlackIsZero,
+            ),
+            ColorMode::Gray16 => (
+                // Samples use the byte order of the file, which the encoder writes natively.
+                color::gray16(pixmap)
+                    .iter()
+                    .flat_map(|value| value.to_ne_bytes())
+                    .collect(),
+                &[16u16][..],
+                PhotometricInterpretation::BlackIsZero,
+            ),
+            ColorMode::Rgb if self.alpha => (
+                pixmap.data_as_u8_slice().to_vec(),
+                &[8u16, 8, 8, 8][..],
+                PhotometricInterpretation::RGB,
+            ),
+            ColorMode::Rgb => (
+                flatten_rgb(pixmap),
+                &[8u16, 8, 8][..],
+                PhotometricInterpretation::RGB,
+            ),
+        };
+        let extra_samples = bits_per_sample.len() == 4;
+
+        let (strip, method) = match (self.compression, mono) {
+            (TiffCompression::G4, Some(pixels)) => {
+                (encode_g4(&pixels, pixmap.width()), CompressionMethod::Fax4)
+            }
+            (TiffCompression::None | TiffCompression::G4, _) => (samples, CompressionMethod::None),
+            (TiffCompression::Lzw, _) => (compress(Lzw, &samples)?, CompressionMethod::LZW),
+            (TiffCompression::Deflate, _) => (
+                compress(Deflate::default(), &samples)?,
+                CompressionMethod::Deflate,
+            ),
+        };
+
+        let resolution = Rational {
+            n: (dpi * 100.0).round().max(1.0) as u32,
+            d: 100,
+        };
+        let page_number = [self.pages_written, self.page_count];
+
+        let write = || -> tiff::TiffResult<()> {
+            let mut dir = self.encoder.image_directory()?;
+            let offset = dir.write_data(&strip[..])?;
+            dir.write_tag(Tag::NewSubfileType, SUBFILE_PAGE)?;
+            dir.write_tag(Tag::ImageWidth, width)?;
+            dir.write_tag(Tag::ImageLength, height)?;
+            dir.write_tag(Tag::BitsPerSample, bits_per_sample)?;
+            dir.write_tag(Tag::Compression, method)?;
+            dir.write_tag(Tag::PhotometricInterpretation, photometric)?;
+            dir.write_tag(Tag::StripOffsets, offset as u32)?;
+            dir.write_tag(Tag::SamplesPerPixel, bits_per_sample.len() as u16)?;
+            if extra_samples {
+                // The pixmap is premultiplied, which TIFF calls associated alpha.
+                dir.write_tag(Tag::ExtraSamples, &[ExtraSamples::AssociatedAlpha][..])?;
+            }
+            dir.write_tag(Tag::RowsPerStrip, height)?;
+            dir.write_tag(Tag::StripByteCounts, strip.len() as u32)?;
+            dir.write_tag(Tag::XResolution, resolution.clone())?;
+            dir.write_tag(Tag::YResolution, resolution)?;
+            dir.write_tag(Tag::ResolutionUnit, ResolutionUnit::Inch)?;
+            dir.write_tag(Tag::Unknown(TAG_PAGE_NUMBER), &page_number[..])?;
+            dir.finish()
+        };
+        write().map_err(|e| format!("Failed to write TIFF page: {e}"))?;
+
+        self.pages_written = self.pages_written.saturating_add(1);
+        Ok(())
+    }
+}
+
+/// Run one of the `tiff` crate's strip compressors over `data`.
+fn compress(mut algorithm: impl CompressionAlgorithm, data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    algorithm
+        .write_to(&mut out, data)
+        .map_err(|e| format!("Failed to compress TIFF strip: {e}"))?;
+    Ok(out)
+}
+
+/// Drop the alpha channel of a pixmap. Pages are rendered onto an opaque background, so the
+/// premultiplied colour values are already the visible colours.
+fn flatten_rgb(pixmap: &Pixmap) -> Vec<u8> {
+    pixmap
+        .data_as_u8_slice()
+        .chunks_exact(4)
+        .flat_map(|px| [px[0], px[1], px[2]])
+        .collect()
+}
+
+/// Encode black and white pixels, `width` per row, as a CCITT Group 4 bitstream.
+fn encode_g4(pixels: &[bool], width: u16) -> Vec<u8> {
+    let mut encoder = FaxEncoder::new(VecWriter::new());
+    for row in pixels.chunks_exact((width as usize).max(1)) {
+        let pels = row
+            .iter()
+            .map(|&black| if black { Color::Black } else { Color::White });
+        // Writing into a `Vec` cannot fail.
+        let _ = encoder.encode_line(pels, width);
+    }
+    match encoder.finish() {
+        Ok(writer) => writer.finish(),
+        Err(never) => match never {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::pixmap;
+    use std::io::Cursor;
+    use tiff::decoder::{Decoder, DecodingResult, ifd::Value};
+
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const WHITE: [u8; 4] = [255; 4];
+
+    /// A TIFF of two pages of the same pixmap, 10 pixels wide: black on the left and white
+    /// on the right of its first row, the other way round on the second.
+    fn two_pages(compression: TiffCompression, color: ColorMode) -> Decoder<Cursor<Vec<u8>>> {
+        let mut pixels = [[BLACK; 5], [WHITE; 5], [WHITE; 5], [BLACK; 5]].concat();
+        pixels[12] = [128, 0, 0, 255];
+        let page = pixmap(10, 2, &pixels);
+        let mut cursor = Cursor::new(Vec::new());
+        let mut tiff =
+            MultiPageTiff::new(&mut cursor, compression, color, Dither::Threshold, false, 2)
+                .unwrap();
+        tiff.add_page(&page, 150.0).unwrap();
+        tiff.add_page(&page, 300.0).unwrap();
+        Decoder::new(Cursor::new(cursor.into_inner())).unwrap()
+    }
+
+    fn page_count(decoder: &mut Decoder<Cursor<Vec<u8>>>) -> usize {
+        let mut pages = 1;
+        while decoder.more_images() {
+            decoder.next_image().unwrap();
+            pages += 1;
+        }
+        pages
+    }
+
+    #[test]
+    fn writes_bilevel_pages_with_g4_compression() {
+        let mut decoder = two_pages(TiffCompression::G4, ColorMode::Rgb);
+        assert_eq!(decoder.dimensions().unwrap(), (10, 2));
+        assert_eq!(
+            decoder.get_tag_u32(Tag::Compression).unwrap(),
+            CompressionMethod::Fax4.to_u16() as u32
+        );
+        assert_eq!(decoder.get_tag_u32_vec(Tag::BitsPerSample).unwrap(), [1]);
+        assert_eq!(decoder.get_tag_u32(Tag::SamplesPerPixel).unwrap(), 1);
+        assert_eq!(
+            decoder.get_tag_u32(Tag::PhotometricInterpretation).unwrap(),
+            PhotometricInterpretation::WhiteIsZero.to_u16() as u32
+        );
+        assert_eq!(
+            decoder.get_tag(Tag::XResolution).unwrap(),
+            Value::Rational(15000, 100)
+        );
+        assert_eq!(
+            decoder.get_tag(Tag::YResolution).unwrap(),
+            Value::Rational(15000, 100)
+        );
+        assert_eq!(
+            decoder
+                .get_tag_u32_vec(Tag::Unknown(TAG_PAGE_NUMBER))
+                .unwrap(),
+            [0, 2]
+        );
+        // The `tiff` crate cannot decode G4 strips, so the strip is decoded by itself.
+        let offset = decoder.get_tag_u32(Tag::StripOffsets).unwrap() as usize;
+        let len = decoder.get_tag_u32(Tag::StripByteCounts).unwrap() as usize;
+        let strip = decoder.inner().get_ref()[offset..offset + len].to_vec();
+        let mut rows = Vec::new();
+        fax::decoder::decode_g4(strip.into_iter(), 10, Some(2), |line| {
+            let row = fax::decoder::pels(line, 10).map(|pel| pel == Color::Black);
+            rows.push(row.collect::<Vec<_>>());
+        })
+        .unwrap();
+        // The dark red pixel counts as black.
+        let black = |runs: &[(bool, usize)]| {
+            let row = runs.iter().flat_map(|&(black, len)| vec![black; len]);
+            row.collect::<Vec<_>>()
+        };
+        assert_eq!(
+            rows,
+            [
+                black(&[(true, 5), (false, 5)]),
+                black(&[(false, 2), (true, 1), (false, 2), (true, 5)])
+            ]
+        );
+
+        decoder.next_image().unwrap();
+        assert_eq!(
+            decoder.get_tag(Tag::XResolution).unwrap(),
+            Value::Rational(30000, 100)
+        );
+        assert_eq!(
+            decoder
+                .get_tag_u32_vec(Tag::Unknown(TAG_PAGE_NUMBER))
+                .unwrap(),
+            [1, 2]
+        );
+        assert!(!decoder.more_images());
+    }
+
+    #[test]
+    fn writes_compressed_colour_and_grey_pages() {
+        let mut decoder = two_pages(TiffCompression::Deflate, ColorMode::Rgb);
+        assert_eq!(
+            decoder.get_tag_u32(Tag::Compression).unwrap(),
+            CompressionMethod::Deflate.to_u16() as u32
+        );
+        assert_eq!(
+            decoder.get_tag_u32_vec(Tag::BitsPerSample).unwrap(),
+            [8, 8, 8]
+        );
+        let DecodingResult::U8(rgb) = decoder.read_image().unwrap() else {
+            panic!("RGB pages decode to bytes");
+        };
+        assert_eq!(rgb.len(), 10 * 2 * 3);
+        assert_eq!(rgb[..3], [0, 0, 0]);
+        assert_eq!(rgb[12 * 3..13 * 3], [128, 0, 0]);
+        assert_eq!(page_count(&mut decoder), 2);
+
+        let mut decoder = two_pages(TiffCompression::Lzw, ColorMode::Gray);
+        assert_eq!(
+            decoder.get_tag_u32(Tag::Compression).unwrap(),
+            CompressionMethod::LZW.to_u16() as u32
+        );
+        assert_eq!(decoder.get_tag_u32_vec(Tag::BitsPerSample).unwrap(), [8]);
+        let DecodingResult::U8(gray) = decoder.read_image().unwrap() else {
+            panic!("grey pages decode to bytes");
+        };
+        assert_eq!(gray[..6], [0, 0, 0, 0, 0, 255]);
+        assert_eq!(gray[12], 38);
+    }
+
+    #[test]
+    fn packs_uncompressed_bilevel_pages() {
+        let mut decoder = two_pages(TiffCompression::None, ColorMode::Mono);
+        assert_eq!(decoder.get_tag_u32_vec(Tag::BitsPerSample).unwrap(), [1]);
+        assert_eq!(
+            decoder.get_tag_u32(Tag::Compression).unwrap(),
+            CompressionMethod::None.to_u16() as u32
+        );
+        let DecodingResult::U8(rows) = decoder.read_image().unwrap() else {
+            panic!("bilevel pages decode to bytes");
+        };
+        // The file stores black as set bits, each row padded to whole bytes; the decoder
+        // turns them into intensities, where black is clear.
+        assert_eq!(rows, [0b0000_0111, 0b1111_1111, 0b1101_1000, 0b0011_1111]);
+        assert_eq!(page_count(&mut decoder), 2);
+    }
+}
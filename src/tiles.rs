@@ -0,0 +1,319 @@
+use crate::color::{self, BandDither, ColorMode, Dither};
+use hayro::vello_cpu::peniko::ImageAlphaType;
+use hayro::vello_cpu::{Pixels, Pixmap};
+use png::{BitDepth, ColorType};
+use std::io::Write;
+
+/// Largest width or height of a single pixmap.
+pub const MAX_PIXMAP_SIZE: u32 = u16::MAX as u32;
+
+/// Edge of square tiles in pixels when no tile size is given.
+pub const DEFAULT_TILE_SIZE: u32 = 1024;
+
+/// Memory a page rendered in one piece takes per pixel: the pixmap and the copy the
+/// encoder makes of it.
+pub const PAGE_BYTES_PER_PIXEL: u64 = 8;
+
+/// Memory a band of a stitched page takes per pixel: the samples of the band, plus the
+/// pixmap of one tile and its converted samples.
+const BAND_BYTES_PER_PIXEL: u64 = 12;
+
+/// Widest tile a band is rendered from when it is not limited by a tile size. Bands of
+/// wider pages are rendered from several tiles side by side.
+const MAX_BAND_TILE_WIDTH: u32 = 16384;
+
+/// An image of `width` x `height` pixels divided into a grid of tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileGrid {
+    pub width: u32,
+    pub height: u32,
+    tile_width: u32,
+    tile_height: u32,
+}
+
+impl TileGrid {
+    /// Divide an image into tiles of at most `tile_width` x `tile_height` pixels. Tiles in
+    /// the last row and column are smaller when the image does not divide evenly.
+    pub fn new(width: u32, height: u32, tile_width: u32, tile_height: u32) -> Self {
+        let width = width.max(1);
+        let height = height.max(1);
+        Self {
+            width,
+            height,
+            tile_width: tile_width.clamp(1, MAX_PIXMAP_SIZE).min(width),
+            tile_height: tile_height.clamp(1, MAX_PIXMAP_SIZE).min(height),
+        }
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.height.div_ceil(self.tile_height)
+    }
+
+    pub fn cols(&self) -> u32 {
+        self.width.div_ceil(self.tile_width)
+    }
+
+    /// The pixel rectangle `(x, y, width, height)` of the tile at `row` and `col`.
+    pub fn tile(&self, row: u32, col: u32) -> (u32, u32, u16, u16) {
+        let x = col * self.tile_width;
+        let y = row * self.tile_height;
+        let width = self.tile_width.min(self.width - x);
+        let height = self.tile_height.min(self.height - y);
+        (x, y, width as u16, height as u16)
+    }
+
+    /// Row and column of every tile, row by row.
+    pub fn tiles(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (0..self.rows()).flat_map(move |row| (0..self.cols()).map(move |col| (row, col)))
+    }
+}
+
+/// The grid a page of `width` x `height` pixels is stitched together from: squares of
+/// `tile_size` pixels if given, otherwise bands across the page as tall as `budget` bytes
+/// allow.
+pub fn band_grid(width: u32, height: u32, tile_size: Option<u32>, budget: Option<u64>) -> TileGrid {
+    if let Some(size) = tile_size {
+        return TileGrid::new(width, height, size, size);
+    }
+    let band_height = match budget {
+        Some(budget) => budget / (width as u64 * BAND_BYTES_PER_PIXEL),
+        None => DEFAULT_TILE_SIZE as u64,
+    };
+    TileGrid::new(
+        width,
+        height,
+        MAX_BAND_TILE_WIDTH,
+        band_height.min(u32::MAX as u64) as u32,
+    )
+}
+
+/// File name of the tile at `row` and `col` of the output `name`, both counted from 0:
+/// `page-1.png` becomes `page-1_0_2.png` for the third tile of the first row.
+pub fn tile_name(name: &str, row: u32, col: u32) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) => format!("{stem}_{row}_{col}.{ext}"),
+        None => format!("{name}_{row}_{col}"),
+    }
+}
+
+/// Encode an image as PNG one band of tiles at a time, so only a band is held in memory
+/// besides the compressed output. `render` renders the pixel rectangle of a tile.
+///
+/// The pixels are the same as when the image is encoded in one piece, apart from rounding in
+/// the odd anti-aliased pixel, and dithering carries over from band to band. RGB images
+/// have an alpha channel when the background is transparent (`alpha`), since the encoder
+/// cannot look ahead to see whether any pixel is.
+pub fn stitch_png<F>(
+    grid: &TileGrid,
+    color: ColorMode,
+    dither: Dither,
+    alpha: bool,
+    render: F,
+) -> Result<Vec<u8>, String>
+where
+    F: Fn((u32, u32, u16, u16)) -> Pixmap,
+{
+    let error = |e: png::EncodingError| format!("Failed to encode PNG: {e}");
+    let width = grid.width as usize;
+    let (color_type, depth, bytes_per_pixel) = match color {
+        ColorMode::Rgb if alpha => (ColorType::Rgba, BitDepth::Eight, 4),
+        ColorMode::Rgb => (ColorType::Rgb, BitDepth::Eight, 3),
+        ColorMode::Gray => (ColorType::Grayscale, BitDepth::Eight, 1),
+        ColorMode::Gray16 => (ColorType::Grayscale, BitDepth::Sixteen, 2),
+        ColorMode::Mono => (ColorType::Grayscale, BitDepth::One, 0),
+    };
+
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, grid.width, grid.height);
+    encoder.set_color(color_type);
+    encoder.set_depth(depth);
+    let mut writer = encoder.write_header().map_err(error)?;
+    let mut stream = writer.stream_writer().map_err(error)?;
+
+    let mut dither = BandDither::new(dither, width);
+    for row in 0..grid.rows() {
+        let band_height = grid.tile(row, 0).3 as usize;
+        // Mono bands hold lightness levels, since dithering needs whole rows.
+        let mut levels = Vec::new();
+        let mut samples = Vec::new();
+        if color == ColorMode::Mono {
+            levels = vec![0.0; width * band_height];
+        } else {
+            samples = vec![0; width * bytes_per_pixel * band_height];
+        }
+
+        for col in 0..grid.cols() {
+            let tile = grid.tile(row, col);
+            let pixmap = render(tile);
+            let (x, tile_width) = (tile.0 as usize, tile.2 as usize);
+            if color == ColorMode::Mono {
+                place(&mut levels, width, &color::levels(&pixmap), x, tile_width);
+                continue;
+            }
+            let tile_samples: Vec<u8> = match color {
+                // Unpremultiplied like `Pixmap::into_png` does, so the pixels match.
+                ColorMode::Rgb => match pixmap.try_take_rgb8(ImageAlphaType::Alpha) {
+                    Pixels::Rgba8(rgba) if alpha => rgba,
+                    Pixels::Rgb8(rgb) if alpha => rgb
+                        .chunks_exact(3)
+                        .flat_map(|px| [px[0], px[1], px[2], 255])
+                        .collect(),
+                    Pixels::Rgba8(rgba) => rgba
+                        .chunks_exact(4)
+                        .flat_map(|px| [px[0], px[1], px[2]])
+                        .collect(),
+                    Pixels::Rgb8(rgb) => rgb,
+                },
+                ColorMode::Gray16 => color::gray16(&pixmap)
+                    .iter()
+                    .flat_map(|value| value.to_be_bytes())
+                    .collect(),
+                ColorMode::Gray | ColorMode::Mono => color::gray8(&pixmap),
+            };
+            let n = bytes_per_pixel;
+            place(
+                &mut samples,
+                width * n,
+                &tile_samples,
+                x * n,
+                tile_width * n,
+            );
+        }
+
+        if color == ColorMode::Mono {
+            samples = color::pack_bits(&dither.next_band(levels), width, false);
+        }
+        stream
+            .write_all(&samples)
+            .map_err(|e| format!("Failed to encode PNG: {e}"))?;
+    }
+    stream.finish().map_err(error)?;
+    writer.finish().map_err(error)?;
+    Ok(out)
+}
+
+/// The pixel rectangle `(x, y, width, height)` of `pixmap` as a pixmap of its own.
+pub fn crop_pixmap(pixmap: &Pixmap, (x, y, width, height): (u32, u32, u16, u16)) -> Pixmap {
+    let mut tile = Pixmap::new(width, height);
+    let row_bytes = width as usize * 4;
+    let stride = pixmap.width() as usize * 4;
+    let data = pixmap.data_as_u8_slice();
+    for (i, row) in tile
+        .data_as_u8_slice_mut()
+        .chunks_exact_mut(row_bytes)
+        .enumerate()
+    {
+        let start = (y as usize + i) * stride + x as usize * 4;
+        row.copy_from_slice(&data[start..start + row_bytes]);
+    }
+    tile
+}
+
+/// Fill the pixel rectangle `(x, y, width, height)` of `pixmap` with the premultiplied
+/// colour `rgba`, clipped to the pixmap.
+pub fn fill_rect(pixmap: &mut Pixmap, (x, y, width, height): (i64, i64, i64, i64), rgba: [u8; 4]) {
+    let stride = pixmap.width() as i64;
+    let (x0, y0) = (x.max(0), y.max(0));
+    let (x1, y1) = (
+        (x + width).min(stride),
+        (y + height).min(pixmap.height() as i64),
+    );
+    if x0 >= x1 {
+        return;
+    }
+    let data = pixmap.data_as_u8_slice_mut();
+    for row in y0..y1 {
+        let start = ((row * stride + x0) * 4) as usize;
+        let end = ((row * stride + x1) * 4) as usize;
+        for px in data[start..end].chunks_exact_mut(4) {
+            px.copy_from_slice(&rgba);
+        }
+    }
+}
+
+/// Copy `src` into `dst` with its top-left corner at `x`, `y`. It must fit.
+pub fn paste_pixmap(dst: &mut Pixmap, src: &Pixmap, x: u32, y: u32) {
+    let (x, y) = (x as usize, y as usize);
+    let row_bytes = src.width() as usize * 4;
+    let stride = dst.width() as usize * 4;
+    let data = dst.data_as_u8_slice_mut();
+    for (i, row) in src.data_as_u8_slice().chunks_exact(row_bytes).enumerate() {
+        let start = (y + i) * stride + x * 4;
+        data[start..start + row_bytes].copy_from_slice(row);
+    }
+}
+
+/// Copy the rows of a tile, `tile_width` values wide, into a band `band_width` values wide,
+/// starting at value `x` of each row.
+fn place<T: Copy>(band: &mut [T], band_width: usize, tile: &[T], x: usize, tile_width: usize) {
+    for (y, row) in tile.chunks_exact(tile_width).enumerate() {
+        let start = y * band_width + x;
+        band[start..start + tile_width].copy_from_slice(row);
+    }
+}
+
+/// Parse a memory size such as `512M`, `2G` or `1.5GiB`: a number of bytes, optionally
+/// followed by K, M, G or T for powers of 1024.
+pub fn parse_memory(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid memory size '{s}': expected a size such as 512M or 2G");
+    let upper = s.trim().to_ascii_uppercase();
+    let number = upper
+        .strip_suffix("IB")
+        .or_else(|| upper.strip_suffix('B'))
+        .unwrap_or(&upper);
+    let (number, unit) = match number.char_indices().last() {
+        Some((i, 'K')) => (&number[..i], 1u64 << 10),
+        Some((i, 'M')) => (&number[..i], 1 << 20),
+        Some((i, 'G')) => (&number[..i], 1 << 30),
+        Some((i, 'T')) => (&number[..i], 1 << 40),
+        _ => (number, 1),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() && value * unit as f64 >= 1.0 => {
+            Ok((value * unit as f64).min(u64::MAX as f64) as u64)
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edge_tiles_are_cropped_to_the_image() {
+        let grid = TileGrid::new(2500, 1000, 1024, 1024);
+        assert_eq!((grid.cols(), grid.rows()), (3, 1));
+        assert_eq!(grid.tile(0, 0), (0, 0, 1024, 1000));
+        assert_eq!(grid.tile(0, 2), (2048, 0, 452, 1000));
+        assert_eq!(grid.tiles().count(), 3);
+    }
+
+    #[test]
+    fn tiles_never_exceed_a_pixmap() {
+        let grid = TileGrid::new(200_000, 10, 100_000, 0);
+        assert_eq!(grid.tile(0, 0), (0, 0, u16::MAX, 1));
+        assert_eq!(grid.cols(), 200_000u32.div_ceil(MAX_PIXMAP_SIZE));
+    }
+
+    #[test]
+    fn bands_fit_the_budget() {
+        let grid = band_grid(4000, 10_000, None, Some(48_000_000));
+        let (_, _, width, height) = grid.tile(0, 0);
+        assert_eq!((width, height), (4000, 1000));
+        assert!(width as u64 * height as u64 * BAND_BYTES_PER_PIXEL <= 48_000_000);
+        // Wider pages are rendered from several tiles per band.
+        let grid = band_grid(40_000, 100, None, None);
+        assert_eq!(grid.tile(0, 0), (0, 0, MAX_BAND_TILE_WIDTH as u16, 100));
+        assert_eq!(
+            band_grid(4000, 4000, Some(512), None).tile(1, 1),
+            (512, 512, 512, 512)
+        );
+    }
+
+    #[test]
+    fn names_tiles_by_row_and_column() {
+        assert_eq!(tile_name("page-1.png", 0, 2), "page-1_0_2.png");
+        assert_eq!(tile_name("page", 3, 4), "page_3_4");
+    }
+}
@@ -0,0 +1,128 @@
+use hayro::hayro_syntax::object::{Dict, FromBytes, Name, ObjectIdentifier};
+
+/// Revisions followed through `/Prev`, a bound against loops in malformed files.
+const MAX_REVISIONS: usize = 64;
+
+/// How far before the end of the file `startxref` is looked for.
+const TAIL: usize = 1024;
+
+/// The trailer of a PDF, read from the raw bytes of the file. hayro reads it as well but
+/// does not expose it, and it holds what the document says nowhere else: whether it is
+/// encrypted and which object is its information dictionary.
+pub(crate) struct Trailer<'a> {
+    data: &'a [u8],
+    /// The trailer dictionaries of the revisions of the file, newest first.
+    dicts: Vec<Dict<'a>>,
+}
+
+impl<'a> Trailer<'a> {
+    /// The trailer `startxref` points at, either the dictionary after a cross-reference table
+    /// or that of a cross-reference stream, followed by those of the earlier revisions of the
+    /// file. When `startxref` does not lead to one, as in damaged files, the last `trailer`
+    /// keyword of the file is used instead.
+    pub(crate) fn read(data: &'a [u8]) -> Option<Self> {
+        let mut dicts = Vec::new();
+        let mut offset = start_xref(data);
+        while let Some(at) = offset
+            && dicts.len() < MAX_REVISIONS
+        {
+            let Some(dict) = xref_dict(data, at) else {
+                break;
+            };
+            offset = dict
+                .get::<i64>(b"Prev")
+                .and_then(|prev| usize::try_from(prev).ok());
+            dicts.push(dict);
+        }
+        if dicts.is_empty() {
+            let at = rfind(data, b"trailer")?;
+            dicts.push(Dict::from_bytes(skip_white_space(&data[at + 7..]))?);
+        }
+        Some(Self { data, dicts })
+    }
+
+    /// The newest trailer dictionary that has `key`. Values read from it cannot resolve
+    /// references; use [`Trailer::reference`] and [`Trailer::dict`] for those.
+    pub(crate) fn with_key(&self, key: &[u8]) -> Option<&Dict<'a>> {
+        self.dicts.iter().find(|dict| dict.contains_key(key))
+    }
+
+    /// The object `key` refers to.
+    pub(crate) fn reference(&self, key: &[u8]) -> Option<ObjectIdentifier> {
+        let reference = self.with_key(key)?.get_ref(key)?;
+        Some(ObjectIdentifier::new(
+            reference.obj_number,
+            reference.gen_number,
+        ))
+    }
+
+    /// The dictionary under `key`, given in the trailer itself or as an indirect object. An
+    /// indirect object is found by its `12 0 obj` header, taking the last one in the file as
+    /// the newest revision; this only works for objects outside object streams, which the
+    /// encryption dictionary always is.
+    pub(crate) fn dict(&self, key: &[u8]) -> Option<Dict<'a>> {
+        let Some(id) = self.reference(key) else {
+            return self.with_key(key)?.get::<Dict>(key);
+        };
+        let header = format!("{} {} obj", id.obj_number, id.gen_number);
+        let mut end = self.data.len();
+        while let Some(at) = rfind(&self.data[..end], header.as_bytes()) {
+            // `2 0 obj` must not match the end of `12 0 obj`.
+            if at == 0 || !self.data[at - 1].is_ascii_digit() {
+                return Dict::from_bytes(skip_white_space(&self.data[at + header.len()..]));
+            }
+            end = at;
+        }
+        None
+    }
+}
+
+/// The offset `startxref` at the end of the file gives.
+fn start_xref(data: &[u8]) -> Option<usize> {
+    let tail = data.len().saturating_sub(TAIL);
+    let at = tail + rfind(&data[tail..], b"startxref")?;
+    let digits = skip_white_space(&data[at + 9..]);
+    let len = digits.iter().take_while(|b| b.is_ascii_digit()).count();
+    std::str::from_utf8(&digits[..len]).ok()?.parse().ok()
+}
+
+/// The trailer dictionary of the cross-reference section at `offset`.
+fn xref_dict(data: &[u8], offset: usize) -> Option<Dict<'_>> {
+    let section = skip_white_space(data.get(offset..)?);
+    if let Some(table) = section.strip_prefix(b"xref") {
+        // The entries of a table are only digits, spaces and the letters `f` and `n`.
+        let at = find(table, b"trailer")?;
+        return Dict::from_bytes(skip_white_space(&table[at + 7..]));
+    }
+    // A cross-reference stream: `12 0 obj` and the stream dictionary.
+    let mut rest = section;
+    for _ in 0..2 {
+        let len = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+        if len == 0 {
+            return None;
+        }
+        rest = skip_white_space(&rest[len..]);
+    }
+    let dict = Dict::from_bytes(skip_white_space(rest.strip_prefix(b"obj")?))?;
+    dict.get::<Name>(b"Type")
+        .is_some_and(|name| name.as_ref() == b"XRef")
+        .then_some(dict)
+}
+
+fn skip_white_space(data: &[u8]) -> &[u8] {
+    let len = data
+        .iter()
+        .take_while(|b| b.is_ascii_whitespace() || **b == 0)
+        .count();
+    &data[len..]
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn rfind(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len())
+        .rposition(|window| window == needle)
+}
@@ -3,8 +3,8 @@ use std::path::Path;
 
 const SEP: char = '-';
 
-/// Internal sanitizer: keep ASCII alnum, '-', '_' and '.', replace others with '-'.
-fn sanitize_prefix_raw(s: &str) -> String {
+/// Sanitizer for file name parts: keep ASCII alnum, '-', '_' and '.', replace others with '-'.
+pub fn sanitize_prefix_raw(s: &str) -> String {
     let mut out = String::with_capacity(s.len());
     let mut last_was_sep = false;
     for c in s.chars() {
@@ -70,44 +70,350 @@ pub fn resolve_prefix(prefix: Option<&str>, input: &Path) -> String {
     base
 }
 
-/// Validate requested 1-based page numbers against `total` pages in the document.
+/// A single item of a `--page` specification, before it is resolved against a document.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PageSelector {
+    /// `N`: a single 1-based page.
+    Single(usize),
+    /// `A-B`: an inclusive range of 1-based pages.
+    Range(usize, usize),
+    /// `A-`: from page `A` to the last page.
+    From(usize),
+    /// `-N`: the last `N` pages.
+    Tail(usize),
+    /// `odd`: pages 1, 3, 5, ...
+    Odd,
+    /// `even`: pages 2, 4, 6, ...
+    Even,
+    /// `last`: the last page.
+    Last,
+}
+
+/// Parse one comma-separated item of a page specification.
+///
+/// Returns the selector and whether it is an exclusion (`!` prefix), or `None` when the
+/// item is not valid page syntax.
+fn parse_page_item(item: &str) -> Option<(PageSelector, bool)> {
+    let item = item.trim();
+    let (exclude, body) = match item.strip_prefix('!') {
+        Some(rest) => (true, rest.trim()),
+        None => (false, item),
+    };
+
+    let selector = match body.to_ascii_lowercase().as_str() {
+        "" => return None,
+        "odd" => PageSelector::Odd,
+        "even" => PageSelector::Even,
+        "last" => PageSelector::Last,
+        _ => {
+            if let Some(count) = body.strip_prefix('-') {
+                PageSelector::Tail(count.trim().parse().ok()?)
+            } else if let Some((start, end)) = body.split_once('-') {
+                let start = start.trim().parse().ok()?;
+                let end = end.trim();
+                if end.is_empty() {
+                    PageSelector::From(start)
+                } else {
+                    PageSelector::Range(start, end.parse().ok()?)
+                }
+            } else {
+                PageSelector::Single(body.parse().ok()?)
+            }
+        }
+    };
+
+    Some((selector, exclude))
+}
+
+/// Expand a selector into 1-based page numbers, in the order they were requested.
+///
+/// Out-of-range numbers are pushed to `invalid_pages`; selectors that cannot be expressed as
+/// a page number (such as `-N` with `N` larger than the document) are pushed to `invalid_items`.
+fn expand_selector(
+    selector: PageSelector,
+    raw: &str,
+    total: usize,
+    invalid_pages: &mut Vec<usize>,
+    invalid_items: &mut Vec<String>,
+) -> Vec<usize> {
+    let in_range = |p: usize| p >= 1 && p <= total;
+    match selector {
+        PageSelector::Single(p) => {
+            if in_range(p) {
+                vec![p]
+            } else {
+                invalid_pages.push(p);
+                Vec::new()
+            }
+        }
+        PageSelector::Range(start, end) => {
+            let bad: Vec<usize> = [start, end].into_iter().filter(|&p| !in_range(p)).collect();
+            if !bad.is_empty() {
+                invalid_pages.extend(bad);
+                Vec::new()
+            } else if start <= end {
+                (start..=end).collect()
+            } else {
+                (end..=start).rev().collect()
+            }
+        }
+        PageSelector::From(start) => {
+            if in_range(start) {
+                (start..=total).collect()
+            } else {
+                invalid_pages.push(start);
+                Vec::new()
+            }
+        }
+        PageSelector::Tail(count) => {
+            if count >= 1 && count <= total {
+                (total - count + 1..=total).collect()
+            } else {
+                invalid_items.push(raw.trim().to_string());
+                Vec::new()
+            }
+        }
+        PageSelector::Odd => (1..=total).step_by(2).collect(),
+        PageSelector::Even => (2..=total).step_by(2).collect(),
+        PageSelector::Last => {
+            if total > 0 {
+                vec![total]
+            } else {
+                invalid_items.push(raw.trim().to_string());
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// Resolve a page specification (the comma-separated items given to `--page`) against
+/// `total` pages in the document.
+///
+/// Supported items are `N`, `A-B`, `A-` (to the end), `-N` (last `N` pages), `odd`, `even`
+/// and `last`. Any item may be prefixed with `!` to exclude those pages instead. If the
+/// specification is empty or only contains exclusions, they are applied to the whole document.
+///
+/// When `ordered` is false the pages are returned in document order without duplicates.
+/// When it is true they keep the order in which they were requested and repeated pages
+/// are kept, so `5,1,5` yields three entries.
 ///
 /// Returns:
-/// - `Ok(HashSet<usize>)` with 0-based page indices when all requested pages are valid (may be empty).
-/// - `Err(String)` with a single error message listing all problematic requested pages.
-pub fn validate_requested_pages(
-    requested: &[usize],
+/// - `Ok(Vec<usize>)` with 0-based page indices when every item is valid.
+/// - `Err(String)` with a single error message listing all problematic items.
+pub fn resolve_page_spec(spec: &[String], total: usize, ordered: bool) -> Result<Vec<usize>, String> {
+    let mut invalid_pages: Vec<usize> = Vec::new();
+    let mut invalid_items: Vec<String> = Vec::new();
+    let mut included: Vec<usize> = Vec::new();
+    let mut excluded: HashSet<usize> = HashSet::new();
+    let mut has_inclusion = false;
+
+    for raw in spec {
+        let Some((selector, exclude)) = parse_page_item(raw) else {
+            let item = match raw.trim() {
+                "" => "empty page item",
+                item => item,
+            };
+            invalid_items.push(item.to_string());
+            continue;
+        };
+        let pages = expand_selector(selector, raw, total, &mut invalid_pages, &mut invalid_items);
+        if exclude {
+            excluded.extend(pages);
+        } else {
+            has_inclusion = true;
+            included.extend(pages);
+        }
+    }
+
+    if !invalid_pages.is_empty() || !invalid_items.is_empty() {
+        return Err(invalid_pages_message(invalid_pages, invalid_items, total));
+    }
+
+    if !has_inclusion {
+        included = (1..=total).collect();
+    }
+
+    let mut selected: Vec<usize> = included
+        .into_iter()
+        .filter(|p| !excluded.contains(p))
+        .map(|p| p - 1)
+        .collect();
+    if !ordered {
+        selected.sort_unstable();
+        selected.dedup();
+    }
+
+    if selected.is_empty() {
+        return Err(format!(
+            "The page selection '{}' does not match any page.",
+            spec.join(",")
+        ));
+    }
+
+    Ok(selected)
+}
+
+/// Build the output file name for the `seq`-th of `total` outputs (0-based) rendered from page
+/// `page_idx`.
+///
+/// In document order the name is `<prefix><page>.<ext>`. In ordered mode the output position
+/// comes first so files sort in the requested order: `<prefix><seq>-p<page>.<ext>`.
+pub fn output_file_name(
+    prefix: &str,
+    seq: usize,
     total: usize,
-) -> Result<HashSet<usize>, String> {
-    if requested.is_empty() {
-        return Ok(HashSet::new());
+    page_idx: usize,
+    ordered: bool,
+    ext: &str,
+) -> String {
+    if ordered {
+        let seq = sequence_number(seq, total);
+        format!("{}{}-p{}.{}", prefix, seq, page_idx + 1, ext)
+    } else {
+        format!("{}{}.{}", prefix, page_idx + 1, ext)
     }
+}
+
+/// The 1-based position of the `seq`-th of `total` outputs, zero-padded to the width of
+/// `total` so that names sort in output order: `07` of 12.
+pub(crate) fn sequence_number(seq: usize, total: usize) -> String {
+    let width = total.to_string().len();
+    format!("{:0width$}", seq + 1)
+}
+
+/// Build the aggregated error message for invalid page requests.
+///
+/// Numeric pages are listed first in ascending order, followed by any malformed items in the
+/// order they were given.
+fn invalid_pages_message(
+    mut invalid_pages: Vec<usize>,
+    mut invalid_items: Vec<String>,
+    total: usize,
+) -> String {
+    invalid_pages.sort_unstable();
+    invalid_pages.dedup();
+    let mut seen = HashSet::new();
+    invalid_items.retain(|item| seen.insert(item.clone()));
 
-    // Collect all problematic requested page numbers (1-based values):
-    // - `0` is invalid because pages are 1-based
-    // - any p where p - 1 >= total (i.e. p > total) is out-of-range
-    let mut problematic: Vec<usize> = requested
+    let list = invalid_pages
         .iter()
-        .copied()
-        .filter(|&p| p == 0 || p > total)
-        .collect();
+        .map(|p| p.to_string())
+        .chain(invalid_items)
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "Invalid requested page(s): {}. The page numbers must be between 1 and {}.",
+        list, total
+    )
+}
+
+/// Build the file name for a format that stores every page in one file, such as a
+/// multi-page TIFF: the prefix without its trailing separator, plus `ext`.
+pub fn container_file_name(prefix: &str, ext: &str) -> String {
+    format!("{}.{}", prefix.trim_end_matches(SEP), ext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pages(spec: &str, total: usize, ordered: bool) -> Result<Vec<usize>, String> {
+        let items: Vec<String> = spec.split(',').map(str::to_string).collect();
+        // 1-based, as in the specification.
+        resolve_page_spec(&items, total, ordered)
+            .map(|pages| pages.into_iter().map(|idx| idx + 1).collect())
+    }
 
-    if !problematic.is_empty() {
-        problematic.sort_unstable();
-        problematic.dedup();
-        let list = problematic
-            .iter()
-            .map(|p| p.to_string())
-            .collect::<Vec<_>>()
-            .join(", ");
-        let msg = format!(
-            "Invalid requested page(s): {}. The page numbers must be between 1 and {}.",
-            list, total
+    #[test]
+    fn selects_single_pages_and_ranges() {
+        assert_eq!(pages("1,4", 10, false), Ok(vec![1, 4]));
+        assert_eq!(pages("3-5", 10, false), Ok(vec![3, 4, 5]));
+        assert_eq!(pages("8-", 10, false), Ok(vec![8, 9, 10]));
+        assert_eq!(pages("-2", 10, false), Ok(vec![9, 10]));
+        assert_eq!(pages("last", 10, false), Ok(vec![10]));
+        assert_eq!(pages(" 2 - 3 ", 10, false), Ok(vec![2, 3]));
+    }
+
+    #[test]
+    fn reversed_ranges_count_down_in_ordered_mode() {
+        assert_eq!(pages("5-3", 10, true), Ok(vec![5, 4, 3]));
+        assert_eq!(pages("5-3", 10, false), Ok(vec![3, 4, 5]));
+    }
+
+    #[test]
+    fn selects_odd_and_even_pages_with_exclusions() {
+        assert_eq!(pages("odd", 7, false), Ok(vec![1, 3, 5, 7]));
+        assert_eq!(pages("EVEN", 7, false), Ok(vec![2, 4, 6]));
+        assert_eq!(pages("odd,!3", 7, false), Ok(vec![1, 5, 7]));
+        assert_eq!(pages("!1-5", 7, false), Ok(vec![6, 7]));
+    }
+
+    #[test]
+    fn ordered_mode_keeps_order_and_repeats() {
+        assert_eq!(pages("5,1,5", 10, true), Ok(vec![5, 1, 5]));
+        assert_eq!(pages("5,1,5", 10, false), Ok(vec![1, 5]));
+    }
+
+    #[test]
+    fn reports_every_invalid_item() {
+        let message = |spec| pages(spec, 10, false).unwrap_err();
+        assert_eq!(
+            message("12,0,3-11,abc,-20"),
+            "Invalid requested page(s): 0, 11, 12, abc, -20. The page numbers must be between 1 \
+             and 10."
+        );
+        assert!(message("1,,3").starts_with("Invalid requested page(s): empty page item."));
+        assert!(message("1,").starts_with("Invalid requested page(s): empty page item."));
+        assert_eq!(
+            message("!1-10"),
+            "The page selection '!1-10' does not match any page."
         );
-        return Err(msg);
     }
 
-    // All requested pages are valid; convert to 0-based set and return.
-    let set: HashSet<usize> = requested.iter().map(|&p| p - 1).collect();
-    Ok(set)
+    #[test]
+    fn rejects_numbers_that_overflow() {
+        let huge = format!("{}0", usize::MAX);
+        assert!(pages(&huge, 10, false).unwrap_err().contains(&huge));
+        let max = usize::MAX.to_string();
+        assert!(
+            pages(&format!("{max}-"), 10, false)
+                .unwrap_err()
+                .contains(&max)
+        );
+        assert!(pages(&format!("-{max}"), 10, false).is_err());
+        assert!(pages(&format!("1-{max}"), 10, false).is_err());
+    }
+
+    #[test]
+    fn sanitizes_prefixes() {
+        assert_eq!(
+            resolve_prefix(Some("my report (v2)"), Path::new("")),
+            "my-report-v2-"
+        );
+        assert_eq!(resolve_prefix(None, Path::new("dir/scan.pdf")), "scan-");
+        assert_eq!(
+            resolve_prefix(Some("***"), Path::new("doc.pdf")),
+            "rendered-"
+        );
+    }
+
+    #[test]
+    fn pads_ordered_positions_to_the_output_count() {
+        assert_eq!(
+            output_file_name("doc-", 1, 9, 4, true, "png"),
+            "doc-2-p5.png"
+        );
+        assert_eq!(
+            output_file_name("doc-", 1, 10, 4, true, "png"),
+            "doc-02-p5.png"
+        );
+        assert_eq!(
+            output_file_name("doc-", 99, 100, 0, true, "png"),
+            "doc-100-p1.png"
+        );
+        assert_eq!(
+            output_file_name("doc-", 1, 10, 4, false, "png"),
+            "doc-5.png"
+        );
+    }
 }